@@ -1,4 +1,4 @@
-use alloy_primitives::{Bytes, U256};
+use alloy_primitives::{Bytes, LogData, U256};
 use revm::interpreter::InstructionResult;
 use std::cmp::Ordering;
 
@@ -18,6 +18,26 @@ pub struct MockCallReturnData {
     pub ret_type: InstructionResult,
     /// Return data or error
     pub data: Bytes,
+    /// Logs emitted by the mocked call before returning, only supported in the ZK-VM
+    pub logs: Vec<LogData>,
+    /// Whether the callee executes, with only its return data mocked, only supported in the ZK-VM
+    pub execute: bool,
+}
+
+impl MockCallReturnData {
+    /// Mocked return data returning `data`, without executing the callee.
+    pub fn returning(data: &[u8]) -> Self {
+        Self::new(InstructionResult::Return, data)
+    }
+
+    /// Mocked return data reverting with `data`, without executing the callee.
+    pub fn reverting(data: &[u8]) -> Self {
+        Self::new(InstructionResult::Revert, data)
+    }
+
+    fn new(ret_type: InstructionResult, data: &[u8]) -> Self {
+        Self { ret_type, data: Bytes::copy_from_slice(data), logs: vec![], execute: false }
+    }
 }
 
 impl PartialOrd for MockCallDataContext {
@@ -407,6 +407,17 @@ interface Vm {
     function mockCallRevert(address callee, uint256 msgValue, bytes calldata data, bytes calldata revertData)
         external;
 
+    /// Mocks a call to an address, returning specified data and emitting a log with the specified
+    /// topics and data from the callee before returning. Only supported in the ZK-VM.
+    #[cheatcode(group = Evm, safety = Unsafe)]
+    function zkMockCallEmit(address callee, bytes calldata data, bytes calldata returnData, bytes32[] calldata topics, bytes calldata logData)
+        external;
+
+    /// Mocks a call to an address, executing the callee such that its state changes persist, but
+    /// returning the specified data instead of its return data. Only supported in the ZK-VM.
+    #[cheatcode(group = Evm, safety = Unsafe)]
+    function zkMockCallExecute(address callee, bytes calldata data, bytes calldata returnData) external;
+
     // --- Impersonation (pranks) ---
 
     /// Sets the *next* call's `msg.sender` to be the input address.
@@ -433,6 +444,11 @@ interface Vm {
     #[cheatcode(group = Evm, safety = Unsafe)]
     function readCallers() external returns (CallerMode callerMode, address msgSender, address txOrigin);
 
+    /// Sets the *next* call's `msg.value` as seen by the callee, without transferring any value.
+    /// Only supported in the ZK-VM.
+    #[cheatcode(group = Evm, safety = Unsafe)]
+    function zkPrankMsgValue(uint256 msgValue) external;
+
     // -------- State Snapshots --------
 
     /// Snapshot the current state of the evm.
@@ -1,7 +1,7 @@
 use crate::{Cheatcode, Cheatcodes, CheatsCtxt, DatabaseExt, Result, Vm::*};
-use alloy_primitives::{Address, Bytes, U256};
+use alloy_primitives::{Address, Bytes, LogData, U256};
 use foundry_cheatcodes_common::mock::{MockCallDataContext, MockCallReturnData};
-use revm::{interpreter::InstructionResult, primitives::Bytecode};
+use revm::primitives::Bytecode;
 
 impl Cheatcode for clearMockedCallsCall {
     fn apply(&self, state: &mut Cheatcodes) -> Result {
@@ -14,28 +14,8 @@ impl Cheatcode for clearMockedCallsCall {
 impl Cheatcode for mockCall_0Call {
     fn apply_full<DB: DatabaseExt>(&self, ccx: &mut CheatsCtxt<DB>) -> Result {
         let Self { callee, data, returnData } = self;
-        let (acc, _) = ccx.data.journaled_state.load_account(*callee, ccx.data.db)?;
-
-        // Etches a single byte onto the account if it is empty to circumvent the `extcodesize`
-        // check Solidity might perform.
-        let empty_bytecode = acc.info.code.as_ref().map_or(true, Bytecode::is_empty);
-        if empty_bytecode {
-            let code = Bytecode::new_raw(Bytes::copy_from_slice(
-                &foundry_zksync_core::cheatcodes::EMPTY_CODE,
-            ))
-            .to_checked();
-            ccx.data.journaled_state.set_code(*callee, code.clone());
-        }
-
-        if ccx.state.use_zk_vm {
-            foundry_zksync_core::cheatcodes::set_mocked_account(
-                *callee,
-                ccx.data.db,
-                &mut ccx.data.journaled_state,
-            );
-        }
-
-        mock_call(ccx.state, callee, data, None, returnData, InstructionResult::Return);
+        prepare_mocked_account(ccx, callee)?;
+        mock_call(ccx.state, callee, data, None, MockCallReturnData::returning(returnData));
         Ok(Default::default())
     }
 }
@@ -44,7 +24,13 @@ impl Cheatcode for mockCall_1Call {
     fn apply_full<DB: DatabaseExt>(&self, ccx: &mut CheatsCtxt<DB>) -> Result {
         let Self { callee, msgValue, data, returnData } = self;
         ccx.data.journaled_state.load_account(*callee, ccx.data.db)?;
-        mock_call(ccx.state, callee, data, Some(msgValue), returnData, InstructionResult::Return);
+        mock_call(
+            ccx.state,
+            callee,
+            data,
+            Some(msgValue),
+            MockCallReturnData::returning(returnData),
+        );
         Ok(Default::default())
     }
 }
@@ -52,7 +38,7 @@ impl Cheatcode for mockCall_1Call {
 impl Cheatcode for mockCallRevert_0Call {
     fn apply(&self, state: &mut Cheatcodes) -> Result {
         let Self { callee, data, revertData } = self;
-        mock_call(state, callee, data, None, revertData, InstructionResult::Revert);
+        mock_call(state, callee, data, None, MockCallReturnData::reverting(revertData));
         Ok(Default::default())
     }
 }
@@ -60,22 +46,80 @@ impl Cheatcode for mockCallRevert_0Call {
 impl Cheatcode for mockCallRevert_1Call {
     fn apply(&self, state: &mut Cheatcodes) -> Result {
         let Self { callee, msgValue, data, revertData } = self;
-        mock_call(state, callee, data, Some(msgValue), revertData, InstructionResult::Revert);
+        mock_call(state, callee, data, Some(msgValue), MockCallReturnData::reverting(revertData));
         Ok(Default::default())
     }
 }
 
+impl Cheatcode for zkMockCallEmitCall {
+    fn apply_full<DB: DatabaseExt>(&self, ccx: &mut CheatsCtxt<DB>) -> Result {
+        let Self { callee, data, returnData, topics, logData } = self;
+        if !ccx.state.use_zk_vm {
+            bail!("mocked call logs are only supported in the ZK-VM, see `zkVm`");
+        }
+
+        prepare_mocked_account(ccx, callee)?;
+        let log = LogData::new_unchecked(topics.clone(), Bytes::copy_from_slice(logData));
+        let return_data =
+            MockCallReturnData { logs: vec![log], ..MockCallReturnData::returning(returnData) };
+        mock_call(ccx.state, callee, data, None, return_data);
+        Ok(Default::default())
+    }
+}
+
+impl Cheatcode for zkMockCallExecuteCall {
+    fn apply_full<DB: DatabaseExt>(&self, ccx: &mut CheatsCtxt<DB>) -> Result {
+        let Self { callee, data, returnData } = self;
+        if !ccx.state.use_zk_vm {
+            bail!("executing mocked calls is only supported in the ZK-VM, see `zkVm`");
+        }
+
+        // the callee executes, so its code is left untouched
+        ccx.data.journaled_state.load_account(*callee, ccx.data.db)?;
+        let return_data =
+            MockCallReturnData { execute: true, ..MockCallReturnData::returning(returnData) };
+        mock_call(ccx.state, callee, data, None, return_data);
+        Ok(Default::default())
+    }
+}
+
+/// Etches a single byte onto the `callee` if it is empty to circumvent the `extcodesize` check
+/// Solidity might perform, and marks it as mocked in the ZK-VM.
+fn prepare_mocked_account<DB: DatabaseExt>(
+    ccx: &mut CheatsCtxt<DB>,
+    callee: &Address,
+) -> Result<()> {
+    let (acc, _) = ccx.data.journaled_state.load_account(*callee, ccx.data.db)?;
+
+    let empty_bytecode = acc.info.code.as_ref().map_or(true, Bytecode::is_empty);
+    if empty_bytecode {
+        let code =
+            Bytecode::new_raw(Bytes::copy_from_slice(&foundry_zksync_core::cheatcodes::EMPTY_CODE))
+                .to_checked();
+        ccx.data.journaled_state.set_code(*callee, code.clone());
+    }
+
+    if ccx.state.use_zk_vm {
+        foundry_zksync_core::cheatcodes::set_mocked_account(
+            *callee,
+            ccx.data.db,
+            &mut ccx.data.journaled_state,
+        );
+    }
+
+    Ok(())
+}
+
 #[allow(clippy::ptr_arg)] // Not public API, doesn't matter
 fn mock_call(
     state: &mut Cheatcodes,
     callee: &Address,
     cdata: &Vec<u8>,
     value: Option<&U256>,
-    rdata: &Vec<u8>,
-    ret_type: InstructionResult,
+    return_data: MockCallReturnData,
 ) {
     state.mocked_calls.entry(*callee).or_default().insert(
         MockCallDataContext { calldata: Bytes::copy_from_slice(cdata), value: value.copied() },
-        MockCallReturnData { ret_type, data: Bytes::copy_from_slice(rdata) },
+        return_data,
     );
 }
@@ -88,6 +88,18 @@ impl Cheatcode for stopPrankCall {
     }
 }
 
+impl Cheatcode for zkPrankMsgValueCall {
+    fn apply(&self, state: &mut Cheatcodes) -> Result {
+        let Self { msgValue } = self;
+        if !state.use_zk_vm {
+            bail!("`msg.value` pranks are only supported in the ZK-VM, see `zkVm`");
+        }
+
+        state.zk_prank_msg_value = Some(*msgValue);
+        Ok(Default::default())
+    }
+}
+
 fn prank<DB: DatabaseExt>(
     ccx: &mut CheatsCtxt<DB>,
     new_caller: &Address,
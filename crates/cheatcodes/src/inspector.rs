@@ -249,6 +249,9 @@ pub struct Cheatcodes {
     /// This can be done as each test runs with its own [Cheatcodes] instance, thereby
     /// providing the necessary level of isolation.
     pub persisted_factory_deps: HashMap<H256, Vec<u8>>,
+
+    /// The `msg.value` to present to the callee of the next ZK-VM call, set by `zkPrankMsgValue`.
+    pub zk_prank_msg_value: Option<U256>,
 }
 
 impl Cheatcodes {
@@ -1034,7 +1037,13 @@ impl<DB: DatabaseExt + Send> Inspector<DB> for Cheatcodes {
                     })
                     .map(|(_, v)| v)
             }) {
-                return (return_data.ret_type, gas, return_data.data.clone());
+                // mocks executing the callee are applied by the ZK-VM once the callee returns
+                if !return_data.execute {
+                    return (return_data.ret_type, gas, return_data.data.clone());
+                } else if !self.use_zk_vm {
+                    let msg = "executing mocked calls is only supported in the ZK-VM, see `zkVm`";
+                    return (InstructionResult::Revert, gas, Error::encode(msg));
+                }
             }
         }
 
@@ -1199,11 +1208,21 @@ impl<DB: DatabaseExt + Send> Inspector<DB> for Cheatcodes {
             info!("running call in zk vm {:#?}", call);
             let persisted_factory_deps = self.persisted_factory_deps.clone();
 
+            // the transaction runs from the original `tx.origin`, so that only the pranked
+            // frame sees the pranked one and not the calls it makes
+            let tx_origin = self.prank.as_ref().and_then(|prank| {
+                let origin = prank.new_origin.filter(|origin| *origin == data.env.tx.caller)?;
+                data.env.tx.caller = prank.prank_origin;
+                Some(origin)
+            });
+
             let ccx = foundry_zksync_core::vm::CheatcodeTracerContext {
                 mocked_calls: self.mocked_calls.clone(),
                 expected_calls: Some(&mut self.expected_calls),
                 accesses: self.accesses.as_mut(),
                 persisted_factory_deps,
+                call_value: self.zk_prank_msg_value.take(),
+                tx_origin,
             };
             if let Ok(result) = foundry_zksync_core::vm::call::<_, DatabaseError>(
                 call,
@@ -1221,6 +1240,14 @@ impl<DB: DatabaseExt + Send> Inspector<DB> for Cheatcodes {
                                     data: LogData::new_unchecked(log.topics, log.data),
                                 })
                             }));
+                            // Stores the logs if `recordLogs` has been called
+                            if let Some(storage_recorded_logs) = &mut self.recorded_logs {
+                                storage_recorded_logs.extend(logs.iter().map(|log| Vm::Log {
+                                    topics: log.topics.clone(),
+                                    data: log.data.to_vec(),
+                                    emitter: log.address,
+                                }));
+                            }
                             //for each log in cloned logs call handle_expect_emit
                             if !self.expected_emits.is_empty() {
                                 for log in logs {
@@ -1680,12 +1707,17 @@ impl<DB: DatabaseExt + Send> Inspector<DB> for Cheatcodes {
             self.persisted_factory_deps
                 .extend(factory_deps.iter().map(|dep| (hash_bytecode(dep), dep.clone())));
 
+            // `msg.value` pranks apply to the next call, which this deployment consumes
+            self.zk_prank_msg_value = None;
+
             tracing::debug!(contract = zk_contract.name, "using dual compiled contract");
             let ccx = foundry_zksync_core::vm::CheatcodeTracerContext {
                 mocked_calls: self.mocked_calls.clone(),
                 expected_calls: Some(&mut self.expected_calls),
                 accesses: self.accesses.as_mut(),
                 persisted_factory_deps,
+                call_value: None,
+                tx_origin: None,
             };
             if let Ok(result) = foundry_zksync_core::vm::create::<_, DatabaseError>(
                 call,
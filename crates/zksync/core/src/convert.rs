@@ -130,6 +130,25 @@ impl ConvertAddress for Address {
     }
 }
 
+/// Conversions into [H160], from either [H160] or [Address]. [Address]es are converted via
+/// [ConvertAddress::to_h160], as `Into<H160>` cannot be implemented for them.
+pub trait IntoH160 {
+    /// Convert into [H160]
+    fn into_h160(self) -> H160;
+}
+
+impl IntoH160 for H160 {
+    fn into_h160(self) -> H160 {
+        self
+    }
+}
+
+impl IntoH160 for Address {
+    fn into_h160(self) -> H160 {
+        self.to_h160()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
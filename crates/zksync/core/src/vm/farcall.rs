@@ -1,56 +1,255 @@
-#![allow(unused)]
-
-use std::{collections::HashMap, default, fmt::Debug};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Debug,
+    sync::Arc,
+};
 
-use alloy_primitives::Address;
+use alloy_primitives::{hex, Address};
+use alloy_sol_types::{Panic, Revert, SolError};
 use itertools::Itertools;
 use multivm::{
     vm_latest::{BootloaderState, HistoryMode, SimpleMemory, ZkSyncVmState},
     zk_evm_latest::{
         aux_structures::{MemoryPage, Timestamp},
-        opcodes::DecodedOpcode as ZkDecodedOpcode,
         tracing::{AfterExecutionData, BeforeExecutionData, VmLocalStateData},
         vm_state::{self, PrimitiveValue},
         zkevm_opcode_defs::{
             decoding::{EncodingModeProduction, VmEncodingMode},
-            FarCallABI, FarCallOpcode, FatPointer, Opcode, CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER,
-            CALL_SYSTEM_ABI_REGISTERS, RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER,
+            FarCallABI, FarCallForwardPageType, FarCallOpcode, FatPointer, LogOpcode, Opcode,
+            RetOpcode, CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER, CALL_SYSTEM_ABI_REGISTERS,
+            FIRST_MESSAGE_FLAG_IDX, RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER,
         },
     },
 };
-use zksync_basic_types::{H160, U256};
-use zksync_state::{StoragePtr, WriteStorage};
-use zksync_types::MSG_VALUE_SIMULATOR_ADDRESS;
+use zksync_basic_types::{H160, H256, U256};
+use zksync_state::WriteStorage;
+use zksync_types::{
+    VmEvent, CONTRACT_DEPLOYER_ADDRESS, L2_ETH_TOKEN_ADDRESS, MSG_VALUE_SIMULATOR_ADDRESS,
+};
 
-use crate::convert::{ConvertAddress, ConvertH256, ConvertU256};
+use crate::convert::{ConvertH160, ConvertH256, ConvertU256, IntoH160};
 
 type PcOrImm = <EncodingModeProduction as VmEncodingMode<8>>::PcOrImm;
 type CallStackEntry = vm_state::CallStackEntry<8, EncodingModeProduction>;
-type DecodedOpcode = ZkDecodedOpcode<8, EncodingModeProduction>;
+
+/// The size in bytes of a zkEVM memory word, in which the return data is written.
+pub(crate) const WORD_SIZE: usize = 32;
+
+/// The maximum length of the return data of an immediate return, as memory pages are addressed
+/// by `u32` byte offsets. The return data must also fit in the page past its start, see
+/// [ImmediateReturnOptions::return_data_start].
+pub(crate) const MAX_RETURN_DATA_LEN: u32 = u32::MAX;
 
 /// Contains information about the immediate return from a FarCall.
 #[derive(Debug, Clone)]
 pub(crate) struct ImmediateReturn {
+    /// The FarCall opcode variant the immediate return was set for.
+    pub(crate) opcode: FarCallOpcode,
     pub(crate) return_data: Vec<u8>,
-    pub(crate) return_base_memory_page: u32,
-    pub(crate) next_pc: PcOrImm,
-    pub(crate) next_code_page: u32,
-    pub(crate) next_base_memory_page: u32,
-    pub(crate) next_sp: PcOrImm,
-    pub(crate) next_exception_handler_location: PcOrImm,
-    pub(crate) next_this_address: H160,
-    pub(crate) next_is_local_frame: bool,
-    pub(crate) next_context_u128_value: u128,
+    pub(crate) is_revert: bool,
+    /// The heap page the return data is written to.
+    pub(crate) return_memory_page: u32,
+    /// The word aligned byte offset within the return memory page the return data starts at.
+    pub(crate) return_data_start: u32,
+    /// The number of [WORD_SIZE] words of the return data.
+    pub(crate) return_data_chunks: usize,
+    /// The returned to frame.
+    pub(crate) next_frame: FrameRestore,
+    /// The ergs remaining for the returned to frame. If not set, the ergs remain unchanged.
+    pub(crate) next_ergs_remaining: Option<u32>,
+    /// The address the logs are emitted from, i.e. the returned from frame's this-address.
+    pub(crate) emitter: H160,
+    /// The logs emitted by the returned from frame, prior to returning.
+    pub(crate) logs: Vec<MockedLog>,
+}
+
+/// A log emitted by a mocked call, see [ImmediateReturnOptions::logs].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct MockedLog {
+    /// The indexed topics, including the event signature hash for non-anonymous events.
+    pub(crate) topics: Vec<H256>,
+    /// The non-indexed data.
+    pub(crate) data: Vec<u8>,
+}
+
+/// Defines the overrides of the returned to frame for an [ImmediateReturn].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct ImmediateReturnOptions {
+    /// The ergs remaining for the returned to frame. If not set, the ergs remain unchanged.
+    pub(crate) gas_left: Option<u32>,
+    /// The context value of the returned to frame, e.g. to keep the forwarded value visible
+    /// after a mocked value call. Defaults to `0`.
+    pub(crate) context_u128_value: Option<u128>,
+    /// The logs emitted by the returned from frame before returning, in order. Logs are
+    /// discarded if the call reverts, see [FarCallHandler::take_mocked_events].
+    pub(crate) logs: Vec<MockedLog>,
+    /// The byte offset within the return memory page, i.e. the called frame's heap page, the
+    /// return data is written at, rounded up to a word boundary. Defaults to the page start, or
+    /// for mocked calls to the start of the calldata region in the caller's [FarCallABI].
+    pub(crate) return_data_start: Option<u32>,
+    /// Whether the FarCall returns before executing, or executes and only has its returndata
+    /// overridden. Defaults to [ImmediateReturnMode::SkipExecution].
+    pub(crate) mode: ImmediateReturnMode,
+}
+
+/// An event emitted during execution, see [FarCallHandler::take_mocked_events].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EmittedEvent {
+    /// An event the VM records itself, only tracked for its position.
+    Recorded,
+    /// An event emitted by a mocked call, which the VM does not record.
+    Mocked(VmEvent),
+}
+
+/// Defines when the return data of an immediate return is returned to the caller.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ImmediateReturnMode {
+    /// Return during the `finish_cycle` of the FarCall, skipping the called frame's execution,
+    /// such that none of its state changes take place.
+    #[default]
+    SkipExecution,
+    /// Execute the called frame, and override its returndata once it returns, such that its
+    /// state changes persist but the caller reads the mocked returndata. The outcome of the call
+    /// is kept, i.e. a mocked revert does not revert a successful call, and the returned to
+    /// frame's overrides, i.e. [ImmediateReturnOptions::gas_left] and
+    /// [ImmediateReturnOptions::context_u128_value], do not apply.
+    OverrideOnReturn,
+}
+
+impl ImmediateReturn {
+    /// Returns the fat pointer to the return data, once written to the return memory page.
+    /// The length is the exact return data length, which need not be word aligned.
+    pub(crate) fn return_fat_pointer(&self) -> FatPointer {
+        FatPointer {
+            memory_page: self.return_memory_page,
+            offset: 0,
+            start: self.return_data_start,
+            length: self.return_data.len() as u32,
+        }
+    }
+
+    /// Returns the return data as [WORD_SIZE] words, indexed by their slot in the return memory
+    /// page. The final word is right-padded with zeros if the return data is not word aligned.
+    pub(crate) fn return_data_words(&self) -> Vec<(usize, U256)> {
+        let start_slot = self.return_fat_pointer().start as usize / WORD_SIZE;
+        self.return_data
+            .chunks(WORD_SIZE)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut word = [0u8; WORD_SIZE];
+                word[..chunk.len()].copy_from_slice(chunk);
+                (start_slot + index, U256::from_big_endian(&word))
+            })
+            .collect_vec()
+    }
+
+    /// Writes the return data to the return memory page, and points the returndata register at
+    /// it. The register is always overwritten, so empty return data yields a zero-length
+    /// returndata instead of the previous call's.
+    ///
+    /// The caller's registers are not restored on a far return, instead all registers besides
+    /// the returndata register are cleared. They are cleared here too, so that the registers
+    /// set up for the called frame, e.g. the system call ABI registers, do not leak to the
+    /// caller.
+    ///
+    /// Returns the number of return data bytes written, excluding the padding of the final word.
+    pub(crate) fn write_return_data<H: HistoryMode>(
+        &self,
+        registers: &mut [PrimitiveValue],
+        memory: &mut SimpleMemory<H>,
+        timestamp: Timestamp,
+    ) -> usize {
+        let return_fat_ptr = self.return_fat_pointer();
+        for register in registers.iter_mut() {
+            *register = PrimitiveValue::empty();
+        }
+        registers[RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER as usize] =
+            PrimitiveValue { value: return_fat_ptr.to_u256(), is_pointer: true };
+        memory.populate_page(
+            return_fat_ptr.memory_page as usize,
+            self.return_data_words(),
+            timestamp,
+        );
+        self.return_data.len()
+    }
+
+    /// Returns the emitted logs as [VmEvent]s, or none if the call reverts.
+    pub(crate) fn events(&self) -> Vec<VmEvent> {
+        if self.is_revert {
+            return Vec::new()
+        }
+
+        self.logs
+            .iter()
+            .map(|log| VmEvent {
+                location: Default::default(),
+                address: self.emitter,
+                indexed_topics: log.topics.clone(),
+                value: log.data.clone(),
+            })
+            .collect_vec()
+    }
+
+    /// Restores the returned to frame onto the `current` frame.
+    pub(crate) fn restore_frame(&self, current: &mut CallStackEntry) {
+        self.next_frame.apply(current);
+        if let Some(ergs_remaining) = self.next_ergs_remaining {
+            current.ergs_remaining = ergs_remaining;
+        }
+    }
+}
+
+/// A snapshot of the [CallStackEntry] fields restored onto the current frame when returning
+/// to it early, see [ImmediateReturn::restore_frame].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FrameRestore {
+    pub(crate) pc: PcOrImm,
+    pub(crate) code_page: u32,
+    pub(crate) base_memory_page: u32,
+    pub(crate) sp: PcOrImm,
+    pub(crate) exception_handler_location: PcOrImm,
+    pub(crate) this_address: H160,
+    pub(crate) is_local_frame: bool,
+    pub(crate) context_u128_value: u128,
+}
+
+impl FrameRestore {
+    /// Snapshots the restored fields of the `frame`.
+    pub(crate) fn snapshot(frame: &CallStackEntry) -> Self {
+        Self {
+            pc: frame.pc,
+            code_page: frame.code_page.0,
+            base_memory_page: frame.base_memory_page.0,
+            sp: frame.sp,
+            exception_handler_location: frame.exception_handler_location,
+            this_address: frame.this_address,
+            is_local_frame: frame.is_local_frame,
+            context_u128_value: frame.context_u128_value,
+        }
+    }
+
+    /// Applies the snapshotted fields onto the `frame`.
+    pub(crate) fn apply(&self, frame: &mut CallStackEntry) {
+        frame.pc = self.pc;
+        frame.code_page = MemoryPage(self.code_page);
+        frame.base_memory_page = MemoryPage(self.base_memory_page);
+        frame.sp = self.sp;
+        frame.exception_handler_location = self.exception_handler_location;
+        frame.this_address = self.this_address;
+        frame.is_local_frame = self.is_local_frame;
+        frame.context_u128_value = self.context_u128_value;
+    }
 }
 
 /// The call depth
-#[derive(Debug, Default, Clone, PartialEq)]
-pub(crate) struct CallDepth(u8);
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct CallDepth(u16);
 
 impl CallDepth {
     /// Create a new [CallDepth] instance.
     #[inline]
-    pub(crate) const fn new(depth: u8) -> CallDepth {
+    pub(crate) const fn new(depth: u16) -> CallDepth {
         CallDepth(depth)
     }
 
@@ -71,15 +270,60 @@ impl CallDepth {
     pub(crate) fn decrement(self) -> CallDepth {
         CallDepth(self.0.saturating_sub(1))
     }
+
+    /// Increment [CallDepth] until the maximum value.
+    #[inline]
+    pub(crate) fn increment(self) -> CallDepth {
+        self.saturating_add(1)
+    }
+
+    /// Add `depth` to [CallDepth] until the maximum value, e.g. `CallDepth::current()
+    /// .saturating_add(2)` for two FarCalls from now.
+    #[inline]
+    pub(crate) fn saturating_add(self, depth: u8) -> CallDepth {
+        CallDepth(self.0.saturating_add(depth as u16))
+    }
 }
 
 /// The call action.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) enum CallAction {
     /// Assign msg.sender.
     SetMessageSender(Address),
     /// Assign address(this).
     SetThisAddress(Address),
+    /// Assign tx.origin.
+    SetTxOrigin(Address),
+    /// Assign msg.value, without transferring it, see [CallAction::apply_call_value].
+    SetCallValue(U256),
+}
+
+impl CallAction {
+    /// Returns the application order of the action among the actions of the same frame.
+    /// address(this) is assigned first, as the tx.origin override is keyed by it.
+    fn order(&self) -> u8 {
+        match self {
+            CallAction::SetThisAddress(_) => 0,
+            CallAction::SetMessageSender(_) => 1,
+            CallAction::SetTxOrigin(_) => 2,
+            CallAction::SetCallValue(_) => 3,
+        }
+    }
+
+    /// Assigns the `value` as msg.value of the `frame`, i.e. its `context_u128_value`.
+    /// Fails with [FarCallHandlerError::CallValueOverflow] if the value does not fit in a
+    /// `u128`, leaving the frame unchanged.
+    pub(crate) fn apply_call_value(
+        frame: &mut CallStackEntry,
+        value: U256,
+    ) -> Result<(), FarCallHandlerError> {
+        if value > U256::from(u128::MAX) {
+            return Err(FarCallHandlerError::CallValueOverflow(value))
+        }
+
+        frame.context_u128_value = value.as_u128();
+        Ok(())
+    }
 }
 
 /// The call action.
@@ -118,12 +362,318 @@ impl CallActions {
         self.pending = pending_actions;
     }
 
-    /// Consume the immediate actions.
+    /// Consume the immediate actions, in application order: [CallAction::SetThisAddress] first,
+    /// then [CallAction::SetMessageSender] and finally [CallAction::SetTxOrigin]. Actions of the
+    /// same kind are kept in insertion order, so the last one takes effect.
     pub(crate) fn take_immediate(&mut self) -> Vec<CallAction> {
-        std::mem::take(&mut self.immediate)
+        let mut actions = std::mem::take(&mut self.immediate);
+        actions.sort_by_key(CallAction::order);
+        actions
+    }
+
+    /// Cancel the pending actions matching the `predicate`, returning the number of cancelled
+    /// actions. Only actions that have not yet reached their depth are cancellable, once moved
+    /// to the immediate actions by [CallActions::track] they are applied in the current
+    /// `finish_cycle` regardless.
+    #[cfg(test)]
+    pub(crate) fn cancel_pending(&mut self, predicate: impl Fn(&CallAction) -> bool) -> usize {
+        let len = self.pending.len();
+        self.pending.retain(|(_, action)| !predicate(action));
+        len - self.pending.len()
+    }
+
+    /// Returns `true` if there are neither immediate nor pending actions.
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.immediate.is_empty() && self.pending.is_empty()
+    }
+
+    /// Returns `true` if there are actions pending for a subsequent FarCall.
+    #[cfg(test)]
+    pub(crate) fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Iterates over the pending actions in insertion order, alongside their remaining depth.
+    /// The depth is relative to the next FarCall, so a depth of `0` indicates the action is
+    /// moved to the immediate actions on the next call to [CallActions::track].
+    #[cfg(test)]
+    pub(crate) fn pending_iter(&self) -> impl Iterator<Item = (&CallDepth, &CallAction)> {
+        self.pending.iter().map(|(depth, action)| (depth, action))
+    }
+}
+
+/// Counts the FarCalls performed, by their address, calldata and value.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct CallCounter {
+    counts: HashMap<(H160, Vec<u8>, U256), usize>,
+}
+
+impl CallCounter {
+    /// Record a call.
+    pub(crate) fn increment(&mut self, address: H160, calldata: Vec<u8>, value: U256) {
+        *self.counts.entry((address, calldata, value)).or_default() += 1;
+    }
+
+    /// Counts the recorded calls matching the `address`, the `calldata` as prefix and the
+    /// `value`, if provided. This follows the same rules as [MockedCalls] matching.
+    pub(crate) fn count_matching(
+        &self,
+        address: H160,
+        calldata: &[u8],
+        value: Option<U256>,
+    ) -> usize {
+        self.counts
+            .iter()
+            .filter(|((call_address, call_calldata, call_value), _)| {
+                *call_address == address &&
+                    call_calldata.starts_with(calldata) &&
+                    value.map_or(true, |value| *call_value == value)
+            })
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Clear all recorded calls.
+    #[cfg(test)]
+    pub(crate) fn clear(&mut self) {
+        self.counts.clear();
+    }
+}
+
+/// A FarCall recorded during execution.
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedFarCall {
+    /// The parsed call.
+    pub(crate) call: ParsedFarCall,
+    /// The call stack depth of the called frame.
+    pub(crate) depth: CallDepth,
+    /// The FarCall opcode variant.
+    pub(crate) opcode: FarCallOpcode,
+    /// The outcome of the call, once the called frame completed.
+    pub(crate) outcome: Option<FarCallOutcome>,
+}
+
+/// The outcome of a completed FarCall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FarCallOutcome {
+    /// The called frame returned successfully.
+    Success,
+    /// The called frame reverted.
+    Revert,
+    /// The called frame panicked, e.g. ran out of ergs.
+    Panic,
+}
+
+impl FarCallOutcome {
+    /// Returns `true` if the called frame reverted or panicked.
+    pub(crate) fn is_revert(&self) -> bool {
+        !matches!(self, FarCallOutcome::Success)
+    }
+}
+
+impl From<RetOpcode> for FarCallOutcome {
+    fn from(ret: RetOpcode) -> Self {
+        match ret {
+            RetOpcode::Ok => FarCallOutcome::Success,
+            RetOpcode::Revert => FarCallOutcome::Revert,
+            RetOpcode::Panic => FarCallOutcome::Panic,
+        }
+    }
+}
+
+/// The returndata of a completed FarCall, captured during execution.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CapturedReturnData {
+    /// The call stack depth of the returning frame.
+    pub(crate) depth: CallDepth,
+    /// Whether the FarCall reverted or panicked.
+    pub(crate) is_revert: bool,
+    /// The returndata.
+    pub(crate) data: Vec<u8>,
+}
+
+/// A callback invoked on every parsed FarCall.
+pub(crate) type OnFarCall = Box<dyn FnMut(&ParsedFarCall, CallDepth) + Send>;
+
+/// Holds the optional [OnFarCall] callback. Cloning does not carry the callback over, as it
+/// cannot be cloned.
+#[derive(Default)]
+struct FarCallCallback(Option<OnFarCall>);
+
+impl Debug for FarCallCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("FarCallCallback").field(&self.0.as_ref().map(|_| "..")).finish()
+    }
+}
+
+impl Clone for FarCallCallback {
+    fn clone(&self) -> Self {
+        Self(None)
+    }
+}
+
+/// Observes the FarCalls tracked by a [FarCallHandler], e.g. for logging or coverage, see
+/// `FarCallHandler::set_observer`.
+pub(crate) trait FarCallObserver: Send {
+    /// Invoked once the FarCall is parsed, as the called frame at `depth` starts executing.
+    fn on_before(&mut self, _call: &ParsedFarCall, _depth: CallDepth) {}
+
+    /// Invoked once the called frame at `depth` completed with the `outcome`, including when
+    /// returning immediately.
+    fn on_after(&mut self, _depth: CallDepth, _outcome: FarCallOutcome) {}
+}
+
+/// Holds the optional [FarCallObserver]. Cloning does not carry the observer over, as it
+/// cannot be cloned.
+#[derive(Default)]
+struct FarCallObserverSlot(Option<Box<dyn FarCallObserver>>);
+
+impl Debug for FarCallObserverSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("FarCallObserverSlot").field(&self.0.as_ref().map(|_| "..")).finish()
+    }
+}
+
+impl Clone for FarCallObserverSlot {
+    fn clone(&self) -> Self {
+        Self(None)
+    }
+}
+
+/// A this-address override scoped to a single FarCall, see
+/// `FarCallHandler::set_scoped_this_address`.
+#[derive(Debug, Clone, PartialEq)]
+struct ScopedThisAddress {
+    /// The call stack depth of the frame whose exit ends the scope.
+    exit_depth: CallDepth,
+    /// The this-address of the caller, restored when the scope ends.
+    original: H160,
+}
+
+/// A value call forwarded by the MsgValueSimulator, whose FarCalls are not tracked for the
+/// [CallAction]s until the recipient frame is entered, see [FarCallHandler::track_call_actions].
+#[derive(Debug, Clone, PartialEq)]
+struct ValueCallHop {
+    /// The call stack depth of the MsgValueSimulator frame.
+    depth: CallDepth,
+    /// The recipient of the value call.
+    recipient: H160,
+}
+
+/// A `tx.origin` override scoped to the frame it was applied to, see
+/// [FarCallHandler::set_scoped_tx_origin].
+#[derive(Debug, Clone, PartialEq)]
+struct ScopedTxOrigin {
+    /// The call stack depth of the overridden frame, whose exit ends the scope.
+    depth: CallDepth,
+    /// The this-address of the overridden frame.
+    this_address: H160,
+    /// The overridden `tx.origin`.
+    origin: Address,
+}
+
+/// The deployed addresses forced for `ContractDeployer` deployments, by the hash of the deployed
+/// bytecode, see `FarCallHandler::override_deployment`.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct DeploymentOverrides {
+    addresses: HashMap<H256, H160>,
+}
+
+impl DeploymentOverrides {
+    /// Forces deployments of the `bytecode_hash` to return the `address`, replacing any previous
+    /// override for it.
+    #[cfg(test)]
+    pub(crate) fn insert(&mut self, bytecode_hash: H256, address: H160) {
+        self.addresses.insert(bytecode_hash, address);
+    }
+
+    /// Removes all overrides.
+    #[cfg(test)]
+    pub(crate) fn clear(&mut self) {
+        self.addresses.clear();
+    }
+
+    /// Returns `true` if no deployment is overridden.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// Returns the address to return for the `call`, if it is a `create` or `create2` deployment
+    /// of an overridden bytecode hash.
+    pub(crate) fn get_matching_address(&self, call: &ParsedFarCall) -> Option<H160> {
+        if self.addresses.is_empty() {
+            return None
+        }
+
+        let deployment = call.as_deployment()?;
+        self.addresses.get(&deployment.bytecode_hash).copied()
+    }
+}
+
+/// Selects the FarCalls a [ShortCircuitRule] applies to.
+pub(crate) type ShortCircuitPredicate = Arc<dyn Fn(&ParsedFarCall) -> bool + Send + Sync>;
+
+/// Returns or reverts immediately from every FarCall matching the predicate, e.g. all calls to
+/// an address with a given selector, see `FarCallHandler::add_short_circuit_rule`.
+#[derive(Clone)]
+pub(crate) struct ShortCircuitRule {
+    predicate: ShortCircuitPredicate,
+    outcome: MockCallReturn,
+}
+
+impl ShortCircuitRule {
+    /// Creates a rule returning or reverting with the `outcome` from FarCalls matching the
+    /// `predicate`.
+    #[cfg(test)]
+    pub(crate) fn new(
+        predicate: impl Fn(&ParsedFarCall) -> bool + Send + Sync + 'static,
+        outcome: MockCallReturn,
+    ) -> Self {
+        Self { predicate: Arc::new(predicate), outcome }
+    }
+
+    /// Returns `true` if the rule applies to the `call`.
+    fn matches(&self, call: &ParsedFarCall) -> bool {
+        (self.predicate)(call)
+    }
+}
+
+impl Debug for ShortCircuitRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShortCircuitRule")
+            .field("predicate", &"..")
+            .field("outcome", &self.outcome)
+            .finish()
     }
 }
 
+/// The returndata override of a FarCall executing normally, see
+/// [ImmediateReturnMode::OverrideOnReturn].
+#[derive(Debug, Clone)]
+struct ReturnOverride {
+    /// The call stack depth of the called frame, whose `ret` has its returndata overridden.
+    exit_depth: CallDepth,
+    immediate_return: ImmediateReturn,
+}
+
+/// Errors that may occur when handling FarCalls.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub(crate) enum FarCallHandlerError {
+    /// No FarCall is currently active.
+    #[error("no active far call stack")]
+    NoActiveFarCall,
+    /// The call value does not fit in the `u128` frame context value.
+    #[error("call value {0} exceeds u128")]
+    CallValueOverflow(U256),
+    /// The return memory page is not a heap page of any frame, e.g. for a zeroed frame.
+    #[error("invalid return memory page {0}")]
+    InvalidReturnMemoryPage(u32),
+    /// The return data does not fit in the return memory page, see [MAX_RETURN_DATA_LEN].
+    #[error("return data of {length} bytes exceeds the {max} bytes left in the return page")]
+    ReturnDataTooLarge { length: usize, max: u32 },
+}
+
 /// Tracks state of FarCalls to be able to return from them earlier.
 /// This effectively short-circuits the execution and ignores following opcodes.
 #[derive(Debug, Default, Clone)]
@@ -132,57 +682,202 @@ pub(crate) struct FarCallHandler {
     pub(crate) after_far_call_stack: Option<CallStackEntry>,
     pub(crate) current_far_call: Option<FarCallOpcode>,
     pub(crate) immediate_return: Option<ImmediateReturn>,
+    return_overrides: Vec<ReturnOverride>,
+    /// The returndata override of the FarCall that returned in the current cycle, written
+    /// during `finish_cycle`.
+    ready_return_override: Option<ImmediateReturn>,
     call_actions: CallActions,
+    call_counter: CallCounter,
+    /// The addresses whose FarCalls are counted, see [FarCallHandler::count_calls_to].
+    counted_addresses: HashSet<H160>,
+    record_far_calls: bool,
+    recorded_far_calls: Vec<RecordedFarCall>,
+    capture_return_data: bool,
+    pending_far_return: Option<(CallDepth, FarCallOutcome)>,
+    #[cfg(test)]
+    last_far_call_outcome: Option<(CallDepth, FarCallOutcome)>,
+    captured_return_data: Vec<CapturedReturnData>,
+    far_call_depth: CallDepth,
+    scoped_this_addresses: Vec<ScopedThisAddress>,
+    scoped_tx_origins: Vec<ScopedTxOrigin>,
+    value_call_hop: Option<ValueCallHop>,
+    on_far_call: FarCallCallback,
+    observer: FarCallObserverSlot,
+    /// The events emitted by mocked calls interleaved with the events the VM records, alongside
+    /// the call stack depth of the frame they are emitted into, i.e. the caller of the mocked
+    /// call, see [FarCallHandler::settle_mocked_events].
+    mocked_events: Vec<(CallDepth, EmittedEvent)>,
+    mocked_call_options: HashMap<MockCall, ImmediateReturnOptions>,
+    deployment_overrides: DeploymentOverrides,
+    short_circuit_rules: Vec<ShortCircuitRule>,
+    mocked_calls: MockedCalls,
+    calldata_bytes_read: Option<u64>,
+    /// The buffer calldata is read into when parsing FarCalls, reused across FarCalls unless the
+    /// parsed FarCall is recorded.
+    calldata_buffer: Vec<u8>,
 }
 
 impl FarCallHandler {
     /// Marks the current FarCall opcode to return immediately during `finish_cycle`.
-    /// Must be called during either `before_execution` or `after_execution`.
-    pub(crate) fn set_immediate_return(&mut self, return_data: Vec<u8>) {
-        let immediate_return = self.current_far_call.and_then(|call| match call {
-            FarCallOpcode::Normal | FarCallOpcode::Delegate => {
-                self.before_far_call_stack.map(|before| ImmediateReturn {
-                    return_data,
-                    return_base_memory_page: before.base_memory_page.0,
-                    next_pc: before.pc.saturating_add(1),
-                    next_code_page: before.code_page.0,
-                    next_base_memory_page: before.base_memory_page.0,
-                    next_sp: before.sp,
-                    next_exception_handler_location: before.exception_handler_location,
-                    next_this_address: before.this_address,
-                    next_is_local_frame: false,
-                    next_context_u128_value: 0,
-                })
-            }
-            // Mimic calls case is used to handle the case when a value is sent to a function.
-            // These calls go through a call to MsgValue simulator contract and then do a mimic call
-            // to the actual contract.
-            FarCallOpcode::Mimic => self.before_far_call_stack.map(|before| ImmediateReturn {
-                return_data,
-                // base_memory_page for returndata must be set to current base_memory_page and not
-                // of the caller for calls with value. Reasons unknown, but required in zk vm.
-                return_base_memory_page: self
-                    .after_far_call_stack
-                    .map(|after| after.base_memory_page.0)
-                    .unwrap_or(before.base_memory_page.0),
-                next_pc: before.pc.saturating_add(1),
-                next_code_page: before.code_page.0,
-                next_base_memory_page: before.base_memory_page.0,
-                next_sp: before.sp,
-                next_exception_handler_location: before.exception_handler_location,
-                next_this_address: before.this_address,
-                // `is_local_frame` for return satck needs to be set to same as before state when
-                // returning from calls with value. Reasons unknown, but required in zk vm.
-                next_is_local_frame: before.is_local_frame,
-                next_context_u128_value: 0,
-            }),
-        });
+    /// Must be called during `after_execution`.
+    pub(crate) fn set_immediate_return(
+        &mut self,
+        return_data: Vec<u8>,
+    ) -> Result<(), FarCallHandlerError> {
+        self.set_immediate_mock_return(MockCallReturn::Return(return_data), Default::default())
+    }
 
-        if let Some(immediate_return) = immediate_return {
-            self.immediate_return.replace(immediate_return);
-        } else {
+    /// Marks the current FarCall opcode to revert immediately during `finish_cycle`.
+    /// Must be called during `after_execution`.
+    #[cfg(test)]
+    pub(crate) fn set_immediate_revert(
+        &mut self,
+        revert_data: Vec<u8>,
+    ) -> Result<(), FarCallHandlerError> {
+        self.set_immediate_mock_return(MockCallReturn::Revert(revert_data), Default::default())
+    }
+
+    /// Marks the current FarCall opcode to return or revert immediately during `finish_cycle`,
+    /// depending on the [MockCallReturn] variant, see [ImmediateReturnOptions] for the
+    /// returned to frame's overrides.
+    /// Must be called during `after_execution`, as the return data is written to the called
+    /// frame's heap page, and fails with [FarCallHandlerError::NoActiveFarCall] otherwise. Fails
+    /// with [FarCallHandlerError::ReturnDataTooLarge] if the data exceeds [MAX_RETURN_DATA_LEN]
+    /// past its start in the return memory page.
+    pub(crate) fn set_immediate_mock_return(
+        &mut self,
+        mock_return: MockCallReturn,
+        options: ImmediateReturnOptions,
+    ) -> Result<(), FarCallHandlerError> {
+        let ImmediateReturnOptions { gas_left, context_u128_value, logs, return_data_start, mode } =
+            options;
+        let is_revert = mock_return.is_revert();
+        let return_data = mock_return.into_data();
+        let return_data_chunks = return_data.chunks(WORD_SIZE).len();
+        // Word aligned, as the return data is written to the return memory page in words.
+        let return_data_start = return_data_start.map_or(0, |start| {
+            let word_size = WORD_SIZE as u32;
+            start.checked_next_multiple_of(word_size).unwrap_or(start - start % word_size)
+        });
+        let (Some(call), Some(before), Some(after)) =
+            (self.current_far_call, self.before_far_call_stack, self.after_far_call_stack)
+        else {
             tracing::warn!("No active far call stack, ignoring immediate return");
+            return Err(FarCallHandlerError::NoActiveFarCall)
+        };
+        let immediate_return = ImmediateReturn {
+            opcode: call,
+            return_data,
+            is_revert,
+            // The return data is written to the callee's heap page, which is discarded on return,
+            // as the caller's heap past its heap bound must remain zeroed for it to grow into.
+            // Calls with value go through a call to the MsgValueSimulator contract and then do a
+            // mimic call to the actual contract, which also requires the callee's page.
+            return_memory_page: return_heap_page(after.base_memory_page.0),
+            return_data_start,
+            return_data_chunks,
+            next_frame: FrameRestore {
+                // a reverting call resumes at the exception handler registered for the callee
+                pc: if is_revert {
+                    after.exception_handler_location
+                } else {
+                    before.pc.saturating_add(1)
+                },
+                // a delegatecall made from within a near call returns to the caller's local
+                // frame, which must be preserved like for calls with value
+                is_local_frame: match call {
+                    FarCallOpcode::Normal => false,
+                    FarCallOpcode::Delegate | FarCallOpcode::Mimic => before.is_local_frame,
+                },
+                context_u128_value: context_u128_value.unwrap_or_default(),
+                ..FrameRestore::snapshot(&before)
+            },
+            next_ergs_remaining: gas_left,
+            emitter: after.this_address,
+            logs,
+        };
+        // writing to a page of the zero base memory page would clobber VM internals
+        let return_memory_page = immediate_return.return_memory_page;
+        if return_memory_page <= return_heap_page(0) {
+            tracing::warn!(
+                return_memory_page,
+                "Invalid return memory page, ignoring immediate return"
+            );
+            return Err(FarCallHandlerError::InvalidReturnMemoryPage(return_memory_page))
+        }
+        // the return data must be addressable within the return memory page, past its start
+        let max = MAX_RETURN_DATA_LEN - immediate_return.return_data_start;
+        let length = immediate_return.return_data.len();
+        if length > max as usize {
+            tracing::warn!(
+                length,
+                max,
+                "Return data exceeds the return memory page, ignoring immediate return"
+            );
+            return Err(FarCallHandlerError::ReturnDataTooLarge { length, max })
+        }
+
+        match mode {
+            ImmediateReturnMode::SkipExecution => {
+                self.immediate_return.replace(immediate_return);
+            }
+            ImmediateReturnMode::OverrideOnReturn => {
+                self.return_overrides.push(ReturnOverride {
+                    exit_depth: self.far_call_depth.clone(),
+                    immediate_return,
+                });
+            }
         }
+        Ok(())
+    }
+
+    /// Returns the pending [ImmediateReturn], if any, without consuming it, e.g. to inspect the
+    /// restored frame before `finish_cycle` applies it.
+    pub(crate) fn peek_immediate_return(&self) -> Option<&ImmediateReturn> {
+        self.immediate_return.as_ref()
+    }
+
+    /// Resets all state tracked across FarCalls, keeping only the recording and capturing
+    /// configuration, and the FarCall callback. Must be called between transactions when reusing
+    /// the handler, so that pending [CallAction]s or an unconsumed [ImmediateReturn] do not leak
+    /// into the next one.
+    #[cfg(test)]
+    pub(crate) fn reset(&mut self) {
+        *self = FarCallHandler {
+            record_far_calls: self.record_far_calls,
+            capture_return_data: self.capture_return_data,
+            on_far_call: std::mem::take(&mut self.on_far_call),
+            observer: std::mem::take(&mut self.observer),
+            calldata_bytes_read: self.calldata_bytes_read.map(|_| 0),
+            ..Default::default()
+        };
+    }
+
+    /// Returns the this-address of the frame the most recently entered FarCall was made from,
+    /// to match mocked calls constrained by their caller, see [MockCall::caller].
+    pub(crate) fn caller(&self) -> Option<H160> {
+        self.before_far_call_stack.map(|before| before.this_address)
+    }
+
+    /// Returns the caller's frame of the most recently entered FarCall, as tracked during
+    /// `before_execution`.
+    #[cfg(test)]
+    pub(crate) fn before_stack(&self) -> Option<&CallStackEntry> {
+        self.before_far_call_stack.as_ref()
+    }
+
+    /// Returns the called frame of the most recently entered FarCall, as tracked during
+    /// `after_execution`.
+    #[cfg(test)]
+    pub(crate) fn after_stack(&self) -> Option<&CallStackEntry> {
+        self.after_far_call_stack.as_ref()
+    }
+
+    /// Returns the opcode variant of the most recently entered FarCall, as tracked during
+    /// `before_execution` and `after_execution`.
+    #[cfg(test)]
+    pub(crate) fn current_opcode(&self) -> Option<FarCallOpcode> {
+        self.current_far_call
     }
 
     /// Sets a [CallAction] for the current or subsequent FarCalls during `finish_cycle`.
@@ -191,6 +886,100 @@ impl FarCallHandler {
         self.call_actions.push(depth, action)
     }
 
+    /// Overrides the this-address for the currently active FarCall only, restoring the caller's
+    /// this-address once the called frame exits. For calls with value the override applies to the
+    /// recipient frame entered by the MsgValueSimulator, after the value transfer, see
+    /// [FarCallHandler::track_call_actions].
+    /// Must be called during `after_execution`, and fails with
+    /// [FarCallHandlerError::NoActiveFarCall] otherwise.
+    #[cfg(test)]
+    pub(crate) fn set_scoped_this_address(
+        &mut self,
+        address: Address,
+    ) -> Result<(), FarCallHandlerError> {
+        let (Some(before), Some(after)) = (self.before_far_call_stack, self.after_far_call_stack)
+        else {
+            tracing::warn!("No active far call stack, ignoring scoped this-address override");
+            return Err(FarCallHandlerError::NoActiveFarCall)
+        };
+
+        let depth = if after.code_address == MSG_VALUE_SIMULATOR_ADDRESS {
+            CallDepth::next()
+        } else {
+            CallDepth::current()
+        };
+        self.call_actions.push(depth, CallAction::SetThisAddress(address));
+        self.scoped_this_addresses.push(ScopedThisAddress {
+            exit_depth: self.far_call_depth.clone(),
+            original: before.this_address,
+        });
+
+        Ok(())
+    }
+
+    /// Ends the scoped overrides of the exiting frame at `depth`.
+    fn on_far_call_exit(&mut self, depth: CallDepth) {
+        while self.scoped_this_addresses.last().is_some_and(|scoped| scoped.exit_depth == depth) {
+            let scoped = self.scoped_this_addresses.pop().expect("scoped override must exist");
+            self.call_actions.push(
+                CallDepth::current(),
+                CallAction::SetThisAddress(scoped.original.to_address()),
+            );
+        }
+        self.scoped_tx_origins.retain(|scoped| scoped.depth != depth);
+        if self.value_call_hop.as_ref().is_some_and(|hop| hop.depth == depth) {
+            // the recipient was never entered, e.g. the value transfer reverted
+            self.value_call_hop = None;
+        }
+    }
+
+    /// Overrides `tx.origin` for the frame at `depth` with the `this_address`, i.e. the frame a
+    /// [CallAction::SetTxOrigin] is applied to. The override ends once the frame exits, restoring
+    /// any override of an outer frame, and does not apply to the calls it makes, see
+    /// [FarCallHandler::scoped_tx_origin].
+    pub(crate) fn set_scoped_tx_origin(
+        &mut self,
+        depth: CallDepth,
+        this_address: H160,
+        origin: Address,
+    ) {
+        self.scoped_tx_origins.push(ScopedTxOrigin { depth, this_address, origin });
+    }
+
+    /// Returns the overridden `tx.origin` for the frame at `depth` with the `this_address`,
+    /// i.e. the caller of `SystemContext.origin()`, if any. The most recent override applies.
+    pub(crate) fn scoped_tx_origin(
+        &self,
+        depth: &CallDepth,
+        this_address: H160,
+    ) -> Option<Address> {
+        self.scoped_tx_origins
+            .iter()
+            .rev()
+            .find(|scoped| scoped.depth == *depth && scoped.this_address == this_address)
+            .map(|scoped| scoped.origin)
+    }
+
+    /// Returns `true` if any [CallAction] is scheduled, immediate or pending, so that
+    /// `finish_cycle` may skip taking the immediate actions otherwise.
+    #[cfg(test)]
+    pub(crate) fn has_actions(&self) -> bool {
+        !self.call_actions.is_empty()
+    }
+
+    /// Returns `true` if any [CallAction] is pending for a subsequent FarCall.
+    #[cfg(test)]
+    pub(crate) fn has_pending_actions(&self) -> bool {
+        self.call_actions.has_pending()
+    }
+
+    /// Iterates over the pending [CallAction]s alongside their remaining depth, see
+    /// [CallActions::pending_iter].
+    #[cfg(test)]
+    pub(crate) fn pending_actions(&self) -> impl Iterator<Item = (&CallDepth, &CallAction)> {
+        self.call_actions.pending_iter()
+    }
+
     /// Tracks the call stack for the currently active FarCall.
     /// Must be called during `before_execution`.
     pub(crate) fn track_before_far_calls(
@@ -198,326 +987,6119 @@ impl FarCallHandler {
         state: &VmLocalStateData<'_>,
         data: &BeforeExecutionData,
     ) {
-        if let Opcode::FarCall(call) = data.opcode.variant.opcode {
-            self.before_far_call_stack.replace(state.vm_local_state.callstack.current);
-            let _ = self.after_far_call_stack.take();
-            self.current_far_call.replace(call);
+        let _span = tracing::trace_span!(
+            "track_before_far_calls",
+            depth = state.vm_local_state.callstack.depth(),
+        )
+        .entered();
+        match data.opcode.variant.opcode {
+            Opcode::FarCall(call) => {
+                tracing::trace!(
+                    opcode = ?call,
+                    caller = ?state.vm_local_state.callstack.current.this_address,
+                    "far call"
+                );
+                self.before_far_call_stack.replace(state.vm_local_state.callstack.current);
+                let _ = self.after_far_call_stack.take();
+                self.current_far_call.replace(call);
+            }
+            // near calls also return via `ret`, but only FarCalls pass returndata
+            Opcode::Ret(ret) => {
+                let current = state.vm_local_state.callstack.current;
+                let depth = CallDepth::new(state.vm_local_state.callstack.depth() as u16);
+                if !current.is_local_frame {
+                    tracing::trace!(?ret, ?depth, "far return");
+                    self.on_far_call_exit(depth.clone());
+                    self.pending_far_return.replace((depth, ret.into()));
+                } else {
+                    // reverted near calls roll back their events as well
+                    self.settle_mocked_events(&depth, ret.into());
+                }
+            }
+            // events spanning multiple messages are recorded once, on their first message
+            Opcode::Log(LogOpcode::Event) if data.opcode.variant.flags[FIRST_MESSAGE_FLAG_IDX] => {
+                let depth = CallDepth::new(state.vm_local_state.callstack.depth() as u16);
+                self.mocked_events.push((depth, EmittedEvent::Recorded));
+            }
+            _ => (),
         }
     }
 
-    /// Tracks the call stack for the currently active FarCall.
+    /// Tracks the call stack for the currently active FarCall, and the outcome of the completed
+    /// FarCall, capturing its returndata if enabled.
     /// Must be called during `after_execution`.
-    pub(crate) fn track_after_far_calls(
+    pub(crate) fn track_after_far_calls<H: HistoryMode>(
         &mut self,
         state: &VmLocalStateData<'_>,
         data: &AfterExecutionData,
+        memory: &SimpleMemory<H>,
     ) {
-        if let Opcode::FarCall(call) = data.opcode.variant.opcode {
-            self.after_far_call_stack.replace(state.vm_local_state.callstack.current);
-            self.current_far_call.replace(call);
+        match data.opcode.variant.opcode {
+            Opcode::FarCall(call) => {
+                self.after_far_call_stack.replace(state.vm_local_state.callstack.current);
+                self.current_far_call.replace(call);
+                self.far_call_depth = CallDepth::new(state.vm_local_state.callstack.depth() as u16);
+            }
+            Opcode::Ret(_) => {
+                if let Some((depth, outcome)) = self.pending_far_return.take() {
+                    self.on_far_call_completed(depth.clone(), outcome);
+                    self.settle_mocked_events(&depth, outcome);
+                    // the overridden logs are only emitted if the call executed successfully
+                    if let Some(events) = self
+                        .prepare_return_override(&depth)
+                        .filter(|_| !outcome.is_revert())
+                        .map(ImmediateReturn::events)
+                    {
+                        self.push_mocked_events(depth.clone().decrement(), events);
+                    }
+                    if !self.capture_return_data {
+                        return
+                    }
+
+                    let reg = state.vm_local_state.registers
+                        [RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER as usize];
+                    // the caller reads the overridden returndata instead
+                    let data = if let Some(return_override) = &self.ready_return_override {
+                        return_override.return_data.clone()
+                    } else if reg.is_pointer {
+                        read_fat_pointer_bytes(FatPointer::from_u256(reg.value), memory)
+                    } else {
+                        vec![]
+                    };
+                    self.on_far_call_return(depth, outcome.is_revert(), data);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Readies the returndata override of the called frame at `depth` that just returned, if
+    /// any, to be written during `finish_cycle`.
+    fn prepare_return_override(&mut self, depth: &CallDepth) -> Option<&ImmediateReturn> {
+        self.ready_return_override = None;
+        if self
+            .return_overrides
+            .last()
+            .is_some_and(|return_override| return_override.exit_depth == *depth)
+        {
+            let return_override = self.return_overrides.pop().expect("return override must exist");
+            self.ready_return_override = Some(return_override.immediate_return);
+        }
+        self.ready_return_override.as_ref()
+    }
+
+    /// Records the outcome of the completed FarCall at `depth`, onto the most recently recorded
+    /// FarCall at the same depth that has not yet completed.
+    fn on_far_call_completed(&mut self, depth: CallDepth, outcome: FarCallOutcome) {
+        if let Some(recorded) = self
+            .recorded_far_calls
+            .iter_mut()
+            .rev()
+            .find(|recorded| recorded.depth == depth && recorded.outcome.is_none())
+        {
+            recorded.outcome = Some(outcome);
+        }
+        if let Some(observer) = self.observer.0.as_mut() {
+            observer.on_after(depth.clone(), outcome);
+        }
+        #[cfg(test)]
+        {
+            self.last_far_call_outcome = Some((depth, outcome));
+        }
+    }
+
+    /// Returns the outcome of the most recently completed FarCall, alongside the call stack depth
+    /// of its called frame. Immediate returns complete the FarCall as well.
+    #[cfg(test)]
+    pub(crate) fn last_far_call_outcome(&self) -> Option<(CallDepth, FarCallOutcome)> {
+        self.last_far_call_outcome.clone()
+    }
+
+    /// Captures the returndata of a completed FarCall.
+    fn on_far_call_return(&mut self, depth: CallDepth, is_revert: bool, data: Vec<u8>) {
+        if self.capture_return_data {
+            self.captured_return_data.push(CapturedReturnData { depth, is_revert, data });
         }
     }
 
+    /// Enables or disables capturing the returndata of all completed FarCalls. Capturing is
+    /// disabled by default.
+    #[cfg(test)]
+    pub(crate) fn set_capture_return_data(&mut self, enabled: bool) {
+        self.capture_return_data = enabled;
+    }
+
+    /// Returns the captured returndata, in the order the FarCalls completed.
+    #[cfg(test)]
+    pub(crate) fn captured_return_data(&self) -> &[CapturedReturnData] {
+        &self.captured_return_data
+    }
+
+    /// Consumes the captured returndata.
+    #[cfg(test)]
+    pub(crate) fn take_captured_return_data(&mut self) -> Vec<CapturedReturnData> {
+        std::mem::take(&mut self.captured_return_data)
+    }
+
     /// Tracks the call stack for the currently executable [CallAction]s.
+    /// Calls with value are made to the MsgValueSimulator, which transfers the value via the
+    /// `L2EthToken` and then mimics the call to the recipient, so the pending actions target the
+    /// recipient frame, skipping any FarCall in between.
     /// Must be called during `after_execution`.
     pub(crate) fn track_call_actions(
         &mut self,
         state: &VmLocalStateData<'_>,
         data: &AfterExecutionData,
     ) {
-        if let Opcode::FarCall(_call) = data.opcode.variant.opcode {
-            self.call_actions.track();
+        if let Opcode::FarCall(call) = data.opcode.variant.opcode {
+            let depth = CallDepth::new(state.vm_local_state.callstack.depth() as u16);
+            self.on_far_call_entered(
+                call,
+                &state.vm_local_state.callstack.current,
+                depth,
+                &state.vm_local_state.registers,
+            );
         }
     }
 
-    /// Attempts to return the preset data ignoring any following opcodes, if set.
-    /// Must be called during `finish_cycle`.
-    pub(crate) fn maybe_return_early<S: WriteStorage + Send, H: HistoryMode>(
+    /// Tracks the [CallAction]s for the entered `current` frame at `depth`, unless it is the
+    /// MsgValueSimulator frame or any frame it enters before the recipient of the value call,
+    /// see [FarCallHandler::track_call_actions].
+    fn on_far_call_entered(
         &mut self,
-        state: &mut ZkSyncVmState<S, H>,
-        _bootloader_state: &mut BootloaderState,
+        call: FarCallOpcode,
+        current: &CallStackEntry,
+        depth: CallDepth,
+        reg: &[PrimitiveValue],
     ) {
-        if let Some(immediate_return) = self.immediate_return.take() {
-            // set return data
-            let data_chunks = immediate_return.return_data.chunks(32);
-            let return_memory_page = CallStackEntry::heap_page_from_base(MemoryPage(
-                immediate_return.return_base_memory_page,
-            ));
-            let return_fat_ptr = FatPointer {
-                memory_page: return_memory_page.0,
-                offset: 0,
-                start: 0,
-                length: (data_chunks.len() as u32) * 32,
-            };
-            let start_slot = (return_fat_ptr.start / 32) as usize;
-            let data = data_chunks
-                .enumerate()
-                .map(|(index, value)| (start_slot + index, U256::from_big_endian(value)))
-                .collect_vec();
-            state.local_state.registers[RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER as usize] =
-                PrimitiveValue { value: return_fat_ptr.to_u256(), is_pointer: true };
-            state.memory.populate_page(
-                return_fat_ptr.memory_page as usize,
-                data,
-                Timestamp(state.local_state.timestamp),
-            );
+        if let Some(hop) = &self.value_call_hop {
+            let is_recipient = call == FarCallOpcode::Mimic &&
+                depth == hop.depth.clone().increment() &&
+                current.code_address == hop.recipient;
+            if !is_recipient {
+                tracing::trace!(to = ?current.code_address, "value call hop, skipping call actions");
+                return
+            }
+            self.value_call_hop = None;
+        }
 
-            // change current stack to simulate return
-            let current = state.local_state.callstack.get_current_stack_mut();
-            current.pc = immediate_return.next_pc;
-            current.base_memory_page = MemoryPage(immediate_return.next_base_memory_page);
-            current.code_page = MemoryPage(immediate_return.next_code_page);
-            current.context_u128_value = immediate_return.next_context_u128_value;
-            current.sp = immediate_return.next_sp;
-            current.exception_handler_location = immediate_return.next_exception_handler_location;
-            current.this_address = immediate_return.next_this_address;
-            current.is_local_frame = immediate_return.next_is_local_frame;
+        if current.code_address == MSG_VALUE_SIMULATOR_ADDRESS {
+            let config = FarCallParseConfig::default();
+            let recipient = reg[config.address_reg()].value.to_h256().to_h160();
+            tracing::trace!(?recipient, "value call, skipping the MsgValueSimulator hop");
+            self.value_call_hop = Some(ValueCallHop { depth, recipient });
+            return
         }
+
+        self.call_actions.track();
     }
 
-    /// Returns immediate [CallAction]s for the currently active FarCall.
-    /// Must be called during `finish_cycle`.
-    pub(crate) fn take_immediate_actions<S: WriteStorage + Send, H: HistoryMode>(
-        &mut self,
-        state: &mut ZkSyncVmState<S, H>,
-        _bootloader_state: &mut BootloaderState,
-    ) -> Vec<CallAction> {
-        self.call_actions.take_immediate()
+    /// Returns `true` if anything consumes the parsed FarCalls, i.e. a counted address, a mocked
+    /// call, a deployment override, a [ShortCircuitRule], the FarCall callback or observer, or
+    /// the recording or calldata counting, see [FarCallHandler::track_parsed_far_calls].
+    fn has_parsed_far_call_consumers(&self) -> bool {
+        !self.counted_addresses.is_empty() ||
+            !self.mocked_calls.is_empty() ||
+            !self.deployment_overrides.is_empty() ||
+            !self.short_circuit_rules.is_empty() ||
+            self.on_far_call.0.is_some() ||
+            self.observer.0.is_some() ||
+            self.record_far_calls ||
+            self.calldata_bytes_read.is_some()
     }
-}
 
-/// Defines the [MockCall]s return type.
-type MockCallReturn = Vec<u8>;
+    /// Parses the currently active FarCall, to count and optionally record it.
+    /// FarCalls are only parsed if anything consumes them, so that tracing costs nothing
+    /// otherwise, see [FarCallHandler::has_parsed_far_call_consumers].
+    /// Must be called during `after_execution`, as the called frame is only then available.
+    pub(crate) fn track_parsed_far_calls<H: HistoryMode>(
+        &mut self,
+        state: &VmLocalStateData<'_>,
+        data: &AfterExecutionData,
+        memory: &SimpleMemory<H>,
+    ) {
+        if !self.has_parsed_far_call_consumers() {
+            return
+        }
 
-/// Defines the match criteria of a mocked call.
-#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
-pub(crate) struct MockCall {
-    pub(crate) address: H160,
-    pub(crate) value: Option<U256>,
-    pub(crate) calldata: Vec<u8>,
-}
+        if let Opcode::FarCall(opcode) = data.opcode.variant.opcode {
+            // the opcode is known here, so the kind need not be inferred
+            let call = parse_frame_with_buffer(
+                state.vm_local_state.callstack.get_current_stack(),
+                &state.vm_local_state.registers,
+                memory,
+                &FarCallParseConfig::default(),
+                opcode.into(),
+                Some(&mut self.calldata_buffer),
+            );
+            let depth = CallDepth::new(state.vm_local_state.callstack.depth() as u16);
+            self.on_parsed_far_call(call, depth, opcode);
+        }
+    }
 
-/// Contains the list of mocked calls.
-/// Note that mocked calls with value take precedence of the ones without.
-#[derive(Default, Debug, Clone)]
-pub(crate) struct MockedCalls {
-    /// List of mocked calls with the value parameter.
-    pub(crate) with_value: HashMap<MockCall, MockCallReturn>,
+    /// Sets the callback invoked on every parsed FarCall, replacing any previous one.
+    /// The callback is invoked during `after_execution`, once the called frame is active.
+    #[cfg(test)]
+    pub(crate) fn set_on_far_call(
+        &mut self,
+        callback: impl FnMut(&ParsedFarCall, CallDepth) + Send + 'static,
+    ) {
+        self.on_far_call.0.replace(Box::new(callback));
+    }
 
-    /// List of mocked calls without the value parameter.
-    pub(crate) without_value: HashMap<MockCall, MockCallReturn>,
-}
+    /// Sets the [FarCallObserver] notified of every tracked FarCall, replacing any previous one.
+    #[cfg(test)]
+    pub(crate) fn set_observer(&mut self, observer: impl FarCallObserver + 'static) {
+        self.observer.0.replace(Box::new(observer));
+    }
 
-impl MockedCalls {
-    /// Insert a mocked call with its return data.
-    pub(crate) fn insert(&mut self, call: MockCall, return_data: MockCallReturn) {
-        if call.value.is_some() {
-            self.with_value.insert(call, return_data);
+    /// Removes the [FarCallObserver], returning it if set.
+    #[cfg(test)]
+    pub(crate) fn take_observer(&mut self) -> Option<Box<dyn FarCallObserver>> {
+        self.observer.0.take()
+    }
+
+    /// Removes the callback invoked on every parsed FarCall, returning it if set.
+    #[cfg(test)]
+    pub(crate) fn take_on_far_call(&mut self) -> Option<OnFarCall> {
+        self.on_far_call.0.take()
+    }
+
+    /// Counts the parsed FarCall, and records it if enabled. Mocked calls, overridden deployments
+    /// and calls matching a [ShortCircuitRule] are marked to return immediately, see
+    /// [FarCallHandler::mock_call] and `FarCallHandler::override_deployment`.
+    fn on_parsed_far_call(&mut self, call: ParsedFarCall, depth: CallDepth, opcode: FarCallOpcode) {
+        // the immediate return is recorded once known, as callbacks may set it
+        let span = tracing::debug_span!(
+            "far_call",
+            to = ?call.to(),
+            selector = %call.selector(),
+            value = %call.value(),
+            depth = depth.0,
+            immediate_return = tracing::field::Empty,
+        );
+        let _guard = span.enter();
+
+        // the callback is taken out while invoked, so it can never be reentered
+        if let Some(mut callback) = self.on_far_call.0.take() {
+            callback(&call, depth.clone());
+            self.on_far_call.0.get_or_insert(callback);
+        }
+        if let Some(observer) = self.observer.0.as_mut() {
+            observer.on_before(&call, depth.clone());
+        }
+
+        // value calls are mocked once the MsgValueSimulator mimics the call to the recipient
+        let mock = match &call {
+            ParsedFarCall::ValueCall { .. } => None,
+            ParsedFarCall::SimpleCall { .. } => self.mocked_calls.get_matching_mock_for_call(&call),
+        };
+        if let Some((mock, mock_return)) = mock {
+            tracing::info!(
+                revert = mock_return.is_revert(),
+                "returning mocked value {:?}",
+                hex::encode(mock_return.data())
+            );
+            let mut options = self.mocked_call_options.get(&mock).cloned().unwrap_or_default();
+            // the returned region mirrors the caller's calldata region, rather than assuming
+            // the page start
+            let calldata_region = call.far_call_abi().memory_quasi_fat_pointer;
+            options
+                .return_data_start
+                .get_or_insert(calldata_region.start.saturating_add(calldata_region.offset));
+            let _ = self.set_immediate_mock_return(mock_return, options);
+        } else if let Some(address) = self.deployment_overrides.get_matching_address(&call) {
+            tracing::debug!(?address, "returning overridden deployment address");
+            let _ = self.set_immediate_return(H256::from(address).as_bytes().to_vec());
+        } else if let Some(outcome) = self
+            .short_circuit_rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(&call))
+            .map(|rule| rule.outcome.clone())
+        {
+            tracing::debug!(?outcome, "short-circuiting far call");
+            let _ = self.set_immediate_mock_return(outcome, Default::default());
+        }
+
+        span.record("immediate_return", self.immediate_return.is_some());
+
+        if let Some(calldata_bytes_read) = self.calldata_bytes_read.as_mut() {
+            *calldata_bytes_read += call.calldata().len() as u64;
+        }
+        // only calls to the counted addresses are counted, keeping the counter bounded
+        if self.counted_addresses.contains(call.to()) {
+            self.call_counter.increment(*call.to(), call.calldata().to_vec(), *call.value());
+        }
+        if self.record_far_calls {
+            self.recorded_far_calls.push(RecordedFarCall { call, depth, opcode, outcome: None });
         } else {
-            self.without_value.insert(call, return_data);
+            // the call is not retained, so its calldata is reused to parse the next FarCall
+            self.calldata_buffer = call.into_calldata();
         }
     }
 
-    /// Clear all mocked calls.
-    pub(crate) fn clear(&mut self) {
-        self.with_value.clear();
-        self.without_value.clear();
+    /// Mocks the FarCalls matching the `call` to return or revert with the `return_data`, see
+    /// [MockedCalls::insert] and [MockedCalls::get_matching_mock_for_call] for the matching
+    /// rules. Calls with value are mocked on the recipient frame.
+    /// The `options` apply to every matching FarCall, e.g. the logs emitted by the called address
+    /// before returning, or whether it executes, see [ImmediateReturnOptions].
+    pub(crate) fn mock_call(
+        &mut self,
+        call: MockCall,
+        return_data: MockCallReturn,
+        options: ImmediateReturnOptions,
+    ) {
+        if options == ImmediateReturnOptions::default() {
+            self.mocked_call_options.remove(&call);
+        } else {
+            self.mocked_call_options.insert(call.clone(), options);
+        }
+        self.mocked_calls.insert(call, return_data);
     }
 
-    /// Matches the mocked calls based on foundry rules. The matching is in the precedence order of:
-    /// * Calls with value parameter and exact calldata match
-    /// * Exact calldata matches
-    /// * Partial calldata matches
-    pub(crate) fn get_matching_return_data(
+    /// Enables or disables counting the calldata bytes read when parsing FarCalls, see
+    /// [FarCallHandler::calldata_bytes_read]. Counting is disabled by default, and enabling it
+    /// restarts the count.
+    #[cfg(test)]
+    pub(crate) fn set_count_calldata_bytes(&mut self, enabled: bool) {
+        self.calldata_bytes_read = enabled.then_some(0);
+    }
+
+    /// Returns the total calldata bytes read from memory when parsing FarCalls, as truncated
+    /// by the maximum calldata length, or `None` if counting is disabled.
+    #[cfg(test)]
+    pub(crate) fn calldata_bytes_read(&self) -> Option<u64> {
+        self.calldata_bytes_read
+    }
+
+    /// Enables or disables recording of all parsed FarCalls. Recording is disabled by default.
+    #[cfg(test)]
+    pub(crate) fn set_record_far_calls(&mut self, enabled: bool) {
+        self.record_far_calls = enabled;
+    }
+
+    /// Returns the recorded FarCalls, in the order they were executed.
+    #[cfg(test)]
+    pub(crate) fn recorded_far_calls(&self) -> &[RecordedFarCall] {
+        &self.recorded_far_calls
+    }
+
+    /// Consumes the recorded FarCalls.
+    #[cfg(test)]
+    pub(crate) fn take_recorded_far_calls(&mut self) -> Vec<RecordedFarCall> {
+        std::mem::take(&mut self.recorded_far_calls)
+    }
+
+    /// Counts the tracked FarCalls matching the `address`, the `calldata` as prefix and the
+    /// `value`, if provided. Only the calls to addresses registered via
+    /// [FarCallHandler::count_calls_to] are tracked.
+    pub(crate) fn count_matching_calls(
         &self,
-        code_address: H160,
-        actual_calldata: &[u8],
-        actual_value: U256,
-    ) -> Option<Vec<u8>> {
-        let mut best_match = None;
+        address: H160,
+        calldata: &[u8],
+        value: Option<U256>,
+    ) -> usize {
+        self.call_counter.count_matching(address, calldata, value)
+    }
 
-        for (call, call_return_data) in self.with_value.iter().chain(self.without_value.iter()) {
-            if call.address == code_address {
-                let value_matches = call.value.map_or(true, |value| value == actual_value);
-                if !value_matches {
-                    continue
+    /// Tracks the FarCalls to the `address`, to be counted via
+    /// [FarCallHandler::count_matching_calls].
+    pub(crate) fn count_calls_to(&mut self, address: H160) {
+        self.counted_addresses.insert(address);
+    }
+
+    /// Forces `create` and `create2` deployments of the `bytecode_hash` via the `ContractDeployer`
+    /// to return the `address` immediately, instead of deploying the bytecode.
+    #[cfg(test)]
+    pub(crate) fn override_deployment(&mut self, bytecode_hash: H256, address: H160) {
+        self.deployment_overrides.insert(bytecode_hash, address);
+    }
+
+    /// Removes all deployment overrides, see [FarCallHandler::override_deployment].
+    #[cfg(test)]
+    pub(crate) fn clear_deployment_overrides(&mut self) {
+        self.deployment_overrides.clear();
+    }
+
+    /// Registers a [ShortCircuitRule], evaluated against every parsed FarCall so that matching
+    /// calls return or revert immediately without setting the immediate return per call.
+    /// The last registered matching rule applies, and deployment overrides take precedence,
+    /// see [FarCallHandler::override_deployment].
+    #[cfg(test)]
+    pub(crate) fn add_short_circuit_rule(&mut self, rule: ShortCircuitRule) {
+        self.short_circuit_rules.push(rule);
+    }
+
+    /// Removes all [ShortCircuitRule]s.
+    #[cfg(test)]
+    pub(crate) fn clear_short_circuit_rules(&mut self) {
+        self.short_circuit_rules.clear();
+    }
+
+    /// Consumes the events emitted by mocked calls, in the order the mocked calls returned,
+    /// alongside the number of events the VM recorded before each of them. The VM does not
+    /// record them, so they must be inserted into the transaction's events at that position.
+    /// Events emitted within reverted frames are discarded, see
+    /// [FarCallHandler::settle_mocked_events].
+    pub(crate) fn take_mocked_events(&mut self) -> Vec<(usize, VmEvent)> {
+        let mut recorded = 0;
+        std::mem::take(&mut self.mocked_events)
+            .into_iter()
+            .filter_map(|(_, event)| match event {
+                EmittedEvent::Recorded => {
+                    recorded += 1;
+                    None
                 }
+                EmittedEvent::Mocked(event) => Some((recorded, event)),
+            })
+            .collect()
+    }
 
-                if actual_calldata.starts_with(&call.calldata) {
-                    // return early if exact match
-                    if call.calldata.len() == actual_calldata.len() {
-                        return Some(call_return_data.clone())
-                    }
+    /// Records the `events` of a mocked call, as emitted into the frame at `depth`.
+    fn push_mocked_events(&mut self, depth: CallDepth, events: Vec<VmEvent>) {
+        self.mocked_events
+            .extend(events.into_iter().map(|event| (depth.clone(), EmittedEvent::Mocked(event))));
+    }
 
-                    // else check for partial matches and pick the best
-                    let matched_len = call.calldata.len();
-                    best_match = best_match.map_or(
-                        Some((matched_len, call_return_data)),
-                        |(best_match, best_match_return_data)| {
-                            if matched_len > best_match {
-                                Some((matched_len, call_return_data))
-                            } else {
-                                Some((best_match, best_match_return_data))
-                            }
-                        },
-                    );
+    /// Settles the mocked events emitted into the frame at `depth` or any of its completed
+    /// subframes, as the frame exits with the `outcome`. They are discarded if it reverted, like
+    /// the events the VM records, or otherwise attributed to its caller.
+    fn settle_mocked_events(&mut self, depth: &CallDepth, outcome: FarCallOutcome) {
+        if outcome.is_revert() {
+            self.mocked_events.retain(|(event_depth, _)| event_depth < depth);
+        } else {
+            let caller_depth = depth.clone().decrement();
+            for (event_depth, _) in self.mocked_events.iter_mut() {
+                if &*event_depth >= depth {
+                    *event_depth = caller_depth.clone();
                 }
             }
         }
+    }
+
+    /// Attempts to return the preset data ignoring any following opcodes, if set.
+    /// For reverts, the returndata is set identically but execution resumes at the
+    /// exception handler location instead of the next instruction.
+    /// Returns the number of return data bytes written if the FarCall returned early, e.g. to
+    /// record the size of a mocked call's return data.
+    /// Must be called during `finish_cycle`. The storage need not be `Send`, as the VM state
+    /// holds it behind an `Rc` regardless.
+    pub(crate) fn maybe_return_early<S: WriteStorage, H: HistoryMode>(
+        &mut self,
+        state: &mut ZkSyncVmState<S, H>,
+        _bootloader_state: &mut BootloaderState,
+    ) -> Option<usize> {
+        let _span = tracing::trace_span!(
+            "maybe_return_early",
+            depth = state.local_state.callstack.depth(),
+            immediate_return = self.immediate_return.is_some(),
+        )
+        .entered();
+        let immediate_return = self.immediate_return.take()?;
+        tracing::debug!(
+            opcode = ?immediate_return.opcode,
+            revert = immediate_return.is_revert,
+            "returning early from far call"
+        );
+
+        let written = immediate_return.write_return_data(
+            &mut state.local_state.registers,
+            &mut state.memory,
+            Timestamp(state.local_state.timestamp),
+        );
+
+        // the returned from frame exits without a `ret`, and its caller's frame is restored
+        // as is, so any scoped overrides end with it
+        let depth = CallDepth::new(state.local_state.callstack.depth() as u16);
+        // logs are emitted by the returned from frame into its caller, preceding the return
+        self.push_mocked_events(depth.clone().decrement(), immediate_return.events());
+        self.scoped_this_addresses.retain(|scoped| scoped.exit_depth != depth);
+        self.scoped_tx_origins.retain(|scoped| scoped.depth != depth);
+        if self.value_call_hop.as_ref().is_some_and(|hop| hop.depth == depth) {
+            self.value_call_hop = None;
+        }
+        self.return_overrides.retain(|return_override| return_override.exit_depth != depth);
+        let outcome = if immediate_return.is_revert {
+            FarCallOutcome::Revert
+        } else {
+            FarCallOutcome::Success
+        };
+        self.on_far_call_completed(depth, outcome);
 
-        best_match.map(|(_, return_data)| return_data.clone())
+        // change current stack to simulate return
+        let current = state.local_state.callstack.get_current_stack_mut();
+        immediate_return.restore_frame(current);
+        Some(written)
     }
-}
 
-/// Selector for `L2EthToken::balanceOf(uint256)`
-pub const SELECTOR_L2_ETH_BALANCE_OF: &str = "9cc7f708";
-/// Selector for `SystemContext::getBlockNumber()`
-pub const SELECTOR_SYSTEM_CONTEXT_BLOCK_NUMBER: &str = "42cbb15c";
-/// Selector for `SystemContext::getBlockTimestamp()`
-pub const SELECTOR_SYSTEM_CONTEXT_BLOCK_TIMESTAMP: &str = "796b89b9";
-// Selector for `ContractDeployer::create(bytes32, bytes32, bytes)`
-pub const SELECTOR_CONTRACT_DEPLOYER_CREATE: &str = "9c4d535b";
-// Selector for `ContractDeployer::create2(bytes32, bytes32, bytes)`
-pub const SELECTOR_CONTRACT_DEPLOYER_CREATE2: &str = "3cda3351";
+    /// Overrides the returndata of the FarCall that returned in the current cycle, if set with
+    /// [ImmediateReturnMode::OverrideOnReturn]. Unlike [FarCallHandler::maybe_return_early] the
+    /// returned to frame is left as is.
+    /// Returns the number of return data bytes written if the returndata was overridden.
+    /// Must be called during `finish_cycle`.
+    pub(crate) fn maybe_override_return_data<S: WriteStorage, H: HistoryMode>(
+        &mut self,
+        state: &mut ZkSyncVmState<S, H>,
+        _bootloader_state: &mut BootloaderState,
+    ) -> Option<usize> {
+        self.apply_return_override(
+            &mut state.local_state.registers,
+            &mut state.memory,
+            Timestamp(state.local_state.timestamp),
+        )
+    }
 
-/// Represents a parsed FarCall from the ZK-EVM
-pub enum ParsedFarCall {
-    /// A call to MsgValueSimulator contract used when transferring ETH
-    ValueCall { to: H160, value: U256, calldata: Vec<u8>, recipient: H160, is_system_call: bool },
-    /// A simple FarCall with calldata.
-    SimpleCall { to: H160, value: U256, calldata: Vec<u8> },
+    /// Writes the ready returndata override, see [FarCallHandler::maybe_override_return_data].
+    fn apply_return_override<H: HistoryMode>(
+        &mut self,
+        registers: &mut [PrimitiveValue],
+        memory: &mut SimpleMemory<H>,
+        timestamp: Timestamp,
+    ) -> Option<usize> {
+        let return_override = self.ready_return_override.take()?;
+        tracing::debug!(
+            opcode = ?return_override.opcode,
+            revert = return_override.is_revert,
+            "overriding far call returndata"
+        );
+
+        Some(return_override.write_return_data(registers, memory, timestamp))
+    }
+
+    /// Returns immediate [CallAction]s for the currently active FarCall, in application order,
+    /// see [CallActions::take_immediate].
+    /// Must be called during `finish_cycle`.
+    pub(crate) fn take_immediate_actions<S: WriteStorage, H: HistoryMode>(
+        &mut self,
+        _state: &mut ZkSyncVmState<S, H>,
+        _bootloader_state: &mut BootloaderState,
+    ) -> Vec<CallAction> {
+        self.call_actions.take_immediate()
+    }
 }
 
-impl ParsedFarCall {
-    /// Retrieves the `to` address for the call, if any
-    pub(crate) fn to(&self) -> &H160 {
+/// Defines the [MockCall]s return type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum MockCallReturn {
+    /// Return the data successfully.
+    Return(Vec<u8>),
+    /// Revert with the data.
+    Revert(Vec<u8>),
+}
+
+impl MockCallReturn {
+    /// Returns `true` if the mocked call must revert.
+    pub(crate) fn is_revert(&self) -> bool {
+        matches!(self, MockCallReturn::Revert(_))
+    }
+
+    /// Retrieves the return or revert data.
+    pub(crate) fn data(&self) -> &[u8] {
         match self {
-            ParsedFarCall::ValueCall { to, .. } => to,
-            ParsedFarCall::SimpleCall { to, .. } => to,
+            MockCallReturn::Return(data) => data,
+            MockCallReturn::Revert(data) => data,
         }
     }
 
-    /// Retrieves the `value` for the call
-    pub(crate) fn value(&self) -> &U256 {
+    /// Consumes self, returning the return or revert data.
+    pub(crate) fn into_data(self) -> Vec<u8> {
         match self {
-            ParsedFarCall::ValueCall { value, .. } => value,
-            ParsedFarCall::SimpleCall { value, .. } => value,
+            MockCallReturn::Return(data) => data,
+            MockCallReturn::Revert(data) => data,
         }
     }
+}
 
-    /// Retrieves the selector for the call, or returns an empty string if none.
-    pub(crate) fn selector(&self) -> String {
-        let calldata = self.calldata();
+/// Defines the value match criteria of a mocked call.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) enum ValueMatch {
+    /// Matches any value.
+    #[default]
+    Any,
+    /// Matches the exact value.
+    Exact(U256),
+}
 
-        if calldata.len() < 4 {
-            String::from("")
-        } else {
-            hex::encode(&calldata[0..4])
+impl ValueMatch {
+    /// Returns `true` if the `value` satisfies the criteria.
+    pub(crate) fn matches(&self, value: U256) -> bool {
+        match self {
+            ValueMatch::Any => true,
+            ValueMatch::Exact(exact) => value == *exact,
         }
     }
 
-    /// Retrieves the calldata for the call, if any
-    pub(crate) fn calldata(&self) -> &[u8] {
+    /// Returns `true` if the criteria constrains the value.
+    pub(crate) fn is_constrained(&self) -> bool {
+        !matches!(self, ValueMatch::Any)
+    }
+
+    /// Returns `true` if the criteria matches a strictly narrower set of values than `other`,
+    /// i.e. it is constrained while `other` is not.
+    pub(crate) fn is_narrower_than(&self, other: &ValueMatch) -> bool {
+        self.is_constrained() && !other.is_constrained()
+    }
+}
+
+/// Defines the calldata match criteria of a mocked call.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) enum CalldataMatch {
+    /// Matches any calldata, with the lowest precedence.
+    #[default]
+    Any,
+    /// Matches calldata starting with the bytes, or exactly equal to them.
+    /// An empty prefix only matches empty calldata, use [CalldataMatch::Any] to match any
+    /// calldata. Note that empty prefixes previously matched any calldata.
+    Prefix(#[cfg_attr(feature = "serde", serde(with = "hex::serde"))] Vec<u8>),
+}
+
+/// The precedence of a calldata match, in increasing order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum CalldataMatchRank {
+    /// Any calldata match.
+    Any,
+    /// Partial calldata match, of the given length.
+    Partial(usize),
+    /// Exact calldata match.
+    Exact,
+}
+
+impl CalldataMatch {
+    /// Returns the [CalldataMatchRank] if the `calldata` satisfies the criteria.
+    fn rank(&self, calldata: &[u8]) -> Option<CalldataMatchRank> {
         match self {
-            ParsedFarCall::ValueCall { calldata, .. } => calldata,
-            ParsedFarCall::SimpleCall { calldata, .. } => calldata,
+            CalldataMatch::Any => Some(CalldataMatchRank::Any),
+            CalldataMatch::Prefix(prefix) => {
+                if !calldata.starts_with(prefix) {
+                    None
+                } else if prefix.len() == calldata.len() {
+                    Some(CalldataMatchRank::Exact)
+                } else if prefix.is_empty() {
+                    // an empty prefix is not an implicit catch-all
+                    None
+                } else {
+                    Some(CalldataMatchRank::Partial(prefix.len()))
+                }
+            }
         }
     }
+}
 
-    /// Retrieves the parameters from calldata, if any
-    pub(crate) fn params(&self) -> Vec<[u8; 32]> {
-        let params = &match self {
-            ParsedFarCall::ValueCall { calldata, .. } => calldata,
-            ParsedFarCall::SimpleCall { calldata, .. } => calldata,
-        }[4..];
-        if params.is_empty() {
-            return Vec::new()
+/// Defines the match criteria of a mocked call.
+/// A mocked call without an `address` matches calls to any address, and one without a `caller`
+/// matches calls from any caller.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct MockCall {
+    pub(crate) address: Option<H160>,
+    pub(crate) value: ValueMatch,
+    pub(crate) calldata: CalldataMatch,
+    /// The this-address of the calling frame.
+    pub(crate) caller: Option<H160>,
+    /// The maximum call stack depth of the called frame the mocked call applies to, e.g. to
+    /// only mock calls made directly by the test contract, see
+    /// `MockedCalls::get_matching_return_data_at_depth`.
+    pub(crate) max_depth: Option<CallDepth>,
+}
+
+impl MockCall {
+    /// Creates a mocked call matching all calls to the `address`, with any value and calldata,
+    /// to be narrowed down via struct update syntax. Both [H160]s and [Address]es are accepted,
+    /// so that all addresses are converted the same way.
+    pub(crate) fn new(address: impl IntoH160) -> Self {
+        Self {
+            address: Some(address.into_h160()),
+            value: ValueMatch::Any,
+            calldata: CalldataMatch::Any,
+            ..Default::default()
         }
+    }
 
-        params
-            .chunks(32)
-            .map(|c| c.try_into().expect("chunk must be exactly 32 bytes"))
-            .collect_vec()
+    /// Creates a mocked call for the `address`, matching calls to the function with the
+    /// `signature`, e.g. `transfer(address,uint256)`, whose arguments start with the ABI-encoded
+    /// `args`. The signature must be canonical, i.e. without spaces nor parameter names, as the
+    /// selector is its keccak hash. Any value matches.
+    #[cfg(test)]
+    pub(crate) fn from_signature(
+        address: impl IntoH160,
+        signature: &str,
+        args: Vec<alloy_dyn_abi::DynSolValue>,
+    ) -> Self {
+        let mut calldata = alloy_primitives::keccak256(signature)[..4].to_vec();
+        calldata.extend(alloy_dyn_abi::DynSolValue::Tuple(args).abi_encode_params());
+        Self { calldata: CalldataMatch::Prefix(calldata), ..Self::new(address) }
     }
 
-    /// Retrieves all bytes after the `offset` number of 32byte words
-    pub(crate) fn param_bytes_after(&self, offset_words: usize) -> Vec<u8> {
-        let params = &match self {
-            ParsedFarCall::ValueCall { calldata, .. } => calldata,
-            ParsedFarCall::SimpleCall { calldata, .. } => calldata,
-        }[4..];
-        if params.is_empty() || params.len() < 32 * offset_words {
-            return Vec::new()
-        }
+    /// Returns `true` if a call at `depth` is within the scope of the mocked call. A call at an
+    /// unknown depth is only within the scope of mocked calls without a maximum depth.
+    fn is_in_scope(&self, depth: Option<&CallDepth>) -> bool {
+        self.max_depth
+            .as_ref()
+            .map_or(true, |max_depth| depth.is_some_and(|depth| depth <= max_depth))
+    }
+}
 
-        params[32 * offset_words..].to_vec()
+/// The actual call a [MockCall] is matched against.
+#[derive(Debug, Clone, Copy)]
+struct MockCallContext<'a> {
+    calldata: &'a [u8],
+    value: U256,
+    /// The this-address of the calling frame, if known.
+    caller: Option<H160>,
+    /// The call stack depth of the called frame, if known.
+    depth: Option<CallDepth>,
+}
+
+/// Defines the queued [MockCallReturn]s of a mocked call.
+/// Each match consumes the front of the queue, and the last entry is repeated once exhausted.
+type MockCallReturns = VecDeque<MockCallReturn>;
+
+/// Locates a mocked call within [MockedCalls].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MockCallIndex {
+    /// The address the mocked call is registered for.
+    address: Option<H160>,
+    /// The list the mocked call is registered in.
+    bucket: MockCallBucket,
+    /// The index within the list.
+    index: usize,
+}
+
+/// Identifies the list of [MockedCalls] a mocked call is registered in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum MockCallBucket {
+    /// Mocked calls with the value parameter.
+    WithValue,
+    /// Mocked calls without the value parameter.
+    WithoutValue,
+}
+
+impl MockCallBucket {
+    /// Returns the bucket the `call` is registered in.
+    fn of(call: &MockCall) -> Self {
+        if call.value.is_constrained() {
+            MockCallBucket::WithValue
+        } else {
+            MockCallBucket::WithoutValue
+        }
     }
 }
 
-impl Debug for ParsedFarCall {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ParsedFarCall::ValueCall { to, value, calldata, recipient, is_system_call } => f
-                .debug_struct("ValueCall")
-                .field("to", to)
-                .field("value", value)
-                .field("calldata", &hex::encode(calldata))
-                .field("recipient", recipient)
-                .field("is_system_call", is_system_call)
-                .finish(),
-            ParsedFarCall::SimpleCall { to, value, calldata } => f
-                .debug_struct("SimpleCall")
-                .field("to", to)
-                .field("value", value)
-                .field("calldata", &hex::encode(calldata))
-                .finish(),
+/// A registered mocked call, with its queued return data.
+#[derive(Debug, Clone)]
+struct MockCallEntry {
+    call: MockCall,
+    returns: MockCallReturns,
+    /// The number of times the mocked call matched, see `MockedCalls::invocation_count`.
+    invocations: usize,
+}
+
+/// The mocked calls registered for a single address, or for any address, in insertion order.
+#[derive(Default, Debug, Clone)]
+struct AddressMockedCalls {
+    /// List of mocked calls with the value parameter.
+    with_value: Vec<MockCallEntry>,
+
+    /// List of mocked calls without the value parameter.
+    without_value: Vec<MockCallEntry>,
+}
+
+impl AddressMockedCalls {
+    fn bucket(&self, bucket: MockCallBucket) -> &Vec<MockCallEntry> {
+        match bucket {
+            MockCallBucket::WithValue => &self.with_value,
+            MockCallBucket::WithoutValue => &self.without_value,
+        }
+    }
+
+    fn bucket_mut(&mut self, bucket: MockCallBucket) -> &mut Vec<MockCallEntry> {
+        match bucket {
+            MockCallBucket::WithValue => &mut self.with_value,
+            MockCallBucket::WithoutValue => &mut self.without_value,
         }
     }
+
+    fn len(&self) -> usize {
+        self.with_value.len() + self.without_value.len()
+    }
 }
 
-const MSG_VALUE_SIMULATOR_ADDRESS_EXTRA_PARAM_REG_OFFSET: u8 = CALL_SYSTEM_ABI_REGISTERS.start;
-const MSG_VALUE_SIMULATOR_DATA_VALUE_REG: u8 = MSG_VALUE_SIMULATOR_ADDRESS_EXTRA_PARAM_REG_OFFSET;
-const MSG_VALUE_SIMULATOR_DATA_ADDRESS_REG: u8 =
-    MSG_VALUE_SIMULATOR_ADDRESS_EXTRA_PARAM_REG_OFFSET + 1;
-const MSG_VALUE_SIMULATOR_DATA_IS_SYSTEM_REG: u8 =
-    MSG_VALUE_SIMULATOR_ADDRESS_EXTRA_PARAM_REG_OFFSET + 2;
-const MSG_VALUE_SIMULATOR_IS_SYSTEM_BIT: u8 = 1;
+/// Contains the mocked calls, indexed by the address they are registered for so that matching
+/// a call only considers the ones registered for the called address and for any address.
+/// Note that mocked calls with value take precedence of the ones without.
+#[derive(Default, Debug, Clone)]
+pub(crate) struct MockedCalls {
+    /// Mocked calls by their address, with `None` for the ones registered for any address.
+    by_address: HashMap<Option<H160>, AddressMockedCalls>,
 
-/// Parses a FarCall into ZKSync's normal calls or MsgValue calls.
-/// For MsgValueSimulator call parsing, see https://github.com/matter-labs/era-system-contracts/blob/main/contracts/MsgValueSimulator.sol#L25
-/// For normal call parsing, see https://github.com/matter-labs/zksync-era/blob/main/core/lib/multivm/src/tracers/call_tracer/vm_latest/mod.rs#L115
-pub(crate) fn parse<H: HistoryMode>(
-    state: &VmLocalStateData<'_>,
-    memory: &SimpleMemory<H>,
-) -> ParsedFarCall {
-    let current = state.vm_local_state.callstack.get_current_stack();
-    let reg = &state.vm_local_state.registers;
-    let value = U256::from(current.context_u128_value);
+    /// Minimum length of partial calldata matches, shorter ones are ignored.
+    min_match_len: usize,
 
-    let packed_abi = reg[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize];
-    assert!(packed_abi.is_pointer);
-    let far_call_abi = FarCallABI::from_u256(packed_abi.value);
-    let calldata = memory.read_unaligned_bytes(
-        far_call_abi.memory_quasi_fat_pointer.memory_page as usize,
-        far_call_abi.memory_quasi_fat_pointer.start as usize,
-        far_call_abi.memory_quasi_fat_pointer.length as usize,
-    );
-    if current.code_address == MSG_VALUE_SIMULATOR_ADDRESS {
-        let value = U256::from(reg[MSG_VALUE_SIMULATOR_DATA_VALUE_REG as usize].value.low_u128());
-        let address = reg[MSG_VALUE_SIMULATOR_DATA_ADDRESS_REG as usize].value.to_h256();
-        let address = address.to_h160();
-        let is_system_call = reg[MSG_VALUE_SIMULATOR_DATA_IS_SYSTEM_REG as usize]
-            .value
-            .bit(MSG_VALUE_SIMULATOR_IS_SYSTEM_BIT as usize);
+    /// Mocked calls with dynamic return data, in insertion order, see
+    /// `MockedCalls::insert_dynamic`.
+    dynamic: Vec<DynamicMockCall>,
 
-        ParsedFarCall::ValueCall {
-            to: current.code_address,
-            value,
-            calldata,
-            recipient: address,
-            is_system_call,
+    /// Whether inserting a mocked call replaces the conflicting ones, see
+    /// `MockedCalls::set_replace_conflicting`.
+    replace_conflicting: bool,
+}
+
+/// Computes the return data of a dynamic mocked call from the matched call.
+pub(crate) type MockCallFn = Arc<dyn Fn(&ParsedFarCall) -> Vec<u8> + Send + Sync>;
+
+/// A mocked call with its return data computed from the matched call. Kept apart from the other
+/// mocked calls, as the return data cannot be compared nor hashed.
+#[derive(Clone)]
+struct DynamicMockCall {
+    call: MockCall,
+    returns: MockCallFn,
+}
+
+impl Debug for DynamicMockCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DynamicMockCall").field("call", &self.call).field("returns", &"..").finish()
+    }
+}
+
+impl MockedCalls {
+    /// Insert a mocked call with its return or revert data.
+    /// Re-inserting an existing mocked call replaces it, and marks it as the latest registered.
+    /// See `MockedCalls::set_replace_conflicting` to also replace conflicting mocked calls.
+    pub(crate) fn insert(&mut self, call: MockCall, return_data: MockCallReturn) {
+        self.insert_queue(call, [return_data])
+    }
+
+    /// Insert a mocked call with a queue of return or revert data, returned in order on each
+    /// successive match. Once the queue is exhausted, the last entry is returned for all
+    /// subsequent matches. Empty queues are ignored.
+    /// Re-inserting an existing mocked call replaces it, and marks it as the latest registered.
+    pub(crate) fn insert_queue(
+        &mut self,
+        call: MockCall,
+        returns: impl IntoIterator<Item = MockCallReturn>,
+    ) {
+        let returns = returns.into_iter().collect::<MockCallReturns>();
+        if returns.is_empty() {
+            tracing::warn!(?call, "ignoring mocked call with no return data");
+            return
         }
-    } else {
-        ParsedFarCall::SimpleCall { to: current.code_address, value, calldata }
+
+        let mocks =
+            self.by_address.entry(call.address).or_default().bucket_mut(MockCallBucket::of(&call));
+        let replace_conflicting = self.replace_conflicting;
+        mocks.retain(|existing| {
+            if existing.call == call {
+                return false
+            }
+            let is_conflicting = replace_conflicting &&
+                existing.call.value == call.value &&
+                existing.call.calldata == call.calldata;
+            if is_conflicting {
+                tracing::warn!(replaced = ?existing.call, ?call, "replacing conflicting mocked call");
+            }
+            !is_conflicting
+        });
+        mocks.push(MockCallEntry { call, returns, invocations: 0 });
+    }
+
+    /// Sets whether inserting a mocked call replaces the existing ones registered for the same
+    /// address with the same value and calldata match, regardless of their other criteria,
+    /// logging a warning for each replaced mocked call. Such mocked calls would otherwise both
+    /// match the same calls, with the selected one only depending on the registration order,
+    /// which usually hints at a fixture bug. Defaults to `false`, only replacing identical
+    /// mocked calls.
+    #[cfg(test)]
+    pub(crate) fn set_replace_conflicting(&mut self, replace_conflicting: bool) {
+        self.replace_conflicting = replace_conflicting;
+    }
+
+    /// Insert a mocked call with a sequence of outcomes, each returned or reverted with for the
+    /// given number of successive matches, e.g. succeeding twice and then reverting. Once the
+    /// sequence is exhausted, the last outcome is returned for all subsequent matches, see
+    /// [MockedCalls::insert_queue]. Outcomes repeated `0` times are skipped.
+    #[cfg(test)]
+    pub(crate) fn insert_sequence(
+        &mut self,
+        call: MockCall,
+        outcomes: impl IntoIterator<Item = (usize, MockCallReturn)>,
+    ) {
+        let returns = outcomes
+            .into_iter()
+            .flat_map(|(times, return_data)| std::iter::repeat(return_data).take(times));
+        self.insert_queue(call, returns)
+    }
+
+    /// Returns the number of times the registered mocked call matched since it was inserted, or
+    /// `None` if it is not registered. Only matches advancing its queue of return data count.
+    #[cfg(test)]
+    pub(crate) fn invocation_count(&self, call: &MockCall) -> Option<usize> {
+        self.by_address
+            .get(&call.address)?
+            .bucket(MockCallBucket::of(call))
+            .iter()
+            .find(|entry| &entry.call == call)
+            .map(|entry| entry.invocations)
+    }
+
+    /// Insert multiple mocked calls with their return or revert data, in order, following the
+    /// same rules as [MockedCalls::insert] for each entry.
+    pub(crate) fn insert_many(
+        &mut self,
+        entries: impl IntoIterator<Item = (MockCall, MockCallReturn)>,
+    ) {
+        for (call, return_data) in entries {
+            self.insert(call, return_data);
+        }
+    }
+
+    /// Insert a mocked call returning the data computed by `returns` from the matched call, e.g.
+    /// to echo its arguments. Dynamic mocked calls are only matched for [ParsedFarCall]s, see
+    /// [MockedCalls::get_matching_return_data_for_call], and only once no other mocked call
+    /// matches. Among them, the last registered matching one is used, regardless of how
+    /// specific its criteria are.
+    #[cfg(test)]
+    pub(crate) fn insert_dynamic(
+        &mut self,
+        call: MockCall,
+        returns: impl Fn(&ParsedFarCall) -> Vec<u8> + Send + Sync + 'static,
+    ) {
+        self.dynamic.push(DynamicMockCall { call, returns: Arc::new(returns) });
+    }
+
+    /// Sets the minimum length of partial calldata matches, below which they are ignored.
+    /// Exact matches are unaffected. Defaults to `0`, accepting all partial
+    /// matches.
+    #[cfg(test)]
+    pub(crate) fn set_min_match_len(&mut self, min_match_len: usize) {
+        self.min_match_len = min_match_len;
+    }
+
+    /// Returns the number of mocked calls, with or without the value parameter, including the
+    /// dynamic ones.
+    pub(crate) fn len(&self) -> usize {
+        self.by_address.values().map(AddressMockedCalls::len).sum::<usize>() + self.dynamic.len()
+    }
+
+    /// Returns `true` if there are no mocked calls.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over all mocked calls, with value first, ordered by the address they are registered
+    /// for and in insertion order for each address. Each entry yields the return data of its next
+    /// match.
+    #[cfg(test)]
+    pub(crate) fn iter_all(
+        &self,
+    ) -> impl Iterator<Item = (MockCallBucket, &MockCall, &MockCallReturn)> + '_ {
+        [MockCallBucket::WithValue, MockCallBucket::WithoutValue]
+            .into_iter()
+            .flat_map(move |bucket| {
+                self.by_address.iter().sorted_by_key(|(address, _)| **address).flat_map(
+                    move |(_, mocks)| mocks.bucket(bucket).iter().map(move |entry| (bucket, entry)),
+                )
+            })
+            .filter_map(|(bucket, entry)| Some((bucket, &entry.call, entry.returns.front()?)))
+    }
+
+    /// Returns a summary of all mocked calls, one per line, to be formatted for debugging,
+    /// e.g. when a call is unexpectedly not mocked.
+    #[cfg(test)]
+    pub(crate) fn summary(&self) -> MockedCallsSummary<'_> {
+        MockedCallsSummary(self)
+    }
+
+    /// Clear all mocked calls.
+    pub(crate) fn clear(&mut self) {
+        self.by_address.clear();
+        self.dynamic.clear();
+    }
+
+    /// Clear all mocked calls registered for the `address`, returning the number of removed
+    /// mocked calls. Mocked calls registered for any address are kept.
+    #[cfg(test)]
+    pub(crate) fn clear_for_address(&mut self, address: H160) -> usize {
+        let len = self.dynamic.len();
+        self.dynamic.retain(|mock| mock.call.address != Some(address));
+        let removed = len - self.dynamic.len();
+        removed + self.by_address.remove(&Some(address)).map_or(0, |mocks| mocks.len())
+    }
+
+    /// Returns the distinct addresses mocked calls are registered for, with or without the value
+    /// parameter, including the dynamic ones, e.g. to clear them selectively via
+    /// [MockedCalls::clear_for_address]. Mocked calls registered for any address are omitted.
+    #[cfg(test)]
+    pub(crate) fn mocked_addresses(&self) -> HashSet<H160> {
+        let mut addresses = HashSet::with_capacity(self.by_address.len());
+        addresses.extend(
+            self.by_address
+                .iter()
+                .filter(|(_, mocks)| mocks.len() > 0)
+                .filter_map(|(address, _)| *address),
+        );
+        addresses.extend(self.dynamic.iter().filter_map(|mock| mock.call.address));
+        addresses
+    }
+
+    /// Matches the mocked calls based on foundry rules. The matching is in the precedence order of:
+    /// * Calls with value parameter and exact calldata match
+    /// * Exact calldata matches
+    /// * Partial calldata matches, the longest first
+    ///
+    /// Among calls with value parameter matching the same calldata, the one with the narrowest
+    /// [ValueMatch] takes precedence.
+    ///
+    /// Mocked calls registered for the `code_address` always take precedence over the ones
+    /// registered for any address, with the above precedence applied within each of them.
+    ///
+    /// Partial matches of equal length are resolved deterministically, with mocked calls with
+    /// value winning over the ones without, and the last registered mocked call winning otherwise.
+    /// Partial matches shorter than [MockedCalls::set_min_match_len] are ignored.
+    ///
+    /// Matching a mocked call advances its queue of return data, see [MockedCalls::insert_queue].
+    #[cfg(test)]
+    pub(crate) fn get_matching_return_data(
+        &mut self,
+        code_address: H160,
+        actual_calldata: &[u8],
+        actual_value: U256,
+    ) -> Option<MockCallReturn> {
+        let index = self.find_matching_index(code_address, actual_calldata, actual_value, None)?;
+        self.next_return_data(index)
+    }
+
+    /// Matches the mocked calls for a call whose called frame is at `depth`, see
+    /// [MockedCalls::get_matching_return_data]. Unlike it, mocked calls scoped by
+    /// [MockCall::max_depth] are matched if the `depth` is within their scope.
+    #[cfg(test)]
+    pub(crate) fn get_matching_return_data_at_depth(
+        &mut self,
+        code_address: H160,
+        actual_calldata: &[u8],
+        actual_value: U256,
+        depth: CallDepth,
+    ) -> Option<MockCallReturn> {
+        let index =
+            self.find_matching_index(code_address, actual_calldata, actual_value, Some(depth))?;
+        self.next_return_data(index)
+    }
+
+    /// Borrows the return data of the best matching mocked call, without advancing its queue of
+    /// return data, see [MockedCalls::get_matching_return_data] for the matching rules.
+    #[cfg(test)]
+    pub(crate) fn get_matching_return_data_ref(
+        &self,
+        code_address: H160,
+        actual_calldata: &[u8],
+        actual_value: U256,
+    ) -> Option<&MockCallReturn> {
+        self.get_matching_mock(code_address, actual_calldata, actual_value)
+            .map(|(_, return_data)| return_data)
+    }
+
+    /// Returns the best matching mocked call alongside its next return data, without advancing
+    /// its queue of return data, see [MockedCalls::get_matching_return_data] for the matching
+    /// rules. Useful to assert which mocked call is selected.
+    #[cfg(test)]
+    pub(crate) fn get_matching_mock(
+        &self,
+        code_address: H160,
+        actual_calldata: &[u8],
+        actual_value: U256,
+    ) -> Option<(&MockCall, &MockCallReturn)> {
+        let index = self.find_matching_index(code_address, actual_calldata, actual_value, None)?;
+        let entry = self.entry(index);
+        Some((&entry.call, entry.returns.front()?))
+    }
+
+    /// Returns the mocked call that would match, without advancing its queue of return data nor
+    /// counting the invocation, see [MockedCalls::get_matching_return_data] for the matching
+    /// rules. Useful to validate fixtures before running them.
+    #[cfg(test)]
+    pub(crate) fn would_match(
+        &self,
+        code_address: H160,
+        actual_calldata: &[u8],
+        actual_value: U256,
+    ) -> Option<&MockCall> {
+        self.get_matching_mock(code_address, actual_calldata, actual_value).map(|(call, _)| call)
+    }
+
+    /// Finds the best matching mocked call for the `code_address`, falling back to the ones
+    /// registered for any address.
+    #[cfg(test)]
+    fn find_matching_index(
+        &self,
+        code_address: H160,
+        actual_calldata: &[u8],
+        actual_value: U256,
+        depth: Option<CallDepth>,
+    ) -> Option<MockCallIndex> {
+        let actual =
+            MockCallContext { calldata: actual_calldata, value: actual_value, caller: None, depth };
+        self.find_matching(Some(code_address), &actual)
+            .or_else(|| self.find_matching(None, &actual))
+    }
+
+    /// Matches the mocked calls for a [ParsedFarCall], see
+    /// [MockedCalls::get_matching_return_data].
+    ///
+    /// For [ParsedFarCall::ValueCall]s, mocked calls registered for the MsgValueSimulator take
+    /// precedence, followed by the ones registered for the `recipient` of the value, and then the
+    /// ones registered for any address.
+    #[cfg(test)]
+    pub(crate) fn get_matching_return_data_for_call(
+        &mut self,
+        call: &ParsedFarCall,
+    ) -> Option<MockCallReturn> {
+        self.find_matching_mock_for_call(call, None).map(|(_, return_data)| return_data)
+    }
+
+    /// Matches the mocked calls for a [ParsedFarCall], returning the matched mocked call
+    /// alongside its return data, see `MockedCalls::get_matching_return_data_for_call`.
+    pub(crate) fn get_matching_mock_for_call(
+        &mut self,
+        call: &ParsedFarCall,
+    ) -> Option<(MockCall, MockCallReturn)> {
+        self.find_matching_mock_for_call(call, None)
+    }
+
+    /// Matches the mocked calls for a [ParsedFarCall] made by the `caller`, see
+    /// [MockedCalls::get_matching_return_data_for_call]. Mocked calls constrained to the
+    /// `caller` take precedence over unconstrained ones, see [FarCallHandler::caller].
+    #[cfg(test)]
+    pub(crate) fn get_matching_return_data_for_caller(
+        &mut self,
+        call: &ParsedFarCall,
+        caller: H160,
+    ) -> Option<MockCallReturn> {
+        self.find_matching_mock_for_call(call, Some(caller)).map(|(_, return_data)| return_data)
+    }
+
+    fn find_matching_mock_for_call(
+        &mut self,
+        call: &ParsedFarCall,
+        caller: Option<H160>,
+    ) -> Option<(MockCall, MockCallReturn)> {
+        let actual = MockCallContext {
+            calldata: call.calldata(),
+            value: *call.value(),
+            caller,
+            depth: None,
+        };
+        let index = match call {
+            ParsedFarCall::ValueCall { to, recipient, .. } => self
+                .find_matching(Some(*to), &actual)
+                .or_else(|| self.find_matching(Some(*recipient), &actual)),
+            ParsedFarCall::SimpleCall { to, .. } => self.find_matching(Some(*to), &actual),
+        }
+        .or_else(|| self.find_matching(None, &actual));
+
+        match index {
+            Some(index) => {
+                let mock = self.entry(index).call.clone();
+                self.next_return_data(index).map(|return_data| (mock, return_data))
+            }
+            None => self.find_dynamic_mock(call, &actual),
+        }
+    }
+
+    /// Computes the return data of the last registered matching dynamic mocked call, see
+    /// `MockedCalls::insert_dynamic`.
+    fn find_dynamic_mock(
+        &self,
+        call: &ParsedFarCall,
+        actual: &MockCallContext<'_>,
+    ) -> Option<(MockCall, MockCallReturn)> {
+        let mock = self.dynamic.iter().rev().find(|mock| {
+            let address_matches = mock
+                .call
+                .address
+                .map_or(true, |address| address == *call.to() || call.recipient() == Some(address));
+            address_matches &&
+                mock.call.value.matches(actual.value) &&
+                mock.call.caller.map_or(true, |caller| actual.caller == Some(caller)) &&
+                mock.call.is_in_scope(actual.depth.as_ref()) &&
+                mock.call.calldata.rank(actual.calldata).is_some()
+        })?;
+
+        Some((mock.call.clone(), MockCallReturn::Return((mock.returns)(call))))
+    }
+
+    /// Returns the mocked call at `index`, with its queued return data.
+    fn entry(&self, index: MockCallIndex) -> &MockCallEntry {
+        &self.by_address[&index.address].bucket(index.bucket)[index.index]
+    }
+
+    /// Returns the next return data of the mocked call at `index`, advancing its queue.
+    /// Only the last queued entry is cloned, as it is repeated for subsequent matches.
+    fn next_return_data(&mut self, index: MockCallIndex) -> Option<MockCallReturn> {
+        let mocks = self.by_address.get_mut(&index.address)?;
+        let entry = &mut mocks.bucket_mut(index.bucket)[index.index];
+        entry.invocations += 1;
+        let returns = &mut entry.returns;
+        if returns.len() > 1 {
+            returns.pop_front()
+        } else {
+            returns.front().cloned()
+        }
+    }
+
+    /// Finds the best matching mocked call registered exactly for the `address`, see
+    /// `MockedCalls::get_matching_return_data`.
+    /// Mocked calls constrained to the caller take precedence over the unconstrained ones.
+    fn find_matching(
+        &self,
+        address: Option<H160>,
+        actual: &MockCallContext<'_>,
+    ) -> Option<MockCallIndex> {
+        self.find_matching_by_caller(address, actual, true)
+            .or_else(|| self.find_matching_by_caller(address, actual, false))
+    }
+
+    /// Finds the best matching mocked call registered exactly for the `address`, only among the
+    /// ones constrained to a caller if `caller_constrained`, or the unconstrained ones otherwise.
+    fn find_matching_by_caller(
+        &self,
+        address: Option<H160>,
+        actual: &MockCallContext<'_>,
+        caller_constrained: bool,
+    ) -> Option<MockCallIndex> {
+        let mocks = self.by_address.get(&address)?;
+        let mut best_match = None;
+
+        // iterate in reverse so the last registered call is the first to be considered
+        let candidates = [MockCallBucket::WithValue, MockCallBucket::WithoutValue]
+            .into_iter()
+            .flat_map(move |bucket| {
+                mocks.bucket(bucket).iter().enumerate().rev().map(move |(index, entry)| {
+                    (MockCallIndex { address, bucket, index }, &entry.call)
+                })
+            });
+        for (index, call) in candidates {
+            if call.caller.is_some() == caller_constrained {
+                if !call.value.matches(actual.value) ||
+                    call.caller.is_some_and(|caller| actual.caller != Some(caller)) ||
+                    !call.is_in_scope(actual.depth.as_ref())
+                {
+                    continue
+                }
+
+                if let Some(rank) = call.calldata.rank(actual.calldata) {
+                    if matches!(rank, CalldataMatchRank::Partial(len) if len < self.min_match_len) {
+                        continue
+                    }
+
+                    // pick the most specific calldata match, with the narrowest value match
+                    // taking precedence for equally specific calldata matches
+                    let is_better_match =
+                        best_match.map_or(true, |(best_match, best_match_value, _)| {
+                            rank > best_match ||
+                                (rank == best_match &&
+                                    call.value.is_narrower_than(best_match_value))
+                        });
+                    if is_better_match {
+                        best_match = Some((rank, &call.value, index));
+                    }
+                }
+            }
+        }
+
+        best_match.map(|(_, _, index)| index)
+    }
+}
+
+/// Formats a summary of [MockedCalls], see [MockedCalls::summary].
+#[cfg(test)]
+pub(crate) struct MockedCallsSummary<'a>(&'a MockedCalls);
+
+#[cfg(test)]
+impl<'a> std::fmt::Display for MockedCallsSummary<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (bucket, call, return_data) in self.0.iter_all() {
+            match call.address {
+                Some(address) => write!(f, "{bucket:?} address={address:?}")?,
+                None => write!(f, "{bucket:?} address=*")?,
+            }
+            if let Some(caller) = call.caller {
+                write!(f, " caller={caller:?}")?;
+            }
+            if let Some(max_depth) = &call.max_depth {
+                write!(f, " max_depth={max_depth:?}")?;
+            }
+            write!(f, " value={:?}", call.value)?;
+            match &call.calldata {
+                CalldataMatch::Any => write!(f, " calldata=*")?,
+                CalldataMatch::Prefix(prefix) => write!(f, " calldata=0x{}", hex::encode(prefix))?,
+            }
+            let kind = if return_data.is_revert() { "revert" } else { "return" };
+            writeln!(f, " => {kind} 0x{}", hex::encode(return_data.data()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl<'a> Debug for MockedCallsSummary<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+impl Extend<(MockCall, MockCallReturn)> for MockedCalls {
+    fn extend<T: IntoIterator<Item = (MockCall, MockCallReturn)>>(&mut self, iter: T) {
+        self.insert_many(iter)
+    }
+}
+
+impl FromIterator<(MockCall, MockCallReturn)> for MockedCalls {
+    fn from_iter<T: IntoIterator<Item = (MockCall, MockCallReturn)>>(iter: T) -> Self {
+        let mut mocks = MockedCalls::default();
+        mocks.insert_many(iter);
+        mocks
+    }
+}
+
+/// Selector for `L2EthToken::balanceOf(uint256)`
+pub const SELECTOR_L2_ETH_BALANCE_OF: &str = "9cc7f708";
+/// Selector for `SystemContext::getBlockNumber()`
+pub const SELECTOR_SYSTEM_CONTEXT_BLOCK_NUMBER: &str = "42cbb15c";
+/// Selector for `SystemContext::getBlockTimestamp()`
+pub const SELECTOR_SYSTEM_CONTEXT_BLOCK_TIMESTAMP: &str = "796b89b9";
+/// Selector for `ContractDeployer::create(bytes32, bytes32, bytes)`
+pub const SELECTOR_CONTRACT_DEPLOYER_CREATE: &str = "9c4d535b";
+/// Selector for `ContractDeployer::create2(bytes32, bytes32, bytes)`
+pub const SELECTOR_CONTRACT_DEPLOYER_CREATE2: &str = "3cda3351";
+
+/// Selector bytes for `L2EthToken::balanceOf(uint256)`
+pub const SELECTOR_L2_ETH_BALANCE_OF_BYTES: [u8; 4] = hex!("9cc7f708");
+/// Selector bytes for `L2EthToken::transferFromTo(address, address, uint256)`
+pub const SELECTOR_L2_ETH_TRANSFER_FROM_TO_BYTES: [u8; 4] = hex!("579952fc");
+/// Selector bytes for `SystemContext::getBlockNumber()`
+pub const SELECTOR_SYSTEM_CONTEXT_BLOCK_NUMBER_BYTES: [u8; 4] = hex!("42cbb15c");
+/// Selector bytes for `SystemContext::getBlockTimestamp()`
+pub const SELECTOR_SYSTEM_CONTEXT_BLOCK_TIMESTAMP_BYTES: [u8; 4] = hex!("796b89b9");
+/// Selector bytes for `SystemContext::getBlockHashEVM(uint256)`
+pub const SELECTOR_SYSTEM_CONTEXT_BLOCK_HASH_EVM_BYTES: [u8; 4] = hex!("80b41246");
+/// Selector bytes for `SystemContext::chainId()`
+pub const SELECTOR_SYSTEM_CONTEXT_CHAIN_ID_BYTES: [u8; 4] = hex!("9a8a0592");
+/// Selector bytes for `SystemContext::origin()`
+pub const SELECTOR_SYSTEM_CONTEXT_ORIGIN_BYTES: [u8; 4] = hex!("938b5f32");
+/// Selector bytes for `SystemContext::baseFee()`
+pub const SELECTOR_SYSTEM_CONTEXT_BASE_FEE_BYTES: [u8; 4] = hex!("6ef25c3a");
+/// Selector bytes for `ContractDeployer::extendedAccountVersion(address)`
+pub const SELECTOR_CONTRACT_DEPLOYER_EXTENDED_ACCOUNT_VERSION_BYTES: [u8; 4] = hex!("bb0fd610");
+/// Selector bytes for `IAccount::executeTransaction(bytes32, bytes32, Transaction)`, as called by
+/// the bootloader
+pub const SELECTOR_BOOTLOADER_EXECUTE_TRANSACTION_BYTES: [u8; 4] = hex!("df9c1589");
+/// Selector bytes for `NonceHolder::getMinNonce(address)`
+pub const SELECTOR_NONCE_HOLDER_MIN_NONCE_BYTES: [u8; 4] = hex!("896909dc");
+/// Selector bytes for `ContractDeployer::create(bytes32, bytes32, bytes)`
+pub const SELECTOR_CONTRACT_DEPLOYER_CREATE_BYTES: [u8; 4] = hex!("9c4d535b");
+/// Selector bytes for `ContractDeployer::create2(bytes32, bytes32, bytes)`
+pub const SELECTOR_CONTRACT_DEPLOYER_CREATE2_BYTES: [u8; 4] = hex!("3cda3351");
+/// Selector bytes for `ContractDeployer::createAccount(bytes32, bytes32, bytes, uint8)`
+pub const SELECTOR_CONTRACT_DEPLOYER_CREATE_ACCOUNT_BYTES: [u8; 4] = hex!("ecf95b8a");
+/// Selector bytes for `ContractDeployer::create2Account(bytes32, bytes32, bytes, uint8)`
+pub const SELECTOR_CONTRACT_DEPLOYER_CREATE2_ACCOUNT_BYTES: [u8; 4] = hex!("5d382700");
+
+/// Errors that may occur when parsing a FarCall.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum FarCallParseError {
+    /// The calldata parameters are not a multiple of 32 bytes.
+    #[error("calldata parameters of {0} bytes are not a multiple of 32 bytes")]
+    UnalignedParams(usize),
+    /// The calldata register does not hold a fat pointer.
+    #[error("calldata register does not hold a pointer")]
+    NoCalldataPointer,
+}
+
+/// The kind of a FarCall, mirroring [FarCallOpcode] without exposing the zkEVM types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FarCallKind {
+    /// A regular call, executing in the context of the called address.
+    Normal,
+    /// A delegatecall, executing in the context of the caller.
+    Delegate,
+    /// A call with an arbitrary `msg.sender`, as made by system contracts.
+    Mimic,
+}
+
+impl From<FarCallOpcode> for FarCallKind {
+    fn from(opcode: FarCallOpcode) -> Self {
+        match opcode {
+            FarCallOpcode::Normal => FarCallKind::Normal,
+            FarCallOpcode::Delegate => FarCallKind::Delegate,
+            FarCallOpcode::Mimic => FarCallKind::Mimic,
+        }
+    }
+}
+
+impl FarCallKind {
+    /// Infers the kind of FarCall that entered the `callee` frame from the `caller` frame, if
+    /// any, as the opcode is not retained once executed. Delegatecalls retain the caller's
+    /// this-address, while mimic calls set a `msg.sender` other than the caller.
+    fn infer(caller: Option<&CallStackEntry>, callee: &CallStackEntry) -> Self {
+        if callee.this_address != callee.code_address {
+            FarCallKind::Delegate
+        } else if caller.is_some_and(|caller| caller.this_address != callee.msg_sender) {
+            FarCallKind::Mimic
+        } else {
+            FarCallKind::Normal
+        }
+    }
+}
+
+/// How the calldata memory is forwarded to the called frame, mirroring
+/// [FarCallForwardPageType] without exposing the zkEVM types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FarCallForwardingMode {
+    /// A new fat pointer to the caller's heap.
+    UseHeap,
+    /// An existing fat pointer, e.g. the caller's calldata or returndata, narrowed as requested.
+    ForwardFatPointer,
+    /// A new fat pointer to the caller's auxiliary heap.
+    UseAuxHeap,
+}
+
+impl From<FarCallForwardPageType> for FarCallForwardingMode {
+    fn from(mode: FarCallForwardPageType) -> Self {
+        match mode {
+            FarCallForwardPageType::UseHeap => FarCallForwardingMode::UseHeap,
+            FarCallForwardPageType::ForwardFatPointer => FarCallForwardingMode::ForwardFatPointer,
+            FarCallForwardPageType::UseAuxHeap => FarCallForwardingMode::UseAuxHeap,
+        }
+    }
+}
+
+/// The kind of a `ContractDeployer` deployment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeploymentKind {
+    /// Deployment via `ContractDeployer::create`.
+    Create,
+    /// Deployment via `ContractDeployer::create2`.
+    Create2,
+}
+
+/// Represents a decoded `ContractDeployer` deployment call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeploymentCall {
+    /// The kind of deployment.
+    pub kind: DeploymentKind,
+    /// The deployment salt. This is ignored by the ContractDeployer for `create`.
+    pub salt: H256,
+    /// The hash of the deployed bytecode.
+    pub bytecode_hash: H256,
+    /// The ABI-encoded constructor arguments.
+    pub constructor_input: Vec<u8>,
+}
+
+/// The account abstraction version an account is deployed with, mirroring
+/// `IContractDeployer.AccountAbstractionVersion`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountAbstractionVersion {
+    /// The account is not an account abstraction.
+    None,
+    /// The account supports the first version of account abstraction.
+    Version1,
+}
+
+impl TryFrom<U256> for AccountAbstractionVersion {
+    type Error = U256;
+
+    fn try_from(value: U256) -> Result<Self, Self::Error> {
+        if value.is_zero() {
+            Ok(Self::None)
+        } else if value == U256::one() {
+            Ok(Self::Version1)
+        } else {
+            Err(value)
+        }
+    }
+}
+
+/// Represents a decoded `ContractDeployer` account deployment call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountCreationCall {
+    /// The kind of deployment, i.e. `createAccount` or `create2Account`.
+    pub kind: DeploymentKind,
+    /// The deployment salt. This is ignored by the ContractDeployer for `createAccount`.
+    pub salt: H256,
+    /// The hash of the account bytecode.
+    pub bytecode_hash: H256,
+    /// The ABI-encoded constructor arguments.
+    pub constructor_input: Vec<u8>,
+    /// The account abstraction version of the deployed account.
+    pub aa_version: AccountAbstractionVersion,
+}
+
+/// Represents a parsed FarCall from the ZK-EVM
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type"))]
+pub enum ParsedFarCall {
+    /// A call to MsgValueSimulator contract used when transferring ETH
+    #[non_exhaustive]
+    ValueCall {
+        /// The called address, i.e. the MsgValueSimulator.
+        to: H160,
+        /// The transferred value.
+        value: U256,
+        /// The calldata forwarded to the recipient.
+        #[cfg_attr(feature = "serde", serde(with = "hex::serde"))]
+        calldata: Vec<u8>,
+        /// The recipient of the transferred value.
+        recipient: H160,
+        /// Whether the recipient is called as a system call.
+        is_system_call: bool,
+        /// The address of the executing context, i.e. the MsgValueSimulator.
+        context_address: H160,
+        /// Whether the call is flagged as a constructor call.
+        is_constructor: bool,
+        /// How the calldata memory is forwarded to the called frame.
+        forwarding_mode: FarCallForwardingMode,
+        /// Whether the calldata was truncated when parsed, see [parse_with_max_calldata_len].
+        is_truncated: bool,
+        /// The packed [FarCallABI] the call was made with.
+        packed_abi: U256,
+        /// The ergs requested to be passed, see [ParsedFarCall::ergs_passed].
+        ergs_passed: u32,
+        /// The kind of FarCall made, see [ParsedFarCall::kind].
+        kind: FarCallKind,
+        /// Whether the called frame is static, see [ParsedFarCall::is_static].
+        is_static: bool,
+    },
+    /// A simple FarCall with calldata.
+    #[non_exhaustive]
+    SimpleCall {
+        /// The called address.
+        to: H160,
+        /// The value of the call context.
+        value: U256,
+        /// The calldata.
+        #[cfg_attr(feature = "serde", serde(with = "hex::serde"))]
+        calldata: Vec<u8>,
+        /// The address of the executing context, which differs from the called address for
+        /// delegatecalls.
+        context_address: H160,
+        /// Whether the call is flagged as a constructor call.
+        is_constructor: bool,
+        /// How the calldata memory is forwarded to the called frame.
+        forwarding_mode: FarCallForwardingMode,
+        /// Whether the calldata was truncated when parsed, see [parse_with_max_calldata_len].
+        is_truncated: bool,
+        /// The packed [FarCallABI] the call was made with.
+        packed_abi: U256,
+        /// The ergs requested to be passed, see [ParsedFarCall::ergs_passed].
+        ergs_passed: u32,
+        /// Whether the call is flagged as a system call.
+        is_system_call: bool,
+        /// The kind of FarCall made, see [ParsedFarCall::kind].
+        kind: FarCallKind,
+        /// Whether the called frame is static, see [ParsedFarCall::is_static].
+        is_static: bool,
+    },
+}
+
+impl ParsedFarCall {
+    /// Retrieves the `to` address for the call, if any
+    pub fn to(&self) -> &H160 {
+        match self {
+            ParsedFarCall::ValueCall { to, .. } => to,
+            ParsedFarCall::SimpleCall { to, .. } => to,
+        }
+    }
+
+    /// Retrieves the `value` for the call
+    pub fn value(&self) -> &U256 {
+        match self {
+            ParsedFarCall::ValueCall { value, .. } => value,
+            ParsedFarCall::SimpleCall { value, .. } => value,
+        }
+    }
+
+    /// Retrieves the address of the executing context, i.e. the storage context of the call.
+    /// For delegatecalls this is the caller's address, while [ParsedFarCall::to] is the address
+    /// of the executed code.
+    pub fn context_address(&self) -> &H160 {
+        match self {
+            ParsedFarCall::ValueCall { context_address, .. } => context_address,
+            ParsedFarCall::SimpleCall { context_address, .. } => context_address,
+        }
+    }
+
+    /// Returns `true` if the call is flagged as a constructor call in its FarCallABI.
+    pub fn is_constructor(&self) -> bool {
+        match self {
+            ParsedFarCall::ValueCall { is_constructor, .. } => *is_constructor,
+            ParsedFarCall::SimpleCall { is_constructor, .. } => *is_constructor,
+        }
+    }
+
+    /// Retrieves how the calldata memory is forwarded to the called frame, as set in its
+    /// FarCallABI.
+    pub fn forwarding_mode(&self) -> FarCallForwardingMode {
+        match self {
+            ParsedFarCall::ValueCall { forwarding_mode, .. } => *forwarding_mode,
+            ParsedFarCall::SimpleCall { forwarding_mode, .. } => *forwarding_mode,
+        }
+    }
+
+    /// Returns `true` if the calldata was truncated when parsed, see
+    /// [parse_with_max_calldata_len].
+    pub fn is_truncated(&self) -> bool {
+        match self {
+            ParsedFarCall::ValueCall { is_truncated, .. } => *is_truncated,
+            ParsedFarCall::SimpleCall { is_truncated, .. } => *is_truncated,
+        }
+    }
+
+    /// Retrieves the packed [FarCallABI] the call was made with.
+    pub fn packed_abi(&self) -> &U256 {
+        match self {
+            ParsedFarCall::ValueCall { packed_abi, .. } => packed_abi,
+            ParsedFarCall::SimpleCall { packed_abi, .. } => packed_abi,
+        }
+    }
+
+    /// Decodes the [FarCallABI] the call was made with, from [ParsedFarCall::packed_abi].
+    pub(crate) fn far_call_abi(&self) -> FarCallABI {
+        FarCallABI::from_u256(*self.packed_abi())
+    }
+
+    /// Retrieves the ergs passed to the called frame, as encoded in bits `192..224` of the
+    /// [FarCallABI]. This is the requested amount, the VM caps it to 63/64 of the caller's
+    /// remaining ergs, so the called frame may receive less.
+    pub fn ergs_passed(&self) -> u32 {
+        match self {
+            ParsedFarCall::ValueCall { ergs_passed, .. } => *ergs_passed,
+            ParsedFarCall::SimpleCall { ergs_passed, .. } => *ergs_passed,
+        }
+    }
+
+    /// Retrieves the kind of FarCall made. Calls parsed via [parse] infer it from the call stack,
+    /// see [FarCallKind::infer].
+    pub fn kind(&self) -> FarCallKind {
+        match self {
+            ParsedFarCall::ValueCall { kind, .. } => *kind,
+            ParsedFarCall::SimpleCall { kind, .. } => *kind,
+        }
+    }
+
+    /// Returns `true` if the called frame is static, i.e. it may not mutate state nor emit
+    /// events. The flag is not part of the [FarCallABI], instead it is set on the called frame
+    /// for static calls and inherited by all its nested calls.
+    pub fn is_static(&self) -> bool {
+        match self {
+            ParsedFarCall::ValueCall { is_static, .. } => *is_static,
+            ParsedFarCall::SimpleCall { is_static, .. } => *is_static,
+        }
+    }
+
+    /// Returns `true` if the call transfers a non-zero value via the MsgValueSimulator.
+    /// Zero-value calls through the MsgValueSimulator are still parsed as
+    /// [ParsedFarCall::ValueCall] with their recipient, which tells them apart from simple calls.
+    pub fn has_value(&self) -> bool {
+        match self {
+            ParsedFarCall::ValueCall { value, .. } => !value.is_zero(),
+            ParsedFarCall::SimpleCall { .. } => false,
+        }
+    }
+
+    /// Retrieves the recipient of the transferred value, if it is a value call.
+    pub fn recipient(&self) -> Option<H160> {
+        match self {
+            ParsedFarCall::ValueCall { recipient, .. } => Some(*recipient),
+            ParsedFarCall::SimpleCall { .. } => None,
+        }
+    }
+
+    /// Returns `true` if the call is flagged as a system call. For value calls this is whether
+    /// the recipient is called as a system call.
+    pub fn is_system_call(&self) -> bool {
+        match self {
+            ParsedFarCall::ValueCall { is_system_call, .. } |
+            ParsedFarCall::SimpleCall { is_system_call, .. } => *is_system_call,
+        }
+    }
+
+    /// Retrieves the selector for the call, or returns an empty string if none.
+    ///
+    /// ```
+    /// use foundry_zksync_core::vm::ParsedFarCall;
+    ///
+    /// // the selector is hex encoded without a `0x` prefix, e.g. for `transfer(address,uint256)`
+    /// fn is_transfer(call: &ParsedFarCall) -> bool {
+    ///     call.selector() == "a9059cbb"
+    /// }
+    /// ```
+    pub fn selector(&self) -> String {
+        self.selector_bytes().map(hex::encode).unwrap_or_default()
+    }
+
+    /// Retrieves the selector for the call, or returns `None` if the calldata is shorter than
+    /// 4 bytes. Prefer this over [ParsedFarCall::selector] for comparisons, as it doesn't
+    /// allocate.
+    pub fn selector_bytes(&self) -> Option<[u8; 4]> {
+        self.calldata().get(..4).map(|selector| selector.try_into().expect("selector is 4 bytes"))
+    }
+
+    /// Retrieves the calldata for the call, if any
+    pub fn calldata(&self) -> &[u8] {
+        match self {
+            ParsedFarCall::ValueCall { calldata, .. } => calldata,
+            ParsedFarCall::SimpleCall { calldata, .. } => calldata,
+        }
+    }
+
+    /// Consumes the call, returning its calldata.
+    pub fn into_calldata(self) -> Vec<u8> {
+        match self {
+            ParsedFarCall::ValueCall { calldata, .. } => calldata,
+            ParsedFarCall::SimpleCall { calldata, .. } => calldata,
+        }
+    }
+
+    /// Retrieves the length of the calldata, as parsed, see [ParsedFarCall::is_truncated].
+    pub fn calldata_len(&self) -> usize {
+        self.calldata().len()
+    }
+
+    /// Returns `true` if the call is a value call without calldata, i.e. a plain transfer to
+    /// the recipient rather than a function call.
+    pub fn is_bare_transfer(&self) -> bool {
+        matches!(self, ParsedFarCall::ValueCall { .. }) && self.calldata().is_empty()
+    }
+
+    /// Retrieves the parameters from calldata, if any.
+    /// The final parameter is right-padded with zeros if the calldata parameters are not a
+    /// multiple of 32 bytes, see [ParsedFarCall::try_params] for a strict variant.
+    pub fn params(&self) -> Vec<[u8; 32]> {
+        let Some(params) = self.calldata().get(4..) else { return Vec::new() };
+
+        params
+            .chunks(32)
+            .map(|chunk| {
+                let mut word = [0u8; 32];
+                word[..chunk.len()].copy_from_slice(chunk);
+                word
+            })
+            .collect_vec()
+    }
+
+    /// Retrieves the parameters from calldata, if any.
+    /// Fails if the calldata parameters are not a multiple of 32 bytes.
+    pub fn try_params(&self) -> Result<Vec<[u8; 32]>, FarCallParseError> {
+        let Some(params) = self.calldata().get(4..) else { return Ok(Vec::new()) };
+        if params.len() % 32 != 0 {
+            return Err(FarCallParseError::UnalignedParams(params.len()))
+        }
+
+        Ok(params
+            .chunks_exact(32)
+            .map(|chunk| chunk.try_into().expect("chunk is 32 bytes"))
+            .collect_vec())
+    }
+
+    /// Retrieves all bytes after the `offset` number of 32byte words
+    pub fn param_bytes_after(&self, offset_words: usize) -> Vec<u8> {
+        let Some(params) = self.calldata().get(4..) else { return Vec::new() };
+        if params.is_empty() || params.len() < 32 * offset_words {
+            return Vec::new()
+        }
+
+        params[32 * offset_words..].to_vec()
+    }
+
+    /// Decodes the call as a `ContractDeployer::create` or `ContractDeployer::create2`
+    /// deployment, if it is one. Deployments with value are matched on the recipient.
+    pub fn as_deployment(&self) -> Option<DeploymentCall> {
+        if !self.is_contract_deployer_call() {
+            return None
+        }
+
+        let kind = match self.selector_bytes()? {
+            SELECTOR_CONTRACT_DEPLOYER_CREATE_BYTES => DeploymentKind::Create,
+            SELECTOR_CONTRACT_DEPLOYER_CREATE2_BYTES => DeploymentKind::Create2,
+            _ => return None,
+        };
+
+        // create(bytes32 salt, bytes32 bytecodeHash, bytes input)
+        let args = self.param_bytes_after(0);
+        let salt = H256::from_slice(args.get(0..32)?);
+        let bytecode_hash = H256::from_slice(args.get(32..64)?);
+        let constructor_input = decode_abi_bytes(&args, 2)?;
+
+        Some(DeploymentCall { kind, salt, bytecode_hash, constructor_input })
+    }
+
+    /// Decodes the call as a `ContractDeployer::createAccount` or
+    /// `ContractDeployer::create2Account` deployment, if it is one. Unlike [Self::as_deployment]
+    /// the arguments carry a trailing `uint8` account abstraction version in the head, so the
+    /// constructor input offset is followed by the version word.
+    pub fn as_account_creation(&self) -> Option<AccountCreationCall> {
+        if !self.is_contract_deployer_call() {
+            return None
+        }
+
+        let kind = match self.selector_bytes()? {
+            SELECTOR_CONTRACT_DEPLOYER_CREATE_ACCOUNT_BYTES => DeploymentKind::Create,
+            SELECTOR_CONTRACT_DEPLOYER_CREATE2_ACCOUNT_BYTES => DeploymentKind::Create2,
+            _ => return None,
+        };
+
+        // createAccount(bytes32 salt, bytes32 bytecodeHash, bytes input, uint8 aaVersion)
+        let args = self.param_bytes_after(0);
+        let salt = H256::from_slice(args.get(0..32)?);
+        let bytecode_hash = H256::from_slice(args.get(32..64)?);
+        let constructor_input = decode_abi_bytes(&args, 2)?;
+        let aa_version = U256::from_big_endian(args.get(96..128)?).try_into().ok()?;
+
+        Some(AccountCreationCall { kind, salt, bytecode_hash, constructor_input, aa_version })
+    }
+
+    /// Whether the call targets the `ContractDeployer`. Deployments with value are matched on
+    /// the recipient.
+    fn is_contract_deployer_call(&self) -> bool {
+        let deployer = match self {
+            ParsedFarCall::ValueCall { recipient, .. } => recipient,
+            ParsedFarCall::SimpleCall { to, .. } => to,
+        };
+        deployer == &CONTRACT_DEPLOYER_ADDRESS
+    }
+
+    /// Decodes the call as a `L2EthToken::balanceOf` query, returning the queried account.
+    /// The account is passed as a `uint256`, of which the lower 20 bytes are the address.
+    pub fn as_eth_balance_query(&self) -> Option<H160> {
+        if self.to() != &L2_ETH_TOKEN_ADDRESS ||
+            self.selector_bytes()? != SELECTOR_L2_ETH_BALANCE_OF_BYTES
+        {
+            return None
+        }
+
+        let account = self.calldata().get(4..36)?;
+        Some(H160::from_slice(&account[12..]))
+    }
+}
+
+/// Encodes the `words` as returndata, each as a big-endian 32 byte word.
+pub fn encode_return(words: &[U256]) -> Vec<u8> {
+    let mut data = vec![0u8; words.len() * 32];
+    for (word, chunk) in words.iter().zip(data.chunks_exact_mut(32)) {
+        word.to_big_endian(chunk);
+    }
+    data
+}
+
+/// Encodes the `data` as ABI-encoded dynamic `bytes` returndata, as the offset and length words
+/// followed by the data right-padded with zeros to a multiple of 32 bytes.
+pub fn encode_return_bytes(data: &[u8]) -> Vec<u8> {
+    let mut encoded = encode_return(&[U256::from(32), U256::from(data.len())]);
+    encoded.extend_from_slice(data);
+    encoded.resize(encoded.len() + (32 - data.len() % 32) % 32, 0);
+    encoded
+}
+
+/// Decodes the dynamic `bytes` argument whose offset is at the `offset_word` of the ABI-encoded
+/// `args`, excluding the selector. Returns `None` if the encoding is out of bounds.
+fn decode_abi_bytes(args: &[u8], offset_word: usize) -> Option<Vec<u8>> {
+    let read_usize = |start: usize| -> Option<usize> {
+        let word = U256::from_big_endian(args.get(start..start.checked_add(32)?)?);
+        if word > U256::from(usize::MAX) {
+            None
+        } else {
+            Some(word.as_usize())
+        }
+    };
+
+    let offset = read_usize(offset_word * 32)?;
+    let length = read_usize(offset)?;
+    let start = offset.checked_add(32)?;
+
+    args.get(start..start.checked_add(length)?).map(|bytes| bytes.to_vec())
+}
+
+impl Debug for ParsedFarCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParsedFarCall::ValueCall {
+                to,
+                value,
+                calldata,
+                recipient,
+                is_system_call,
+                context_address,
+                is_constructor,
+                forwarding_mode,
+                is_truncated,
+                packed_abi,
+                ergs_passed,
+                kind,
+                is_static,
+            } => f
+                .debug_struct("ValueCall")
+                .field("to", to)
+                .field("value", value)
+                .field("calldata", &hex::encode(calldata))
+                .field("recipient", recipient)
+                .field("is_system_call", is_system_call)
+                .field("context_address", context_address)
+                .field("is_constructor", is_constructor)
+                .field("forwarding_mode", forwarding_mode)
+                .field("is_truncated", is_truncated)
+                .field("packed_abi", packed_abi)
+                .field("ergs_passed", ergs_passed)
+                .field("kind", kind)
+                .field("is_static", is_static)
+                .finish(),
+            ParsedFarCall::SimpleCall {
+                to,
+                value,
+                calldata,
+                context_address,
+                is_constructor,
+                forwarding_mode,
+                is_truncated,
+                packed_abi,
+                ergs_passed,
+                is_system_call,
+                kind,
+                is_static,
+            } => f
+                .debug_struct("SimpleCall")
+                .field("to", to)
+                .field("value", value)
+                .field("calldata", &hex::encode(calldata))
+                .field("context_address", context_address)
+                .field("is_constructor", is_constructor)
+                .field("forwarding_mode", forwarding_mode)
+                .field("is_truncated", is_truncated)
+                .field("packed_abi", packed_abi)
+                .field("ergs_passed", ergs_passed)
+                .field("is_system_call", is_system_call)
+                .field("kind", kind)
+                .field("is_static", is_static)
+                .finish(),
+        }
+    }
+}
+
+/// ABI-encodes the revert `reason` as a standard `Error(string)`, as reverted with by `require`,
+/// to be passed as revert data, e.g. to a mocked call.
+pub fn encode_revert_reason(reason: &str) -> Vec<u8> {
+    Revert { reason: reason.to_string() }.abi_encode()
+}
+
+/// ABI-encodes the panic `code` as a standard `Panic(uint256)`, as reverted with by failed
+/// assertions or arithmetic overflows, to be passed as revert data.
+pub fn encode_panic(code: u64) -> Vec<u8> {
+    Panic { code: alloy_primitives::U256::from(code) }.abi_encode()
+}
+
+/// Returns the heap page of the frame with the `base_memory_page`, to which immediate returns
+/// write the return data. Mirrors `CallStackEntry::heap_page_from_base`.
+pub fn return_heap_page(base_memory_page: u32) -> u32 {
+    CallStackEntry::heap_page_from_base(MemoryPage(base_memory_page)).0
+}
+
+/// Reads the bytes addressed by the fat pointer, from its offset.
+fn read_fat_pointer_bytes<H: HistoryMode>(
+    pointer: FatPointer,
+    memory: &SimpleMemory<H>,
+) -> Vec<u8> {
+    memory.read_unaligned_bytes(
+        pointer.memory_page as usize,
+        pointer.start.saturating_add(pointer.offset) as usize,
+        pointer.length.saturating_sub(pointer.offset) as usize,
+    )
+}
+
+/// The default maximum calldata length read by [parse], matching the maximum heap size.
+pub const DEFAULT_MAX_CALLDATA_LEN: usize = 1 << 24;
+
+/// Reads the calldata addressed by the fat pointer, capping its length to `max_len` bytes as
+/// the fat pointer length is not bounded. Returns the calldata and whether it was truncated.
+fn read_calldata_bounded<H: HistoryMode>(
+    pointer: FatPointer,
+    memory: &SimpleMemory<H>,
+    max_len: usize,
+) -> (Vec<u8>, bool) {
+    let mut calldata = Vec::new();
+    let is_truncated = read_calldata_bounded_into(pointer, memory, max_len, &mut calldata);
+    (calldata, is_truncated)
+}
+
+/// Reads the calldata addressed by the fat pointer into the `buffer`, replacing its contents,
+/// see [read_calldata_bounded]. Unlike [SimpleMemory::read_unaligned_bytes], the words are read
+/// without allocating, so a reused buffer only grows for longer calldata. Returns whether the
+/// calldata was truncated.
+fn read_calldata_bounded_into<H: HistoryMode>(
+    pointer: FatPointer,
+    memory: &SimpleMemory<H>,
+    max_len: usize,
+    buffer: &mut Vec<u8>,
+) -> bool {
+    let length = pointer.length as usize;
+    let is_truncated = length > max_len;
+    if is_truncated {
+        tracing::warn!(length, max_len, "truncating far call calldata");
+    }
+
+    buffer.clear();
+    let (start, end) = (pointer.start as usize, pointer.start as usize + length.min(max_len));
+    buffer.reserve(end - start);
+    let mut offset = start;
+    while offset < end {
+        let slot = offset / WORD_SIZE;
+        let mut word = [0u8; WORD_SIZE];
+        memory.read_slot(pointer.memory_page as usize, slot).value.to_big_endian(&mut word);
+        let word_end = (end - slot * WORD_SIZE).min(WORD_SIZE);
+        buffer.extend_from_slice(&word[offset % WORD_SIZE..word_end]);
+        offset = (slot + 1) * WORD_SIZE;
+    }
+    is_truncated
+}
+
+const MSG_VALUE_SIMULATOR_IS_SYSTEM_BIT: u8 = 1;
+
+/// Configuration of the system contract layout used by [parse_with_config].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FarCallParseConfig {
+    /// The address of the MsgValueSimulator system contract.
+    pub msg_value_simulator_address: H160,
+    /// The first register of the system call ABI, holding the MsgValueSimulator value,
+    /// followed by the recipient address and the system call flags.
+    pub system_abi_registers_start: u8,
+    /// The maximum calldata length read, longer calldata is truncated.
+    pub max_calldata_len: usize,
+}
+
+impl Default for FarCallParseConfig {
+    fn default() -> Self {
+        Self {
+            msg_value_simulator_address: MSG_VALUE_SIMULATOR_ADDRESS,
+            system_abi_registers_start: CALL_SYSTEM_ABI_REGISTERS.start,
+            max_calldata_len: DEFAULT_MAX_CALLDATA_LEN,
+        }
+    }
+}
+
+impl FarCallParseConfig {
+    fn value_reg(&self) -> usize {
+        self.system_abi_registers_start as usize
+    }
+
+    fn address_reg(&self) -> usize {
+        self.system_abi_registers_start as usize + 1
+    }
+
+    fn is_system_reg(&self) -> usize {
+        self.system_abi_registers_start as usize + 2
+    }
+}
+
+/// Parses a FarCall into ZKSync's normal calls or MsgValue calls.
+/// Must be called during `after_execution` of a FarCall opcode, when the called frame is active.
+///
+/// For MsgValueSimulator call parsing, see <https://github.com/matter-labs/era-system-contracts/blob/main/contracts/MsgValueSimulator.sol#L25>
+/// For normal call parsing, see <https://github.com/matter-labs/zksync-era/blob/main/core/lib/multivm/src/tracers/call_tracer/vm_latest/mod.rs#L115>
+pub fn parse<H: HistoryMode>(
+    state: &VmLocalStateData<'_>,
+    memory: &SimpleMemory<H>,
+) -> ParsedFarCall {
+    parse_with_config(state, memory, &FarCallParseConfig::default())
+}
+
+/// Parses a FarCall, see [parse], reading at most `max_calldata_len` bytes of calldata.
+/// Longer calldata is truncated, and flagged via [ParsedFarCall::is_truncated].
+pub fn parse_with_max_calldata_len<H: HistoryMode>(
+    state: &VmLocalStateData<'_>,
+    memory: &SimpleMemory<H>,
+    max_calldata_len: usize,
+) -> ParsedFarCall {
+    parse_with_config(state, memory, &FarCallParseConfig { max_calldata_len, ..Default::default() })
+}
+
+/// Parses a FarCall, see [parse], against the system contract layout in `config`.
+pub fn parse_with_config<H: HistoryMode>(
+    state: &VmLocalStateData<'_>,
+    memory: &SimpleMemory<H>,
+    config: &FarCallParseConfig,
+) -> ParsedFarCall {
+    let callstack = &state.vm_local_state.callstack;
+    let current = callstack.get_current_stack();
+    parse_frame(
+        current,
+        &state.vm_local_state.registers,
+        memory,
+        config,
+        FarCallKind::infer(callstack.inner.last(), current),
+    )
+}
+
+/// Parses a FarCall, see [parse], failing with [FarCallParseError::NoCalldataPointer] instead
+/// of defaulting to empty calldata if the calldata register does not hold a pointer.
+pub fn try_parse<H: HistoryMode>(
+    state: &VmLocalStateData<'_>,
+    memory: &SimpleMemory<H>,
+) -> Result<ParsedFarCall, FarCallParseError> {
+    let callstack = &state.vm_local_state.callstack;
+    let current = callstack.get_current_stack();
+    try_parse_frame(
+        current,
+        &state.vm_local_state.registers,
+        memory,
+        &FarCallParseConfig::default(),
+        FarCallKind::infer(callstack.inner.last(), current),
+    )
+}
+
+/// Parses the FarCall that entered the `current` frame, see [try_parse].
+fn try_parse_frame<H: HistoryMode>(
+    current: &CallStackEntry,
+    reg: &[PrimitiveValue],
+    memory: &SimpleMemory<H>,
+    config: &FarCallParseConfig,
+    kind: FarCallKind,
+) -> Result<ParsedFarCall, FarCallParseError> {
+    if !reg[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize].is_pointer {
+        return Err(FarCallParseError::NoCalldataPointer)
+    }
+
+    Ok(parse_frame(current, reg, memory, config, kind))
+}
+
+/// Parses the FarCall of `kind` that entered the `current` frame, given the registers on entry.
+/// Calldata defaults to empty if the calldata register does not hold a pointer.
+fn parse_frame<H: HistoryMode>(
+    current: &CallStackEntry,
+    reg: &[PrimitiveValue],
+    memory: &SimpleMemory<H>,
+    config: &FarCallParseConfig,
+    kind: FarCallKind,
+) -> ParsedFarCall {
+    parse_frame_with_buffer(current, reg, memory, config, kind, None)
+}
+
+/// Parses the FarCall like [parse_frame], reading the calldata into the `buffer` if provided.
+/// The buffer is moved into the parsed calldata, leaving it empty, so that parsing many FarCalls
+/// reuses its capacity if the calldata is handed back once the call is no longer needed, see
+/// [ParsedFarCall::into_calldata].
+fn parse_frame_with_buffer<H: HistoryMode>(
+    current: &CallStackEntry,
+    reg: &[PrimitiveValue],
+    memory: &SimpleMemory<H>,
+    config: &FarCallParseConfig,
+    kind: FarCallKind,
+    buffer: Option<&mut Vec<u8>>,
+) -> ParsedFarCall {
+    let value = U256::from(current.context_u128_value);
+
+    let packed_abi = reg[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize];
+    let far_call_abi = FarCallABI::from_u256(packed_abi.value);
+    let pointer = far_call_abi.memory_quasi_fat_pointer;
+    // calldata is only addressable via a fat pointer, which a malformed call may not pass
+    let (calldata, is_truncated) = if packed_abi.is_pointer {
+        match buffer {
+            Some(buffer) => {
+                let is_truncated =
+                    read_calldata_bounded_into(pointer, memory, config.max_calldata_len, buffer);
+                (std::mem::take(buffer), is_truncated)
+            }
+            None => read_calldata_bounded(pointer, memory, config.max_calldata_len),
+        }
+    } else {
+        tracing::warn!("far call calldata register is not a pointer, defaulting to empty calldata");
+        (Vec::new(), false)
+    };
+    if current.code_address == config.msg_value_simulator_address {
+        let value = U256::from(reg[config.value_reg()].value.low_u128());
+        let address = reg[config.address_reg()].value.to_h256();
+        let address = address.to_h160();
+        let is_system_call =
+            reg[config.is_system_reg()].value.bit(MSG_VALUE_SIMULATOR_IS_SYSTEM_BIT as usize);
+
+        ParsedFarCall::ValueCall {
+            to: current.code_address,
+            value,
+            calldata,
+            recipient: address,
+            is_system_call,
+            context_address: current.this_address,
+            is_constructor: far_call_abi.constructor_call,
+            forwarding_mode: far_call_abi.forwarding_mode.into(),
+            is_truncated,
+            packed_abi: packed_abi.value,
+            ergs_passed: far_call_abi.ergs_passed,
+            kind,
+            is_static: current.is_static,
+        }
+    } else {
+        ParsedFarCall::SimpleCall {
+            to: current.code_address,
+            value,
+            calldata,
+            context_address: current.this_address,
+            is_constructor: far_call_abi.constructor_call,
+            forwarding_mode: far_call_abi.forwarding_mode.into(),
+            is_truncated,
+            packed_abi: packed_abi.value,
+            ergs_passed: far_call_abi.ergs_passed,
+            is_system_call: far_call_abi.to_system,
+            kind,
+            is_static: current.is_static,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::RefCell,
+        rc::Rc,
+        sync::{Arc, Mutex},
+    };
+
+    use alloy_dyn_abi::DynSolValue;
+    use multivm::vm_latest::HistoryDisabled;
+    use zksync_state::{InMemoryStorage, ReadStorage};
+    use zksync_types::{AccountTreeId, StorageKey};
+
+    use super::*;
+    use crate::{convert::ConvertAddress, vm::storage_view::StorageView};
+
+    // only the serde round-trip tests use it
+    #[cfg(not(feature = "serde"))]
+    use serde_json as _;
+
+    fn far_call_handler(call: FarCallOpcode) -> FarCallHandler {
+        let mut before = CallStackEntry::empty_context();
+        before.pc = 10;
+        before.exception_handler_location = 20;
+        before.base_memory_page = MemoryPage(8);
+        let mut after = CallStackEntry::empty_context();
+        after.exception_handler_location = 42;
+        after.base_memory_page = MemoryPage(16);
+
+        FarCallHandler {
+            before_far_call_stack: Some(before),
+            after_far_call_stack: Some(after),
+            current_far_call: Some(call),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_mocked_calls_revert() {
+        let address = H160::repeat_byte(0x1);
+        let revert_data = hex::decode("deadbeef").unwrap();
+        let mut mocks = MockedCalls::default();
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                value: ValueMatch::Any,
+                calldata: CalldataMatch::Prefix(vec![0xaa, 0xbb]),
+                ..Default::default()
+            },
+            MockCallReturn::Revert(revert_data.clone()),
+        );
+
+        let matched = mocks.get_matching_return_data(address, &[0xaa, 0xbb, 0xcc], U256::zero());
+        assert_eq!(matched, Some(MockCallReturn::Revert(revert_data)));
+    }
+
+    #[test]
+    fn test_peek_immediate_return() {
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        assert!(handler.peek_immediate_return().is_none());
+
+        let before = handler.before_far_call_stack.as_mut().unwrap();
+        before.base_memory_page = MemoryPage(8);
+        before.code_page = MemoryPage(9);
+        handler.set_immediate_return(vec![0xaa; 33]).unwrap();
+
+        let immediate_return =
+            handler.peek_immediate_return().expect("immediate return must be set");
+        assert_eq!(immediate_return.next_frame.pc, 11);
+        assert_eq!(immediate_return.next_frame.base_memory_page, 8);
+        assert_eq!(immediate_return.next_frame.code_page, 9);
+        assert_eq!(
+            immediate_return.return_memory_page,
+            CallStackEntry::heap_page_from_base(MemoryPage(16)).0
+        );
+        assert_eq!(immediate_return.return_data_chunks, 2);
+
+        // peeking does not consume the immediate return
+        assert!(handler.peek_immediate_return().is_some());
+        assert!(handler.immediate_return.is_some());
+    }
+
+    #[test]
+    fn test_immediate_mock_return_revert_uses_exception_handler() {
+        let revert_data = hex::decode("deadbeef").unwrap();
+        for call in [FarCallOpcode::Normal, FarCallOpcode::Delegate, FarCallOpcode::Mimic] {
+            let mut handler = far_call_handler(call);
+            handler
+                .set_immediate_mock_return(
+                    MockCallReturn::Revert(revert_data.clone()),
+                    Default::default(),
+                )
+                .unwrap();
+
+            let immediate_return = handler.immediate_return.expect("immediate return must be set");
+            assert!(immediate_return.is_revert);
+            assert_eq!(immediate_return.return_data, revert_data);
+            assert_eq!(immediate_return.next_frame.pc, 42);
+        }
+
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler
+            .set_immediate_mock_return(MockCallReturn::Return(revert_data), Default::default())
+            .unwrap();
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        assert!(!immediate_return.is_revert);
+        assert_eq!(immediate_return.next_frame.pc, 11);
+    }
+
+    #[test]
+    fn test_immediate_revert_for_value_call() {
+        let revert_data = hex::decode("deadbeef").unwrap();
+        let mut handler = far_call_handler(FarCallOpcode::Mimic);
+        handler.before_far_call_stack.as_mut().unwrap().base_memory_page = MemoryPage(1);
+        handler.after_far_call_stack.as_mut().unwrap().base_memory_page = MemoryPage(2);
+        handler.set_immediate_revert(revert_data.clone()).unwrap();
+
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        assert!(immediate_return.is_revert);
+        assert_eq!(immediate_return.return_data, revert_data);
+        assert_eq!(
+            immediate_return.return_memory_page,
+            CallStackEntry::heap_page_from_base(MemoryPage(2)).0
+        );
+        assert_eq!(immediate_return.next_frame.base_memory_page, 1);
+        assert_eq!(immediate_return.next_frame.pc, 42);
+    }
+
+    #[test]
+    fn test_immediate_return_for_delegate_call_in_local_frame() {
+        let caller = H160::repeat_byte(0x1);
+        let mut handler = far_call_handler(FarCallOpcode::Delegate);
+        let before = handler.before_far_call_stack.as_mut().unwrap();
+        before.is_local_frame = true;
+        before.this_address = caller;
+        handler.set_immediate_return(vec![0xaa]).unwrap();
+
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        let mut current = handler.after_far_call_stack.unwrap();
+        current.this_address = H160::repeat_byte(0x2);
+        immediate_return.restore_frame(&mut current);
+        assert!(current.is_local_frame);
+        assert_eq!(current.pc, 11);
+        assert_eq!(current.this_address, caller);
+
+        // normal calls keep returning to a non-local frame
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.before_far_call_stack.as_mut().unwrap().is_local_frame = true;
+        handler.set_immediate_return(vec![0xaa]).unwrap();
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        assert!(!immediate_return.next_frame.is_local_frame);
+    }
+
+    #[test]
+    fn test_immediate_return_without_active_far_call() {
+        let mut handler = FarCallHandler::default();
+        assert_eq!(
+            handler.set_immediate_return(vec![1]),
+            Err(FarCallHandlerError::NoActiveFarCall)
+        );
+        assert_eq!(
+            handler.set_immediate_revert(vec![1]),
+            Err(FarCallHandlerError::NoActiveFarCall)
+        );
+        assert!(handler.immediate_return.is_none());
+
+        handler.current_far_call = Some(FarCallOpcode::Normal);
+        assert_eq!(
+            handler.set_immediate_return(vec![1]),
+            Err(FarCallHandlerError::NoActiveFarCall)
+        );
+        assert!(handler.immediate_return.is_none());
+    }
+
+    #[test]
+    fn test_immediate_return_gas_left() {
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler
+            .set_immediate_mock_return(
+                MockCallReturn::Return(vec![]),
+                ImmediateReturnOptions { gas_left: Some(1000), ..Default::default() },
+            )
+            .unwrap();
+
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        let mut frame = CallStackEntry::empty_context();
+        frame.ergs_remaining = 5000;
+        immediate_return.restore_frame(&mut frame);
+        assert_eq!(frame.ergs_remaining, 1000);
+        assert_eq!(frame.pc, 11);
+
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.set_immediate_return(vec![]).unwrap();
+
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        let mut frame = CallStackEntry::empty_context();
+        frame.ergs_remaining = 5000;
+        immediate_return.restore_frame(&mut frame);
+        assert_eq!(frame.ergs_remaining, 5000);
+    }
+
+    #[test]
+    fn test_frame_restore() {
+        let mut frame = CallStackEntry::empty_context();
+        frame.pc = 10;
+        frame.code_page = MemoryPage(2);
+        frame.base_memory_page = MemoryPage(3);
+        frame.sp = 4;
+        frame.exception_handler_location = 5;
+        frame.this_address = H160::repeat_byte(0x6);
+        frame.is_local_frame = true;
+        frame.context_u128_value = 7;
+        let snapshot = FrameRestore::snapshot(&frame);
+
+        let mut current = CallStackEntry::empty_context();
+        current.pc = 100;
+        current.code_page = MemoryPage(20);
+        current.this_address = H160::repeat_byte(0x60);
+        current.ergs_remaining = 1000;
+        snapshot.apply(&mut current);
+
+        assert_eq!(FrameRestore::snapshot(&current), snapshot);
+        assert_eq!(current.pc, 10);
+        assert_eq!(current.code_page, MemoryPage(2));
+        assert_eq!(current.base_memory_page, MemoryPage(3));
+        assert_eq!(current.sp, 4);
+        assert_eq!(current.exception_handler_location, 5);
+        assert_eq!(current.this_address, H160::repeat_byte(0x6));
+        assert!(current.is_local_frame);
+        assert_eq!(current.context_u128_value, 7);
+        // fields outside the snapshot are left untouched
+        assert_eq!(current.ergs_remaining, 1000);
+    }
+
+    #[test]
+    fn test_immediate_return_context_value() {
+        let mut handler = far_call_handler(FarCallOpcode::Mimic);
+        handler
+            .set_immediate_mock_return(
+                MockCallReturn::Return(vec![]),
+                ImmediateReturnOptions { context_u128_value: Some(100), ..Default::default() },
+            )
+            .unwrap();
+
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        let mut frame = CallStackEntry::empty_context();
+        immediate_return.restore_frame(&mut frame);
+        assert_eq!(frame.context_u128_value, 100);
+
+        let mut handler = far_call_handler(FarCallOpcode::Mimic);
+        handler.set_immediate_return(vec![]).unwrap();
+
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        let mut frame = CallStackEntry::empty_context();
+        frame.context_u128_value = 100;
+        immediate_return.restore_frame(&mut frame);
+        assert_eq!(frame.context_u128_value, 0);
+    }
+
+    #[test]
+    fn test_immediate_return_logs() {
+        let emitter = H160::repeat_byte(0x1);
+        let log = MockedLog {
+            topics: vec![H256::repeat_byte(0xee), H256::from(H160::repeat_byte(0x2))],
+            data: vec![0x64],
+        };
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.after_far_call_stack.as_mut().unwrap().this_address = emitter;
+        handler
+            .set_immediate_mock_return(
+                MockCallReturn::Return(vec![0x1]),
+                ImmediateReturnOptions { logs: vec![log.clone()], ..Default::default() },
+            )
+            .unwrap();
+
+        let immediate_return =
+            handler.immediate_return.take().expect("immediate return must be set");
+        let events = immediate_return.events();
+        assert_eq!(
+            events,
+            vec![VmEvent {
+                location: Default::default(),
+                address: emitter,
+                indexed_topics: log.topics.clone(),
+                value: log.data.clone(),
+            }]
+        );
+        handler.push_mocked_events(CallDepth::current(), events);
+        assert_eq!(handler.take_mocked_events().len(), 1);
+        assert!(handler.take_mocked_events().is_empty());
+
+        // reverted calls emit no logs
+        handler
+            .set_immediate_mock_return(
+                MockCallReturn::Revert(vec![]),
+                ImmediateReturnOptions { logs: vec![log], ..Default::default() },
+            )
+            .unwrap();
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        assert!(immediate_return.events().is_empty());
+    }
+
+    #[test]
+    fn test_settle_mocked_events() {
+        let event = |value: u8| VmEvent {
+            location: Default::default(),
+            address: H160::repeat_byte(0x1),
+            indexed_topics: vec![],
+            value: vec![value],
+        };
+        let values = |handler: &mut FarCallHandler| {
+            handler.take_mocked_events().into_iter().map(|(_, event)| event.value[0]).collect_vec()
+        };
+        let mut handler = FarCallHandler::default();
+
+        // a successful frame's events are attributed to its caller, and survive its siblings
+        handler.push_mocked_events(CallDepth::new(3), vec![event(1)]);
+        handler.settle_mocked_events(&CallDepth::new(3), FarCallOutcome::Success);
+        handler.settle_mocked_events(&CallDepth::new(2), FarCallOutcome::Success);
+        handler.push_mocked_events(CallDepth::new(2), vec![event(2)]);
+        handler.settle_mocked_events(&CallDepth::new(2), FarCallOutcome::Revert);
+        assert_eq!(values(&mut handler), vec![1]);
+
+        // a reverted frame discards the events of its completed subframes
+        handler.push_mocked_events(CallDepth::new(1), vec![event(1)]);
+        handler.push_mocked_events(CallDepth::new(3), vec![event(2)]);
+        handler.settle_mocked_events(&CallDepth::new(3), FarCallOutcome::Success);
+        handler.settle_mocked_events(&CallDepth::new(2), FarCallOutcome::Panic);
+        assert_eq!(values(&mut handler), vec![1]);
+    }
+
+    #[test]
+    fn test_mocked_events_position() {
+        let event = |value: u8| VmEvent {
+            location: Default::default(),
+            address: H160::repeat_byte(0x1),
+            indexed_topics: vec![],
+            value: vec![value],
+        };
+        let mut handler = FarCallHandler::default();
+
+        // mocked events are positioned among the recorded events that were not rolled back
+        handler.mocked_events.push((CallDepth::new(2), EmittedEvent::Recorded));
+        handler.push_mocked_events(CallDepth::new(2), vec![event(1)]);
+        handler.mocked_events.push((CallDepth::new(3), EmittedEvent::Recorded));
+        handler.settle_mocked_events(&CallDepth::new(3), FarCallOutcome::Revert);
+        handler.mocked_events.push((CallDepth::new(3), EmittedEvent::Recorded));
+        handler.settle_mocked_events(&CallDepth::new(3), FarCallOutcome::Success);
+        handler.push_mocked_events(CallDepth::new(2), vec![event(2), event(3)]);
+        handler.mocked_events.push((CallDepth::new(2), EmittedEvent::Recorded));
+
+        assert_eq!(handler.take_mocked_events(), vec![(1, event(1)), (2, event(2)), (2, event(3))]);
+        assert!(handler.take_mocked_events().is_empty());
+    }
+
+    #[test]
+    fn test_mock_call_logs() {
+        let target = H160::repeat_byte(0x1);
+        let log = MockedLog { topics: vec![H256::repeat_byte(0xee)], data: vec![0x64] };
+        let call = ParsedFarCall::SimpleCall {
+            to: target,
+            value: U256::zero(),
+            calldata: vec![0xaa; 4],
+            context_address: target,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.after_far_call_stack.as_mut().unwrap().this_address = target;
+        handler.mock_call(
+            MockCall::new(target),
+            MockCallReturn::Return(vec![0x1]),
+            ImmediateReturnOptions { logs: vec![log.clone()], ..Default::default() },
+        );
+        handler.on_parsed_far_call(call.clone(), CallDepth::next(), FarCallOpcode::Normal);
+        let immediate_return =
+            handler.immediate_return.take().expect("immediate return must be set");
+        assert_eq!(immediate_return.logs, vec![log]);
+        assert_eq!(immediate_return.emitter, target);
+
+        // mocking the call again without logs drops them
+        handler.mock_call(
+            MockCall::new(target),
+            MockCallReturn::Return(vec![0x1]),
+            Default::default(),
+        );
+        handler.on_parsed_far_call(call.clone(), CallDepth::next(), FarCallOpcode::Normal);
+        let immediate_return =
+            handler.immediate_return.take().expect("immediate return must be set");
+        assert!(immediate_return.logs.is_empty());
+
+        // mocking the call to execute overrides the returndata once the called frame returns
+        handler.far_call_depth = CallDepth::new(2);
+        handler.mock_call(
+            MockCall::new(target),
+            MockCallReturn::Return(vec![0x1]),
+            ImmediateReturnOptions {
+                mode: ImmediateReturnMode::OverrideOnReturn,
+                ..Default::default()
+            },
+        );
+        handler.on_parsed_far_call(call, CallDepth::next(), FarCallOpcode::Normal);
+        assert!(handler.immediate_return.is_none());
+        assert!(handler.prepare_return_override(&CallDepth::new(2)).is_some());
+    }
+
+    #[test]
+    fn test_immediate_return_retains_opcode() {
+        let mut handler = far_call_handler(FarCallOpcode::Mimic);
+        if let Some(after) = handler.after_far_call_stack.as_mut() {
+            after.base_memory_page = MemoryPage(7);
+        }
+        handler.set_immediate_return(vec![]).unwrap();
+
+        // an interleaved Normal call doesn't affect the already set immediate return
+        let mut before = CallStackEntry::empty_context();
+        before.pc = 100;
+        before.base_memory_page = MemoryPage(3);
+        handler.before_far_call_stack.replace(before);
+        handler.after_far_call_stack.take();
+        handler.current_far_call.replace(FarCallOpcode::Normal);
+
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        assert_eq!(immediate_return.opcode, FarCallOpcode::Mimic);
+        assert_eq!(
+            immediate_return.return_memory_page,
+            CallStackEntry::heap_page_from_base(MemoryPage(7)).0
+        );
+        assert_eq!(immediate_return.next_frame.pc, 11);
+    }
+
+    #[test]
+    fn test_immediate_return_zero_base_memory_page() {
+        for call in [FarCallOpcode::Normal, FarCallOpcode::Delegate, FarCallOpcode::Mimic] {
+            let mut handler = far_call_handler(call);
+            handler.after_far_call_stack.as_mut().unwrap().base_memory_page = MemoryPage(0);
+
+            let zero_heap_page = CallStackEntry::heap_page_from_base(MemoryPage(0)).0;
+            assert_eq!(
+                handler.set_immediate_return(vec![0xaa]),
+                Err(FarCallHandlerError::InvalidReturnMemoryPage(zero_heap_page))
+            );
+            assert!(handler.immediate_return.is_none());
+        }
+
+        // the return page is resolved from the callee frame, regardless of the caller's
+        for call in [FarCallOpcode::Normal, FarCallOpcode::Delegate, FarCallOpcode::Mimic] {
+            let mut handler = far_call_handler(call);
+            handler.before_far_call_stack.as_mut().unwrap().base_memory_page = MemoryPage(0);
+            handler.set_immediate_return(vec![0xaa]).unwrap();
+            assert_eq!(
+                handler.immediate_return.unwrap().return_memory_page,
+                CallStackEntry::heap_page_from_base(MemoryPage(16)).0
+            );
+        }
+
+        // which is unknown during `before_execution`
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.after_far_call_stack.take();
+        assert_eq!(
+            handler.set_immediate_return(vec![0xaa]),
+            Err(FarCallHandlerError::NoActiveFarCall)
+        );
+    }
+
+    #[test]
+    fn test_return_heap_page() {
+        for base in [0, 1, 8, 16, 1024, u32::MAX - 8] {
+            assert_eq!(
+                return_heap_page(base),
+                CallStackEntry::heap_page_from_base(MemoryPage(base)).0,
+                "{base}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_immediate_return_oversized_return_data() {
+        let start = MAX_RETURN_DATA_LEN - 63;
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        let options =
+            || ImmediateReturnOptions { return_data_start: Some(start), ..Default::default() };
+
+        // the return data must fit in the return memory page past its start
+        assert_eq!(
+            handler.set_immediate_mock_return(MockCallReturn::Return(vec![0xaa; 64]), options()),
+            Err(FarCallHandlerError::ReturnDataTooLarge { length: 64, max: 63 })
+        );
+        assert!(handler.immediate_return.is_none());
+
+        handler
+            .set_immediate_mock_return(MockCallReturn::Return(vec![0xaa; 63]), options())
+            .unwrap();
+        let return_fat_ptr = handler.immediate_return.unwrap().return_fat_pointer();
+        assert_eq!(return_fat_ptr.start, start);
+        assert_eq!(return_fat_ptr.length, 63);
+    }
+
+    #[test]
+    fn test_immediate_return_populates_return_page() {
+        let return_data = [[0x1; 32], [0x2; 32], [0x3; 32]].concat();
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.after_far_call_stack.as_mut().unwrap().base_memory_page = MemoryPage(24);
+        handler.set_immediate_return(return_data.clone()).unwrap();
+
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        assert_eq!(immediate_return.return_data_chunks, 3);
+        let return_fat_ptr = immediate_return.return_fat_pointer();
+        assert_eq!(
+            return_fat_ptr.memory_page,
+            CallStackEntry::heap_page_from_base(MemoryPage(24)).0
+        );
+        assert_eq!(return_fat_ptr.length, 96);
+
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        memory.populate_page(
+            return_fat_ptr.memory_page as usize,
+            immediate_return.return_data_words(),
+            Timestamp(0),
+        );
+        assert_eq!(read_fat_pointer_bytes(return_fat_ptr, &memory), return_data);
+    }
+
+    #[test]
+    fn test_immediate_return_two_words() {
+        let return_data = [[0x1; WORD_SIZE], [0x2; WORD_SIZE]].concat();
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler
+            .set_immediate_mock_return(
+                MockCallReturn::Return(return_data.clone()),
+                ImmediateReturnOptions { return_data_start: Some(64), ..Default::default() },
+            )
+            .unwrap();
+
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        assert_eq!(immediate_return.return_data_chunks, 2);
+        let return_fat_ptr = immediate_return.return_fat_pointer();
+        assert_eq!(return_fat_ptr.start, 64);
+        assert_eq!(return_fat_ptr.length, 64);
+        assert_eq!(
+            immediate_return.return_data_words(),
+            vec![
+                (2, U256::from_big_endian(&[0x1; WORD_SIZE])),
+                (3, U256::from_big_endian(&[0x2; WORD_SIZE]))
+            ]
+        );
+
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        memory.populate_page(
+            return_fat_ptr.memory_page as usize,
+            immediate_return.return_data_words(),
+            Timestamp(0),
+        );
+        assert_eq!(read_fat_pointer_bytes(return_fat_ptr, &memory), return_data);
+    }
+
+    #[test]
+    fn test_immediate_return_unaligned_return_data() {
+        let return_data = (0..36).collect::<Vec<u8>>();
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.set_immediate_return(return_data.clone()).unwrap();
+
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        assert_eq!(immediate_return.return_data_chunks, 2);
+        let return_fat_ptr = immediate_return.return_fat_pointer();
+        assert_eq!(return_fat_ptr.length, 36);
+
+        let words = immediate_return.return_data_words();
+        let mut final_word = [0u8; 32];
+        final_word[..4].copy_from_slice(&return_data[32..]);
+        assert_eq!(words[1], (1, U256::from_big_endian(&final_word)));
+
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        memory.populate_page(return_fat_ptr.memory_page as usize, words, Timestamp(0));
+        assert_eq!(read_fat_pointer_bytes(return_fat_ptr, &memory), return_data);
+    }
+
+    #[test]
+    fn test_immediate_return_empty_return_data() {
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.set_immediate_return(vec![]).unwrap();
+
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        assert_eq!(immediate_return.return_data_chunks, 0);
+        assert!(immediate_return.return_data_words().is_empty());
+
+        // the returndata of a previous call is still addressed by the register
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        let stale_ptr = FatPointer { memory_page: 9, offset: 0, start: 0, length: 32 };
+        memory.populate_page(9, vec![(0, U256::from_big_endian(&[0xff; 32]))], Timestamp(0));
+        let mut registers = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+        registers[RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER as usize] =
+            PrimitiveValue { value: stale_ptr.to_u256(), is_pointer: true };
+
+        immediate_return.write_return_data(&mut registers, &mut memory, Timestamp(1));
+        let reg = registers[RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER as usize];
+        assert!(reg.is_pointer);
+        let return_fat_ptr = FatPointer::from_u256(reg.value);
+        assert_eq!(return_fat_ptr.length, 0);
+        assert_eq!(return_fat_ptr.memory_page, immediate_return.return_memory_page);
+        assert!(read_fat_pointer_bytes(return_fat_ptr, &memory).is_empty());
+    }
+
+    #[test]
+    fn test_immediate_return_clears_registers() {
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.set_immediate_return(vec![0xaa; 32]).unwrap();
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+
+        // the registers as set up for the called frame, with system call ABI arguments
+        let mut registers = [PrimitiveValue { value: U256::from(7), is_pointer: false }; 16];
+        registers[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize] = PrimitiveValue {
+            value: FatPointer { memory_page: 5, offset: 0, start: 0, length: 4 }.to_u256(),
+            is_pointer: true,
+        };
+        registers[3].is_pointer = true;
+
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        let written = immediate_return.write_return_data(&mut registers, &mut memory, Timestamp(0));
+        assert_eq!(written, 32);
+
+        // a real far return only passes the returndata pointer
+        let mut expected = [PrimitiveValue::empty(); 16];
+        expected[RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER as usize] = PrimitiveValue {
+            value: immediate_return.return_fat_pointer().to_u256(),
+            is_pointer: true,
+        };
+        assert_eq!(registers, expected);
+    }
+
+    #[test]
+    fn test_immediate_return_override_on_return() {
+        let mocked = vec![0xaa; 36];
+        let options = || ImmediateReturnOptions {
+            mode: ImmediateReturnMode::OverrideOnReturn,
+            ..Default::default()
+        };
+
+        // the override is keyed by the called frame, which is unknown during `before_execution`
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.after_far_call_stack = None;
+        assert_eq!(
+            handler.set_immediate_mock_return(MockCallReturn::Return(mocked.clone()), options()),
+            Err(FarCallHandlerError::NoActiveFarCall)
+        );
+
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.far_call_depth = CallDepth::new(2);
+        handler
+            .set_immediate_mock_return(MockCallReturn::Return(mocked.clone()), options())
+            .unwrap();
+        // the called frame is executed rather than returning early
+        assert!(handler.peek_immediate_return().is_none());
+
+        // the called frame writes to its heap and returns its own returndata
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        let callee_heap = return_heap_page(16);
+        memory.populate_page(callee_heap as usize, vec![(0, U256::from(0x42))], Timestamp(0));
+        let returned = FatPointer { memory_page: callee_heap, offset: 0, start: 0, length: 32 };
+        let mut registers = [PrimitiveValue::empty(); 16];
+        registers[RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER as usize] =
+            PrimitiveValue { value: returned.to_u256(), is_pointer: true };
+
+        // a nested frame returning does not apply the override
+        assert!(handler.prepare_return_override(&CallDepth::new(3)).is_none());
+        assert_eq!(handler.apply_return_override(&mut registers, &mut memory, Timestamp(1)), None);
+
+        assert!(handler.prepare_return_override(&CallDepth::new(2)).is_some());
+        assert_eq!(
+            handler.apply_return_override(&mut registers, &mut memory, Timestamp(1)),
+            Some(mocked.len())
+        );
+        let return_fat_ptr = FatPointer::from_u256(
+            registers[RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER as usize].value,
+        );
+        assert_eq!(read_fat_pointer_bytes(return_fat_ptr, &memory), mocked);
+        // the called frame's writes persist
+        assert_eq!(
+            read_fat_pointer_bytes(returned, &memory),
+            U256::from(0x42).to_h256().as_bytes().to_vec()
+        );
+        assert!(handler.prepare_return_override(&CallDepth::new(2)).is_none());
+    }
+
+    #[test]
+    fn test_call_actions_skip_value_call_hop() {
+        let prank = Address::repeat_byte(0x1);
+        let origin = Address::repeat_byte(0x3);
+        let recipient = H160::repeat_byte(0x2);
+        let config = FarCallParseConfig::default();
+        let mut reg = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+        reg[config.value_reg()].value = U256::from(100);
+        reg[config.address_reg()].value = U256::from_big_endian(recipient.as_bytes());
+        let frame = |code_address: H160| {
+            let mut frame = CallStackEntry::empty_context();
+            frame.code_address = code_address;
+            frame.this_address = code_address;
+            frame
+        };
+
+        let mut handler = FarCallHandler::default();
+        handler.set_action(CallDepth::next(), CallAction::SetMessageSender(prank));
+        handler.set_action(CallDepth::next(), CallAction::SetTxOrigin(origin));
+        handler.set_action(CallDepth::next(), CallAction::SetCallValue(U256::from(7)));
+        handler.set_action(CallDepth::new(2), CallAction::SetThisAddress(prank));
+
+        // the MsgValueSimulator frame is entered without the overrides
+        handler.on_far_call_entered(
+            FarCallOpcode::Normal,
+            &frame(MSG_VALUE_SIMULATOR_ADDRESS),
+            CallDepth::new(2),
+            &reg,
+        );
+        assert!(handler.call_actions.take_immediate().is_empty());
+        assert_eq!(handler.pending_actions().count(), 4);
+
+        // as is the `L2EthToken.transferFromTo` frame transferring the value
+        handler.on_far_call_entered(
+            FarCallOpcode::Normal,
+            &frame(L2_ETH_TOKEN_ADDRESS),
+            CallDepth::new(3),
+            &reg,
+        );
+        assert!(handler.call_actions.take_immediate().is_empty());
+        assert_eq!(handler.pending_actions().count(), 4);
+
+        // the recipient frame, entered via a mimic call, sees all the overrides
+        handler.on_far_call_entered(
+            FarCallOpcode::Mimic,
+            &frame(recipient),
+            CallDepth::new(3),
+            &reg,
+        );
+        assert_eq!(
+            handler.call_actions.take_immediate(),
+            vec![
+                CallAction::SetMessageSender(prank),
+                CallAction::SetTxOrigin(origin),
+                CallAction::SetCallValue(U256::from(7)),
+            ]
+        );
+
+        // and deeper actions count the value call as a single hop
+        handler.on_far_call_entered(
+            FarCallOpcode::Normal,
+            &frame(recipient),
+            CallDepth::new(4),
+            &reg,
+        );
+        assert_eq!(handler.call_actions.take_immediate(), vec![CallAction::SetThisAddress(prank)]);
+        assert!(handler.call_actions.is_empty());
+        assert!(handler.value_call_hop.is_none());
+    }
+
+    #[test]
+    fn test_call_actions_value_call_hop_ends_with_simulator() {
+        let prank = Address::repeat_byte(0x1);
+        let mut reg = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+        reg[FarCallParseConfig::default().address_reg()].value = U256::from(0x2);
+        let mut simulator = CallStackEntry::empty_context();
+        simulator.code_address = MSG_VALUE_SIMULATOR_ADDRESS;
+
+        let mut handler = FarCallHandler::default();
+        handler.set_action(CallDepth::next(), CallAction::SetMessageSender(prank));
+        handler.on_far_call_entered(FarCallOpcode::Normal, &simulator, CallDepth::new(2), &reg);
+        assert!(handler.value_call_hop.is_some());
+
+        // the value transfer reverted, so the recipient is never entered
+        handler.on_far_call_exit(CallDepth::new(2));
+        assert!(handler.value_call_hop.is_none());
+    }
+
+    #[test]
+    fn test_immediate_return_bytes_written() {
+        for return_data in [vec![], vec![0xaa; 4], vec![0xbb; 32], vec![0xcc; 70]] {
+            let mut handler = far_call_handler(FarCallOpcode::Mimic);
+            handler
+                .set_immediate_mock_return(
+                    MockCallReturn::Revert(return_data.clone()),
+                    Default::default(),
+                )
+                .unwrap();
+            let immediate_return = handler.immediate_return.expect("immediate return must be set");
+
+            let mut registers = [PrimitiveValue::empty(); 16];
+            let mut memory = SimpleMemory::<HistoryDisabled>::default();
+            let written =
+                immediate_return.write_return_data(&mut registers, &mut memory, Timestamp(0));
+            assert_eq!(written, return_data.len());
+            assert_eq!(
+                read_fat_pointer_bytes(immediate_return.return_fat_pointer(), &memory),
+                return_data
+            );
+        }
+    }
+
+    #[test]
+    fn test_immediate_return_preserves_caller_heap() {
+        let return_data = [[0x1; 32], [0x2; 32]].concat();
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        let before = handler.before_far_call_stack.as_mut().unwrap();
+        before.base_memory_page = MemoryPage(8);
+        before.heap_bound = 70;
+        handler.set_immediate_return(return_data.clone()).unwrap();
+
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        let heap_page = CallStackEntry::heap_page_from_base(MemoryPage(8)).0;
+        let callee_heap_page = CallStackEntry::heap_page_from_base(MemoryPage(16)).0;
+        assert_eq!(immediate_return.return_memory_page, callee_heap_page);
+        assert_eq!(immediate_return.return_data_start, 0);
+
+        // the caller's heap is left untouched, including past its heap bound, which must remain
+        // zeroed for the caller to grow its heap into
+        let caller_heap = (0..3).map(|slot| (slot, U256::from(slot + 1))).collect_vec();
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        memory.populate_page(heap_page as usize, caller_heap.clone(), Timestamp(0));
+        let mut registers = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+        immediate_return.write_return_data(&mut registers, &mut memory, Timestamp(1));
+
+        let return_fat_ptr = FatPointer::from_u256(
+            registers[RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER as usize].value,
+        );
+        assert_eq!(return_fat_ptr.memory_page, callee_heap_page);
+        assert_eq!(return_fat_ptr.start, 0);
+        assert_eq!(read_fat_pointer_bytes(return_fat_ptr, &memory), return_data);
+        for (slot, word) in caller_heap {
+            let bytes = memory.read_unaligned_bytes(heap_page as usize, slot * 32, 32);
+            assert_eq!(U256::from_big_endian(&bytes), word);
+        }
+        for slot in 3..6 {
+            let bytes = memory.read_unaligned_bytes(heap_page as usize, slot * 32, 32);
+            assert_eq!(U256::from_big_endian(&bytes), U256::zero());
+        }
+
+        // an explicit start offset is rounded up to a word boundary
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler
+            .set_immediate_mock_return(
+                MockCallReturn::Return(return_data),
+                ImmediateReturnOptions { return_data_start: Some(33), ..Default::default() },
+            )
+            .unwrap();
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        assert_eq!(immediate_return.return_fat_pointer().start, 64);
+        assert_eq!(immediate_return.return_data_words()[0].0, 2);
+    }
+
+    #[test]
+    fn test_scoped_this_address() {
+        let original = H160::repeat_byte(0x1);
+        let prank = Address::repeat_byte(0x2);
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.before_far_call_stack.as_mut().unwrap().this_address = original;
+        handler.far_call_depth = CallDepth::new(2);
+        handler.set_scoped_this_address(prank).unwrap();
+
+        let immediate = handler.call_actions.take_immediate();
+        assert_eq!(immediate.len(), 1);
+        assert!(matches!(immediate[0], CallAction::SetThisAddress(address) if address == prank));
+
+        // a nested frame exiting doesn't end the scope
+        handler.on_far_call_exit(CallDepth::new(3));
+        assert!(handler.call_actions.take_immediate().is_empty());
+
+        handler.on_far_call_exit(CallDepth::new(2));
+        let immediate = handler.call_actions.take_immediate();
+        assert_eq!(immediate.len(), 1);
+        assert!(matches!(
+            immediate[0],
+            CallAction::SetThisAddress(address) if address == original.to_address()
+        ));
+
+        // the next call is not overridden
+        handler.call_actions.track();
+        assert!(handler.call_actions.take_immediate().is_empty());
+        assert!(handler.scoped_this_addresses.is_empty());
+    }
+
+    #[test]
+    fn test_scoped_this_address_for_value_call() {
+        let prank = Address::repeat_byte(0x2);
+        let recipient = H160::repeat_byte(0x3);
+        let mut reg = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+        reg[FarCallParseConfig::default().address_reg()].value =
+            U256::from_big_endian(recipient.as_bytes());
+        let mut token = CallStackEntry::empty_context();
+        token.code_address = L2_ETH_TOKEN_ADDRESS;
+        let mut callee = CallStackEntry::empty_context();
+        callee.code_address = recipient;
+
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.after_far_call_stack.as_mut().unwrap().code_address = MSG_VALUE_SIMULATOR_ADDRESS;
+        handler.far_call_depth = CallDepth::new(2);
+        let simulator = handler.after_far_call_stack.unwrap();
+        handler.on_far_call_entered(FarCallOpcode::Normal, &simulator, CallDepth::new(2), &reg);
+        handler.set_scoped_this_address(prank).unwrap();
+        assert!(handler.call_actions.take_immediate().is_empty());
+
+        // not applied on the `L2EthToken.transferFromTo` frame transferring the value
+        handler.on_far_call_entered(FarCallOpcode::Normal, &token, CallDepth::new(3), &reg);
+        assert!(handler.call_actions.take_immediate().is_empty());
+        handler.on_far_call_exit(CallDepth::new(3));
+        assert!(handler.call_actions.take_immediate().is_empty());
+
+        // but on the recipient frame
+        handler.on_far_call_entered(FarCallOpcode::Mimic, &callee, CallDepth::new(3), &reg);
+        let immediate = handler.call_actions.take_immediate();
+        assert_eq!(immediate.len(), 1);
+        assert!(matches!(immediate[0], CallAction::SetThisAddress(address) if address == prank));
+
+        // the scope ends with the MsgValueSimulator frame
+        handler.on_far_call_exit(CallDepth::new(3));
+        assert!(handler.call_actions.take_immediate().is_empty());
+        handler.on_far_call_exit(CallDepth::new(2));
+        assert_eq!(handler.call_actions.take_immediate().len(), 1);
+    }
+
+    #[test]
+    fn test_current_opcode() {
+        assert_eq!(FarCallHandler::default().current_opcode(), None);
+
+        let mut handler = far_call_handler(FarCallOpcode::Delegate);
+        assert_eq!(handler.current_opcode(), Some(FarCallOpcode::Delegate));
+
+        handler.current_far_call.replace(FarCallOpcode::Mimic);
+        assert_eq!(handler.current_opcode(), Some(FarCallOpcode::Mimic));
+    }
+
+    /// A storage that is not `Send`, as used by single-threaded test harnesses.
+    #[derive(Debug, Default)]
+    struct NonSendStorage(Rc<RefCell<InMemoryStorage>>);
+
+    impl ReadStorage for NonSendStorage {
+        fn read_value(&mut self, key: &StorageKey) -> zksync_types::StorageValue {
+            (&*self.0.borrow()).read_value(key)
+        }
+
+        fn is_write_initial(&mut self, key: &StorageKey) -> bool {
+            (&*self.0.borrow()).is_write_initial(key)
+        }
+
+        fn load_factory_dep(&mut self, hash: H256) -> Option<Vec<u8>> {
+            (&*self.0.borrow()).load_factory_dep(hash)
+        }
+
+        fn get_enumeration_index(&mut self, key: &StorageKey) -> Option<u64> {
+            (&*self.0.borrow()).get_enumeration_index(key)
+        }
+    }
+
+    #[test]
+    fn test_far_call_handler_stack_accessors() {
+        let handler = FarCallHandler::default();
+        assert!(handler.before_stack().is_none());
+        assert!(handler.after_stack().is_none());
+
+        let handler = far_call_handler(FarCallOpcode::Normal);
+        let before = handler.before_stack().expect("before stack must be tracked");
+        assert_eq!(before.pc, 10);
+        assert_eq!(before.exception_handler_location, 20);
+        let after = handler.after_stack().expect("after stack must be tracked");
+        assert_eq!(after.exception_handler_location, 42);
+    }
+
+    #[test]
+    fn test_far_call_handler_with_non_send_storage() {
+        // the finish cycle hooks only require `WriteStorage`, so this compiles for a storage
+        // that is not `Send`
+        fn finish_cycle_hooks<S: WriteStorage, H: HistoryMode>() {
+            let _: fn(&mut FarCallHandler, &mut ZkSyncVmState<S, H>, &mut BootloaderState) =
+                FarCallHandler::maybe_return_early::<S, H>;
+            let _: fn(
+                &mut FarCallHandler,
+                &mut ZkSyncVmState<S, H>,
+                &mut BootloaderState,
+            ) -> Vec<CallAction> = FarCallHandler::take_immediate_actions::<S, H>;
+        }
+        finish_cycle_hooks::<StorageView<NonSendStorage>, HistoryDisabled>();
+
+        let mut storage =
+            StorageView::new(NonSendStorage::default(), Default::default(), Default::default());
+        let key = StorageKey::new(AccountTreeId::new(H160::zero()), H256::from_low_u64_be(1));
+        storage.set_value(key, H256::from_low_u64_be(2));
+        assert_eq!(storage.read_value(&key), H256::from_low_u64_be(2));
+    }
+
+    #[test]
+    fn test_on_far_call_callback() {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let mut handler = FarCallHandler::default();
+        handler.set_on_far_call({
+            let calls = calls.clone();
+            move |call, depth| calls.lock().unwrap().push((*call.to(), call.selector(), depth))
+        });
+
+        let target = H160::repeat_byte(0x1);
+        let call = ParsedFarCall::SimpleCall {
+            to: target,
+            value: U256::zero(),
+            calldata: vec![0xaa, 0xbb, 0xcc, 0xdd],
+            context_address: target,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        handler.on_parsed_far_call(call.clone(), CallDepth::new(2), FarCallOpcode::Normal);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![(target, String::from("aabbccdd"), CallDepth::new(2))]
+        );
+
+        assert!(handler.take_on_far_call().is_some());
+        handler.on_parsed_far_call(call, CallDepth::new(2), FarCallOpcode::Normal);
+        assert_eq!(calls.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_far_call_handler_reset() {
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.set_record_far_calls(true);
+        handler.set_capture_return_data(true);
+        handler.set_immediate_return(vec![0xaa]).unwrap();
+        handler.set_action(CallDepth::next(), CallAction::SetTxOrigin(Address::ZERO));
+        handler.set_action(CallDepth::current(), CallAction::SetTxOrigin(Address::ZERO));
+        handler.on_parsed_far_call(
+            ParsedFarCall::SimpleCall {
+                to: H160::repeat_byte(0x1),
+                value: U256::zero(),
+                calldata: vec![0xaa],
+                context_address: H160::repeat_byte(0x1),
+                is_constructor: false,
+                forwarding_mode: FarCallForwardingMode::UseHeap,
+                is_truncated: false,
+                packed_abi: U256::zero(),
+                ergs_passed: 0,
+                is_system_call: false,
+                kind: FarCallKind::Normal,
+                is_static: false,
+            },
+            CallDepth::new(1),
+            FarCallOpcode::Normal,
+        );
+        handler.on_far_call_return(CallDepth::new(1), false, vec![0xbb]);
+
+        handler.reset();
+        assert!(handler.before_far_call_stack.is_none());
+        assert!(handler.after_far_call_stack.is_none());
+        assert!(handler.current_far_call.is_none());
+        assert!(handler.immediate_return.is_none());
+        assert!(handler.call_actions.pending.is_empty());
+        assert!(handler.call_actions.take_immediate().is_empty());
+        assert_eq!(handler.count_matching_calls(H160::repeat_byte(0x1), &[], None), 0);
+        assert!(handler.recorded_far_calls().is_empty());
+        assert!(handler.captured_return_data().is_empty());
+        assert!(handler.record_far_calls);
+        assert!(handler.capture_return_data);
+    }
+
+    #[test]
+    fn test_call_depth_arithmetic() {
+        assert_eq!(CallDepth::current().increment(), CallDepth::next());
+        assert_eq!(CallDepth::current().saturating_add(2), CallDepth::new(2));
+        assert_eq!(CallDepth::new(5).increment().decrement(), CallDepth::new(5));
+        assert_eq!(CallDepth::new(u16::MAX).increment(), CallDepth::new(u16::MAX));
+        assert_eq!(CallDepth::new(u16::MAX - 1).saturating_add(u8::MAX), CallDepth::new(u16::MAX));
+
+        assert!(CallDepth::current() < CallDepth::next());
+        assert!(CallDepth::new(3) > CallDepth::new(2));
+        assert!(CallDepth::next() <= CallDepth::current().increment());
+        assert_eq!(
+            [CallDepth::new(2), CallDepth::current(), CallDepth::next()].into_iter().max(),
+            Some(CallDepth::new(2))
+        );
+
+        let mut actions = CallActions::default();
+        actions
+            .push(CallDepth::current().saturating_add(2), CallAction::SetTxOrigin(Address::ZERO));
+        actions.track();
+        assert!(actions.take_immediate().is_empty());
+        actions.track();
+        assert_eq!(actions.take_immediate(), vec![CallAction::SetTxOrigin(Address::ZERO)]);
+    }
+
+    #[test]
+    fn test_call_actions_deep_call_depth() {
+        let mut actions = CallActions::default();
+        actions.push(CallDepth::new(300), CallAction::SetMessageSender(Address::ZERO));
+
+        for _ in 0..299 {
+            actions.track();
+            assert!(actions.take_immediate().is_empty());
+        }
+
+        actions.track();
+        let immediate = actions.take_immediate();
+        assert_eq!(immediate.len(), 1);
+        assert!(
+            matches!(immediate[0], CallAction::SetMessageSender(sender) if sender == Address::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_call_actions_is_empty() {
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        assert!(!handler.has_actions());
+        assert!(!handler.has_pending_actions());
+
+        handler.set_action(CallDepth::next(), CallAction::SetTxOrigin(Address::ZERO));
+        assert!(handler.has_actions());
+        assert!(handler.has_pending_actions());
+
+        handler.call_actions.track();
+        assert!(handler.has_actions());
+        assert!(!handler.has_pending_actions());
+
+        handler.call_actions.track();
+        assert!(handler.has_actions());
+        assert!(!handler.has_pending_actions());
+
+        assert_eq!(handler.call_actions.take_immediate().len(), 1);
+        assert!(!handler.has_actions());
+        assert!(handler.call_actions.is_empty());
+    }
+
+    #[test]
+    fn test_call_actions_pending_iter() {
+        let sender = Address::repeat_byte(0x1);
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.set_action(CallDepth::current(), CallAction::SetTxOrigin(sender));
+        handler.set_action(CallDepth::new(2), CallAction::SetMessageSender(sender));
+
+        // immediate actions are not pending
+        let pending = handler.pending_actions().collect_vec();
+        assert_eq!(pending, vec![(&CallDepth::next(), &CallAction::SetMessageSender(sender))]);
+
+        handler.call_actions.track();
+        let pending = handler.pending_actions().collect_vec();
+        assert_eq!(pending, vec![(&CallDepth::current(), &CallAction::SetMessageSender(sender))]);
+
+        handler.call_actions.track();
+        assert_eq!(handler.pending_actions().count(), 0);
+        assert_eq!(
+            handler.call_actions.take_immediate(),
+            vec![CallAction::SetMessageSender(sender), CallAction::SetTxOrigin(sender)]
+        );
+    }
+
+    #[test]
+    fn test_call_actions_set_call_value() {
+        let value = U256::from(1_000_000u64);
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.set_action(CallDepth::next(), CallAction::SetCallValue(value));
+
+        handler.call_actions.track();
+        let mut callee = CallStackEntry::empty_context();
+        for action in handler.call_actions.take_immediate() {
+            if let CallAction::SetCallValue(value) = action {
+                CallAction::apply_call_value(&mut callee, value).unwrap();
+            }
+        }
+        assert_eq!(callee.context_u128_value, 1_000_000);
+
+        // values exceeding u128 are rejected, leaving the frame unchanged
+        let overflow = U256::from(u128::MAX) + 1;
+        assert_eq!(
+            CallAction::apply_call_value(&mut callee, overflow),
+            Err(FarCallHandlerError::CallValueOverflow(overflow))
+        );
+        assert_eq!(callee.context_u128_value, 1_000_000);
+        CallAction::apply_call_value(&mut callee, U256::from(u128::MAX)).unwrap();
+        assert_eq!(callee.context_u128_value, u128::MAX);
+    }
+
+    #[test]
+    fn test_call_actions_application_order() {
+        let mut actions = CallActions::default();
+        actions.push(CallDepth::current(), CallAction::SetTxOrigin(Address::repeat_byte(0x1)));
+        actions.push(CallDepth::current(), CallAction::SetMessageSender(Address::repeat_byte(0x2)));
+        actions.push(CallDepth::next(), CallAction::SetThisAddress(Address::repeat_byte(0x3)));
+        actions.push(CallDepth::next(), CallAction::SetMessageSender(Address::repeat_byte(0x4)));
+        actions.push(CallDepth::current(), CallAction::SetThisAddress(Address::repeat_byte(0x5)));
+        actions.push(CallDepth::current(), CallAction::SetMessageSender(Address::repeat_byte(0x6)));
+
+        assert_eq!(
+            actions.take_immediate(),
+            vec![
+                CallAction::SetThisAddress(Address::repeat_byte(0x5)),
+                CallAction::SetMessageSender(Address::repeat_byte(0x2)),
+                CallAction::SetMessageSender(Address::repeat_byte(0x6)),
+                CallAction::SetTxOrigin(Address::repeat_byte(0x1)),
+            ]
+        );
+
+        actions.track();
+        assert_eq!(
+            actions.take_immediate(),
+            vec![
+                CallAction::SetThisAddress(Address::repeat_byte(0x3)),
+                CallAction::SetMessageSender(Address::repeat_byte(0x4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_call_actions_cancel_pending() {
+        let sender = Address::repeat_byte(0x1);
+        let origin = Address::repeat_byte(0x2);
+        let mut actions = CallActions::default();
+        actions.push(CallDepth::new(2), CallAction::SetMessageSender(sender));
+        actions.push(CallDepth::new(2), CallAction::SetTxOrigin(origin));
+
+        actions.track();
+        assert!(actions.take_immediate().is_empty());
+        let cancelled =
+            actions.cancel_pending(|action| matches!(action, CallAction::SetMessageSender(_)));
+        assert_eq!(cancelled, 1);
+
+        actions.track();
+        let immediate = actions.take_immediate();
+        assert_eq!(immediate.len(), 1);
+        assert!(matches!(immediate[0], CallAction::SetTxOrigin(address) if address == origin));
+
+        // immediate actions are not cancellable
+        actions.push(CallDepth::current(), CallAction::SetMessageSender(sender));
+        assert_eq!(actions.cancel_pending(|_| true), 0);
+        assert_eq!(actions.take_immediate().len(), 1);
+    }
+
+    #[test]
+    fn test_call_actions_tx_origin_with_message_sender() {
+        let sender = Address::repeat_byte(0x1);
+        let origin = Address::repeat_byte(0x2);
+        let mut actions = CallActions::default();
+        actions.push(CallDepth::next(), CallAction::SetMessageSender(sender));
+        actions.push(CallDepth::next(), CallAction::SetTxOrigin(origin));
+        assert!(actions.take_immediate().is_empty());
+
+        actions.track();
+        let immediate = actions.take_immediate();
+        assert_eq!(immediate.len(), 2);
+        assert!(matches!(immediate[0], CallAction::SetMessageSender(address) if address == sender));
+        assert!(matches!(immediate[1], CallAction::SetTxOrigin(address) if address == origin));
+    }
+
+    #[test]
+    fn test_scoped_tx_origin() {
+        let caller = H160::repeat_byte(0x1);
+        let nested = H160::repeat_byte(0x2);
+        let origin = Address::repeat_byte(0x3);
+        let nested_origin = Address::repeat_byte(0x4);
+        let mut handler = FarCallHandler::default();
+        handler.set_scoped_tx_origin(CallDepth::new(2), caller, origin);
+        assert_eq!(handler.scoped_tx_origin(&CallDepth::new(2), caller), Some(origin));
+        // calls made by the overridden frame see the original tx.origin
+        assert_eq!(handler.scoped_tx_origin(&CallDepth::new(3), nested), None);
+
+        // a nested override applies to its own frame, and restores the outer one on exit
+        handler.set_scoped_tx_origin(CallDepth::new(3), nested, nested_origin);
+        assert_eq!(handler.scoped_tx_origin(&CallDepth::new(3), nested), Some(nested_origin));
+        handler.on_far_call_exit(CallDepth::new(3));
+        assert_eq!(handler.scoped_tx_origin(&CallDepth::new(3), nested), None);
+        assert_eq!(handler.scoped_tx_origin(&CallDepth::new(2), caller), Some(origin));
+
+        // the override does not leak into later calls once the frame exits
+        handler.on_far_call_exit(CallDepth::new(2));
+        assert_eq!(handler.scoped_tx_origin(&CallDepth::new(2), caller), None);
+    }
+
+    #[test]
+    fn test_mocked_calls_partial_match_tie_break_is_deterministic() {
+        let address = H160::repeat_byte(0x1);
+        let mut mocks = MockedCalls::default();
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                value: ValueMatch::Any,
+                calldata: CalldataMatch::Prefix(vec![0xaa, 0x01]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![1]),
+        );
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                value: ValueMatch::Any,
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![2]),
+        );
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                value: ValueMatch::Exact(U256::zero()),
+                calldata: CalldataMatch::Prefix(vec![0xab]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![3]),
+        );
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                value: ValueMatch::Any,
+                calldata: CalldataMatch::Prefix(vec![0xab]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![4]),
+        );
+
+        for _ in 0..10 {
+            let matched = mocks.get_matching_return_data(address, &[0xaa, 0xff], U256::zero());
+            assert_eq!(matched, Some(MockCallReturn::Return(vec![2])));
+
+            let matched = mocks.get_matching_return_data(address, &[0xab, 0xff], U256::zero());
+            assert_eq!(matched, Some(MockCallReturn::Return(vec![3])));
+        }
+
+        // re-registering an existing call makes it the latest registered
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                value: ValueMatch::Any,
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![5]),
+        );
+        let matched = mocks.get_matching_return_data(address, &[0xaa, 0xff], U256::zero());
+        assert_eq!(matched, Some(MockCallReturn::Return(vec![5])));
+    }
+
+    #[test]
+    fn test_mocked_calls_exact_address_takes_precedence_over_wildcard() {
+        let address = H160::repeat_byte(0x1);
+        let other_address = H160::repeat_byte(0x2);
+        let mut mocks = MockedCalls::default();
+        mocks.insert(
+            MockCall {
+                address: None,
+                value: ValueMatch::Any,
+                calldata: CalldataMatch::Prefix(vec![0xaa, 0xbb]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![1]),
+        );
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                value: ValueMatch::Any,
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![2]),
+        );
+
+        // exact address partial match wins over a longer wildcard match
+        let matched = mocks.get_matching_return_data(address, &[0xaa, 0xbb], U256::zero());
+        assert_eq!(matched, Some(MockCallReturn::Return(vec![2])));
+
+        // wildcard matches any other address
+        let matched = mocks.get_matching_return_data(other_address, &[0xaa, 0xbb], U256::zero());
+        assert_eq!(matched, Some(MockCallReturn::Return(vec![1])));
+
+        // exact address falls back to wildcard when it has no match
+        let matched = mocks.get_matching_return_data(address, &[0xcc], U256::zero());
+        assert_eq!(matched, None);
+        mocks.insert(
+            MockCall {
+                address: None,
+                value: ValueMatch::Any,
+                calldata: CalldataMatch::Prefix(vec![0xcc]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![3]),
+        );
+        let matched = mocks.get_matching_return_data(address, &[0xcc], U256::zero());
+        assert_eq!(matched, Some(MockCallReturn::Return(vec![3])));
+    }
+
+    #[test]
+    fn test_mocked_calls_caller() {
+        let target = H160::repeat_byte(0x1);
+        let admin = H160::repeat_byte(0xad);
+        let user = H160::repeat_byte(0x2);
+        let mut mocks = MockedCalls::default();
+        mocks.insert(
+            MockCall {
+                address: Some(target),
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                caller: Some(admin),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![1]),
+        );
+        mocks.insert(
+            MockCall {
+                address: Some(target),
+                calldata: CalldataMatch::Prefix(vec![0xaa, 0xbb]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![2]),
+        );
+        let call = ParsedFarCall::SimpleCall {
+            to: target,
+            value: U256::zero(),
+            calldata: vec![0xaa, 0xbb],
+            context_address: target,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+
+        // the caller constrained mock outranks the more specific unconstrained one
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.before_far_call_stack.as_mut().unwrap().this_address = admin;
+        let caller = handler.caller().expect("caller must be tracked");
+        assert_eq!(
+            mocks.get_matching_return_data_for_caller(&call, caller),
+            Some(MockCallReturn::Return(vec![1]))
+        );
+        assert_eq!(
+            mocks.get_matching_return_data_for_caller(&call, user),
+            Some(MockCallReturn::Return(vec![2]))
+        );
+
+        // caller constrained mocks never match an unknown caller
+        assert_eq!(
+            mocks.get_matching_return_data_for_call(&call),
+            Some(MockCallReturn::Return(vec![2]))
+        );
+        assert_eq!(mocks.get_matching_return_data(target, &[0xaa], U256::zero()), None);
+    }
+
+    #[test]
+    fn test_mocked_calls_indexed_by_address() {
+        let address = |index: u64| H160::from_low_u64_be(index + 1);
+        let mut mocks = MockedCalls::default();
+        for index in 0..1000u64 {
+            mocks.insert(
+                MockCall {
+                    address: Some(address(index % 100)),
+                    calldata: CalldataMatch::Prefix(index.to_be_bytes().to_vec()),
+                    ..Default::default()
+                },
+                MockCallReturn::Return(index.to_be_bytes().to_vec()),
+            );
+        }
+        mocks.insert(
+            MockCall { calldata: CalldataMatch::Prefix(vec![0xff]), ..Default::default() },
+            MockCallReturn::Return(vec![0xff]),
+        );
+        assert_eq!(mocks.len(), 1001);
+
+        // each address only holds its own mocked calls
+        for index in 0..100u64 {
+            let registered =
+                mocks.by_address.get(&Some(address(index))).expect("address must be indexed");
+            assert_eq!(registered.len(), 10);
+        }
+        assert_eq!(mocks.by_address[&None].len(), 1);
+
+        for index in 0..1000u64 {
+            let calldata = index.to_be_bytes();
+            assert_eq!(
+                mocks.get_matching_return_data_ref(address(index % 100), &calldata, U256::zero()),
+                Some(&MockCallReturn::Return(calldata.to_vec()))
+            );
+            assert_eq!(
+                mocks.get_matching_return_data_ref(
+                    address((index + 1) % 100),
+                    &calldata,
+                    U256::zero()
+                ),
+                None
+            );
+        }
+        assert_eq!(
+            mocks.get_matching_return_data_ref(address(0), &[0xff], U256::zero()),
+            Some(&MockCallReturn::Return(vec![0xff]))
+        );
+
+        assert_eq!(mocks.clear_for_address(address(0)), 10);
+        assert!(!mocks.by_address.contains_key(&Some(address(0))));
+        assert_eq!(mocks.len(), 991);
+    }
+
+    #[test]
+    fn test_mocked_calls_any_calldata() {
+        let address = H160::repeat_byte(0x1);
+        let mut mocks = MockedCalls::default();
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                calldata: CalldataMatch::Prefix(vec![]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![1]),
+        );
+
+        // an empty prefix only matches empty calldata
+        let matched = mocks.get_matching_return_data(address, &[], U256::zero());
+        assert_eq!(matched, Some(MockCallReturn::Return(vec![1])));
+        let matched = mocks.get_matching_return_data(address, &[0xaa, 0xbb], U256::zero());
+        assert_eq!(matched, None);
+
+        // mocked calls match any calldata unless narrowed down
+        assert_eq!(MockCall::default().calldata, CalldataMatch::Any);
+        mocks.insert(
+            MockCall { address: Some(address), ..Default::default() },
+            MockCallReturn::Return(vec![2]),
+        );
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![3]),
+        );
+
+        // any calldata matches everything, with the lowest precedence
+        let matched = mocks.get_matching_return_data(address, &[0xbb], U256::zero());
+        assert_eq!(matched, Some(MockCallReturn::Return(vec![2])));
+        let matched = mocks.get_matching_return_data(address, &[0xaa, 0xbb], U256::zero());
+        assert_eq!(matched, Some(MockCallReturn::Return(vec![3])));
+        let matched = mocks.get_matching_return_data(address, &[], U256::zero());
+        assert_eq!(matched, Some(MockCallReturn::Return(vec![1])));
+    }
+
+    #[test]
+    fn test_mocked_calls_ref_matches_owned() {
+        let address = H160::repeat_byte(0x1);
+        let mut mocks = MockedCalls::default();
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![1]),
+        );
+        mocks.insert(
+            MockCall {
+                address: None,
+                calldata: CalldataMatch::Prefix(vec![0xaa, 0xbb]),
+                ..Default::default()
+            },
+            MockCallReturn::Revert(vec![2; 64]),
+        );
+        mocks.insert_queue(
+            MockCall {
+                address: Some(address),
+                value: ValueMatch::Exact(U256::one()),
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                ..Default::default()
+            },
+            [MockCallReturn::Return(vec![3]), MockCallReturn::Return(vec![4])],
+        );
+
+        for (code_address, calldata, value) in [
+            (address, vec![0xaa, 0xbb], U256::zero()),
+            (H160::repeat_byte(0x2), vec![0xaa, 0xbb], U256::zero()),
+            (address, vec![0xaa], U256::one()),
+            (address, vec![0xaa], U256::one()),
+            (address, vec![0xcc], U256::zero()),
+        ] {
+            let borrowed =
+                mocks.get_matching_return_data_ref(code_address, &calldata, value).cloned();
+            let owned = mocks.get_matching_return_data(code_address, &calldata, value);
+            assert_eq!(borrowed, owned);
+        }
+    }
+
+    #[test]
+    fn test_mocked_calls_get_matching_mock() {
+        let address = H160::repeat_byte(0x1);
+        let exact = MockCall {
+            address: Some(address),
+            calldata: CalldataMatch::Prefix(vec![0xaa, 0xbb]),
+            ..Default::default()
+        };
+        let with_value = MockCall {
+            address: Some(address),
+            value: ValueMatch::Exact(U256::one()),
+            calldata: CalldataMatch::Prefix(vec![0xaa, 0xbb]),
+            ..Default::default()
+        };
+        let partial = MockCall {
+            address: Some(address),
+            calldata: CalldataMatch::Prefix(vec![0xaa]),
+            ..Default::default()
+        };
+        let mut mocks = MockedCalls::default();
+        mocks.insert(exact.clone(), MockCallReturn::Return(vec![1]));
+        mocks.insert(with_value.clone(), MockCallReturn::Return(vec![2]));
+        mocks.insert(partial.clone(), MockCallReturn::Return(vec![3]));
+
+        let matched = mocks.get_matching_mock(address, &[0xaa, 0xbb], U256::zero());
+        assert_eq!(matched, Some((&exact, &MockCallReturn::Return(vec![1]))));
+
+        let matched = mocks.get_matching_mock(address, &[0xaa, 0xbb], U256::one());
+        assert_eq!(matched, Some((&with_value, &MockCallReturn::Return(vec![2]))));
+
+        let matched = mocks.get_matching_mock(address, &[0xaa, 0xcc], U256::zero());
+        assert_eq!(matched, Some((&partial, &MockCallReturn::Return(vec![3]))));
+
+        assert_eq!(mocks.get_matching_mock(address, &[0xcc], U256::zero()), None);
+    }
+
+    #[test]
+    fn test_mocked_calls_clear_for_address() {
+        let address = H160::repeat_byte(0x1);
+        let other_address = H160::repeat_byte(0x2);
+        let mut mocks = MockedCalls::default();
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                value: ValueMatch::Any,
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![1]),
+        );
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                value: ValueMatch::Exact(U256::one()),
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![2]),
+        );
+        mocks.insert(
+            MockCall {
+                address: Some(other_address),
+                value: ValueMatch::Any,
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![3]),
+        );
+
+        assert_eq!(mocks.clear_for_address(address), 2);
+        assert_eq!(mocks.clear_for_address(address), 0);
+        assert_eq!(mocks.get_matching_return_data(address, &[0xaa], U256::one()), None);
+        assert_eq!(
+            mocks.get_matching_return_data(other_address, &[0xaa], U256::one()),
+            Some(MockCallReturn::Return(vec![3]))
+        );
+    }
+
+    #[test]
+    fn test_mocked_calls_queued_returns_repeat_last() {
+        let address = H160::repeat_byte(0x1);
+        let mut mocks = MockedCalls::default();
+        mocks.insert_queue(
+            MockCall {
+                address: Some(address),
+                value: ValueMatch::Any,
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                ..Default::default()
+            },
+            [
+                MockCallReturn::Return(vec![1]),
+                MockCallReturn::Return(vec![2]),
+                MockCallReturn::Return(vec![3]),
+            ],
+        );
+
+        for expected in [1, 2, 3, 3] {
+            let matched = mocks.get_matching_return_data(address, &[0xaa], U256::zero());
+            assert_eq!(matched, Some(MockCallReturn::Return(vec![expected])));
+        }
+    }
+
+    #[test]
+    fn test_mocked_calls_replace_conflicting() {
+        let address = H160::repeat_byte(0x1);
+        let call = MockCall {
+            address: Some(address),
+            value: ValueMatch::Any,
+            calldata: CalldataMatch::Prefix(vec![0xaa]),
+            ..Default::default()
+        };
+        let conflicting = MockCall { caller: Some(H160::repeat_byte(0x2)), ..call.clone() };
+
+        // conflicting mocked calls are kept by default
+        let mut mocks = MockedCalls::default();
+        mocks.insert(conflicting.clone(), MockCallReturn::Return(vec![1]));
+        mocks.insert(call.clone(), MockCallReturn::Return(vec![2]));
+        assert_eq!(mocks.len(), 2);
+
+        // identical mocked calls are always replaced
+        mocks.insert(call.clone(), MockCallReturn::Return(vec![3]));
+        assert_eq!(mocks.len(), 2);
+
+        let mut mocks = MockedCalls::default();
+        mocks.set_replace_conflicting(true);
+        mocks.insert(conflicting.clone(), MockCallReturn::Return(vec![1]));
+        mocks.insert(call.clone(), MockCallReturn::Return(vec![2]));
+        assert_eq!(mocks.len(), 1);
+        assert_eq!(mocks.invocation_count(&conflicting), None);
+        assert_eq!(
+            mocks.get_matching_return_data(address, &[0xaa], U256::zero()),
+            Some(MockCallReturn::Return(vec![2]))
+        );
+
+        // a different calldata match does not conflict
+        mocks.insert(
+            MockCall { calldata: CalldataMatch::Prefix(vec![0xaa, 0xbb]), ..call },
+            MockCallReturn::Return(vec![3]),
+        );
+        assert_eq!(mocks.len(), 2);
+    }
+
+    #[test]
+    fn test_mocked_calls_would_match() {
+        let address = H160::repeat_byte(0x1);
+        let call = MockCall {
+            address: Some(address),
+            value: ValueMatch::Any,
+            calldata: CalldataMatch::Prefix(vec![0xaa]),
+            ..Default::default()
+        };
+        let mut mocks = MockedCalls::default();
+        mocks.insert_queue(
+            call.clone(),
+            [MockCallReturn::Return(vec![1]), MockCallReturn::Return(vec![2])],
+        );
+
+        for _ in 0..2 {
+            assert_eq!(mocks.would_match(address, &[0xaa], U256::zero()), Some(&call));
+        }
+        assert_eq!(mocks.would_match(address, &[0xbb], U256::zero()), None);
+        assert_eq!(mocks.invocation_count(&call), Some(0));
+
+        assert_eq!(
+            mocks.get_matching_return_data(address, &[0xaa], U256::zero()),
+            Some(MockCallReturn::Return(vec![1]))
+        );
+        assert_eq!(mocks.invocation_count(&call), Some(1));
+        assert_eq!(mocks.would_match(address, &[0xaa], U256::zero()), Some(&call));
+        assert_eq!(
+            mocks.get_matching_return_data(address, &[0xaa], U256::zero()),
+            Some(MockCallReturn::Return(vec![2]))
+        );
+    }
+
+    #[test]
+    fn test_mock_call_new() {
+        let address = Address::repeat_byte(0x1);
+        let call = MockCall::new(address);
+        assert_eq!(call, MockCall::new(address.to_h160()));
+        assert_eq!(call.address, Some(H160::repeat_byte(0x1)));
+        assert_eq!(call.calldata, CalldataMatch::Any);
+
+        let mut mocks = MockedCalls::default();
+        mocks.insert(
+            MockCall { calldata: CalldataMatch::Prefix(vec![0xaa]), ..MockCall::new(address) },
+            MockCallReturn::Return(vec![1]),
+        );
+
+        // the parsed call carries the address as H160
+        let parsed = ParsedFarCall::SimpleCall {
+            to: H160::repeat_byte(0x1),
+            value: U256::zero(),
+            calldata: vec![0xaa, 0xbb],
+            context_address: H160::repeat_byte(0x1),
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        assert_eq!(
+            mocks.get_matching_return_data_for_call(&parsed),
+            Some(MockCallReturn::Return(vec![1]))
+        );
+    }
+
+    #[test]
+    fn test_mock_call_from_signature() {
+        let address = H160::repeat_byte(0x1);
+        let call = MockCall::from_signature(
+            address,
+            "transfer(address,uint256)",
+            vec![
+                DynSolValue::Address(Address::repeat_byte(0x22)),
+                DynSolValue::Uint(alloy_primitives::U256::from(1000), 256),
+            ],
+        );
+        assert_eq!(call.address, Some(address));
+        assert_eq!(call.value, ValueMatch::Any);
+        let expected = hex::decode(concat!(
+            "a9059cbb",
+            "0000000000000000000000002222222222222222222222222222222222222222",
+            "00000000000000000000000000000000000000000000000000000000000003e8",
+        ))
+        .unwrap();
+        assert_eq!(call.calldata, CalldataMatch::Prefix(expected.clone()));
+
+        let mut mocks = MockedCalls::default();
+        mocks.insert(call, MockCallReturn::Return(vec![1]));
+        assert_eq!(
+            mocks.get_matching_return_data(address, &expected, U256::zero()),
+            Some(MockCallReturn::Return(vec![1]))
+        );
+
+        // without arguments only the selector is matched
+        let call = MockCall::from_signature(address, "totalSupply()", vec![]);
+        assert_eq!(call.calldata, CalldataMatch::Prefix(hex::decode("18160ddd").unwrap()));
+    }
+
+    #[test]
+    fn test_mocked_calls_mocked_addresses() {
+        let addresses = [H160::repeat_byte(0x1), H160::repeat_byte(0x2), H160::repeat_byte(0x3)];
+        let mut mocks = MockedCalls::default();
+        assert!(mocks.mocked_addresses().is_empty());
+
+        mocks.insert(MockCall::new(addresses[0]), MockCallReturn::Return(vec![1]));
+        mocks.insert(
+            MockCall { value: ValueMatch::Exact(U256::from(1)), ..MockCall::new(addresses[0]) },
+            MockCallReturn::Return(vec![2]),
+        );
+        mocks.insert(
+            MockCall { value: ValueMatch::Exact(U256::from(1)), ..MockCall::new(addresses[1]) },
+            MockCallReturn::Return(vec![3]),
+        );
+        mocks.insert_dynamic(MockCall::new(addresses[2]), |_| vec![4]);
+        // mocked calls for any address are omitted
+        mocks.insert(
+            MockCall { calldata: CalldataMatch::Any, ..Default::default() },
+            MockCallReturn::Return(vec![5]),
+        );
+
+        assert_eq!(mocks.mocked_addresses(), HashSet::from(addresses));
+
+        mocks.clear_for_address(addresses[0]);
+        assert_eq!(mocks.mocked_addresses(), HashSet::from([addresses[1], addresses[2]]));
+    }
+
+    #[test]
+    fn test_mocked_calls_max_depth() {
+        let address = H160::repeat_byte(0x1);
+        let mut mocks = MockedCalls::default();
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                max_depth: Some(CallDepth::next()),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![1]),
+        );
+
+        let matched = mocks.get_matching_return_data_at_depth(
+            address,
+            &[0xaa],
+            U256::zero(),
+            CallDepth::next(),
+        );
+        assert_eq!(matched, Some(MockCallReturn::Return(vec![1])));
+        let matched = mocks.get_matching_return_data_at_depth(
+            address,
+            &[0xaa],
+            U256::zero(),
+            CallDepth::new(3),
+        );
+        assert_eq!(matched, None);
+
+        // the scope is unknown without a depth
+        assert_eq!(mocks.get_matching_return_data(address, &[0xaa], U256::zero()), None);
+
+        // an unscoped mocked call applies at any depth
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![2]),
+        );
+        let matched = mocks.get_matching_return_data_at_depth(
+            address,
+            &[0xaa],
+            U256::zero(),
+            CallDepth::new(3),
+        );
+        assert_eq!(matched, Some(MockCallReturn::Return(vec![2])));
+    }
+
+    #[test]
+    fn test_mocked_calls_outcome_sequence() {
+        let address = H160::repeat_byte(0x1);
+        let call = MockCall {
+            address: Some(address),
+            calldata: CalldataMatch::Prefix(vec![0xaa]),
+            ..Default::default()
+        };
+        let mut mocks = MockedCalls::default();
+        mocks.insert_sequence(
+            call.clone(),
+            [
+                (2, MockCallReturn::Return(vec![1])),
+                (0, MockCallReturn::Return(vec![2])),
+                (1, MockCallReturn::Revert(vec![3])),
+            ],
+        );
+        assert_eq!(mocks.invocation_count(&call), Some(0));
+
+        let mut outcomes = vec![];
+        for _ in 0..4 {
+            let mock_return = mocks
+                .get_matching_return_data(address, &[0xaa], U256::zero())
+                .expect("mocked call must match");
+            let mut handler = far_call_handler(FarCallOpcode::Normal);
+            handler.set_immediate_mock_return(mock_return.clone(), Default::default()).unwrap();
+            let immediate_return = handler.immediate_return.expect("immediate return must be set");
+            assert_eq!(immediate_return.is_revert, mock_return.is_revert());
+            outcomes.push(mock_return);
+        }
+
+        // the third call reverts, and the revert repeats once the sequence is exhausted
+        assert_eq!(
+            outcomes,
+            vec![
+                MockCallReturn::Return(vec![1]),
+                MockCallReturn::Return(vec![1]),
+                MockCallReturn::Revert(vec![3]),
+                MockCallReturn::Revert(vec![3]),
+            ]
+        );
+        assert_eq!(mocks.invocation_count(&call), Some(4));
+        assert_eq!(mocks.invocation_count(&MockCall { address: None, ..call.clone() }), None);
+
+        // re-inserting resets the count
+        mocks.insert(call.clone(), MockCallReturn::Return(vec![]));
+        assert_eq!(mocks.invocation_count(&call), Some(0));
+    }
+
+    #[test]
+    fn test_mocked_calls_dynamic() {
+        let address = H160::repeat_byte(0x1);
+        let selector = [0xde, 0xad, 0xbe, 0xef];
+        let call = |calldata: Vec<u8>| ParsedFarCall::SimpleCall {
+            to: address,
+            value: U256::zero(),
+            calldata,
+            context_address: address,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+
+        let mut mocks = MockedCalls::default();
+        mocks.insert_dynamic(
+            MockCall {
+                address: Some(address),
+                calldata: CalldataMatch::Prefix(selector.to_vec()),
+                ..Default::default()
+            },
+            |call| call.params().first().map(|word| word.to_vec()).unwrap_or_default(),
+        );
+        assert_eq!(mocks.len(), 1);
+
+        // the mocked call echoes its first argument word
+        let word = [0x2; 32];
+        let matched =
+            mocks.get_matching_return_data_for_call(&call([selector.as_slice(), &word].concat()));
+        assert_eq!(matched, Some(MockCallReturn::Return(word.to_vec())));
+        let matched = mocks.get_matching_return_data_for_call(&call(vec![0xaa]));
+        assert_eq!(matched, None);
+
+        // static mocked calls take precedence
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                calldata: CalldataMatch::Prefix(selector.to_vec()),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![0x3]),
+        );
+        let matched =
+            mocks.get_matching_return_data_for_call(&call([selector.as_slice(), &word].concat()));
+        assert_eq!(matched, Some(MockCallReturn::Return(vec![0x3])));
+
+        assert_eq!(mocks.clear_for_address(address), 2);
+        assert!(mocks.is_empty());
+    }
+
+    #[test]
+    fn test_mocked_calls_insert_many() {
+        let address = H160::repeat_byte(0x1);
+        let entries = [
+            (
+                MockCall {
+                    address: Some(address),
+                    value: ValueMatch::Any,
+                    calldata: CalldataMatch::Prefix(vec![0xaa]),
+                    ..Default::default()
+                },
+                MockCallReturn::Return(vec![1]),
+            ),
+            (
+                MockCall {
+                    address: Some(address),
+                    value: ValueMatch::Exact(U256::one()),
+                    calldata: CalldataMatch::Prefix(vec![0xaa]),
+                    ..Default::default()
+                },
+                MockCallReturn::Return(vec![2]),
+            ),
+            (
+                MockCall {
+                    address: None,
+                    value: ValueMatch::Exact(U256::from(5)),
+                    calldata: CalldataMatch::Prefix(vec![0xbb]),
+                    ..Default::default()
+                },
+                MockCallReturn::Revert(vec![3]),
+            ),
+        ];
+
+        let mut mocks = MockedCalls::default();
+        assert!(mocks.is_empty());
+        mocks.insert_many(entries.clone());
+        assert_eq!(mocks.len(), 3);
+        let with_value = |mocks: &MockedCalls| {
+            mocks.iter_all().filter(|(bucket, ..)| *bucket == MockCallBucket::WithValue).count()
+        };
+        assert_eq!(with_value(&mocks), 2);
+        assert_eq!(
+            mocks.get_matching_return_data(address, &[0xaa], U256::zero()),
+            Some(MockCallReturn::Return(vec![1]))
+        );
+        assert_eq!(
+            mocks.get_matching_return_data(address, &[0xaa], U256::one()),
+            Some(MockCallReturn::Return(vec![2]))
+        );
+
+        let mut mocks = entries.into_iter().collect::<MockedCalls>();
+        assert_eq!(mocks.len(), 3);
+        assert_eq!(with_value(&mocks), 2);
+        assert_eq!(
+            mocks.get_matching_return_data(address, &[0xbb], U256::from(5)),
+            Some(MockCallReturn::Revert(vec![3]))
+        );
+
+        mocks.clear();
+        assert!(mocks.is_empty());
+    }
+
+    #[test]
+    fn test_mocked_calls_iter_all() {
+        let address = H160::repeat_byte(0x1);
+        let without_value = MockCall {
+            address: Some(address),
+            value: ValueMatch::Any,
+            calldata: CalldataMatch::Prefix(vec![0xaa]),
+            ..Default::default()
+        };
+        let with_value = MockCall {
+            address: None,
+            value: ValueMatch::Exact(U256::one()),
+            calldata: CalldataMatch::Prefix(vec![0xaa, 0xbb, 0xcc, 0xdd]),
+            ..Default::default()
+        };
+        let mut mocks = MockedCalls::default();
+        mocks.insert(without_value.clone(), MockCallReturn::Return(vec![1]));
+        mocks.insert(with_value.clone(), MockCallReturn::Revert(vec![2]));
+
+        let entries = mocks.iter_all().collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                (MockCallBucket::WithValue, &with_value, &MockCallReturn::Revert(vec![2])),
+                (MockCallBucket::WithoutValue, &without_value, &MockCallReturn::Return(vec![1])),
+            ]
+        );
+
+        let summary = mocks.summary().to_string();
+        assert_eq!(summary.lines().count(), 2);
+        assert!(summary.contains("calldata=0xaabbccdd => revert 0x02"));
+        assert!(summary.contains("calldata=0xaa => return 0x01"));
+        assert!(summary.contains("address=*"));
+    }
+
+    #[test]
+    fn test_mocked_calls_value_call_recipient() {
+        let recipient = H160::repeat_byte(0x1);
+        let value_call = ParsedFarCall::ValueCall {
+            to: MSG_VALUE_SIMULATOR_ADDRESS,
+            value: U256::from(100),
+            calldata: vec![0xaa],
+            recipient,
+            is_system_call: false,
+            context_address: MSG_VALUE_SIMULATOR_ADDRESS,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+
+        let mut mocks = MockedCalls::default();
+        mocks.insert(
+            MockCall {
+                address: Some(recipient),
+                value: ValueMatch::Exact(U256::from(100)),
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![1]),
+        );
+        assert_eq!(
+            mocks.get_matching_return_data_for_call(&value_call),
+            Some(MockCallReturn::Return(vec![1]))
+        );
+        // matching on the code address is unchanged
+        assert_eq!(
+            mocks.get_matching_return_data(MSG_VALUE_SIMULATOR_ADDRESS, &[0xaa], U256::from(100)),
+            None
+        );
+
+        // simulator-addressed mocked calls take precedence
+        mocks.insert(
+            MockCall {
+                address: Some(MSG_VALUE_SIMULATOR_ADDRESS),
+                value: ValueMatch::Any,
+                calldata: CalldataMatch::Prefix(vec![0xaa]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![2]),
+        );
+        assert_eq!(
+            mocks.get_matching_return_data_for_call(&value_call),
+            Some(MockCallReturn::Return(vec![2]))
+        );
+    }
+
+    #[test]
+    fn test_far_call_observer() {
+        #[derive(Default)]
+        struct RecordingObserver(Arc<Mutex<Vec<String>>>);
+
+        impl FarCallObserver for RecordingObserver {
+            fn on_before(&mut self, call: &ParsedFarCall, depth: CallDepth) {
+                self.0.lock().unwrap().push(format!("before {:?} {depth:?}", call.to()));
+            }
+
+            fn on_after(&mut self, depth: CallDepth, outcome: FarCallOutcome) {
+                self.0.lock().unwrap().push(format!("after {depth:?} {outcome:?}"));
+            }
+        }
+
+        let call = |to: H160| ParsedFarCall::SimpleCall {
+            to,
+            value: U256::zero(),
+            calldata: vec![],
+            context_address: to,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        let events = Arc::new(Mutex::new(vec![]));
+        let mut handler = FarCallHandler::default();
+        handler.set_observer(RecordingObserver(events.clone()));
+
+        let (outer, inner) = (H160::repeat_byte(0x1), H160::repeat_byte(0x2));
+        handler.on_parsed_far_call(call(outer), CallDepth::new(1), FarCallOpcode::Normal);
+        handler.on_parsed_far_call(call(inner), CallDepth::new(2), FarCallOpcode::Normal);
+        handler.on_far_call_completed(CallDepth::new(2), FarCallOutcome::Revert);
+        handler.on_far_call_completed(CallDepth::new(1), FarCallOutcome::Success);
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                format!("before {outer:?} CallDepth(1)"),
+                format!("before {inner:?} CallDepth(2)"),
+                "after CallDepth(2) Revert".to_string(),
+                "after CallDepth(1) Success".to_string(),
+            ]
+        );
+
+        // the observer survives a reset, but is removed once taken
+        handler.reset();
+        assert!(handler.take_observer().is_some());
+        handler.on_parsed_far_call(call(outer), CallDepth::new(1), FarCallOpcode::Normal);
+        assert_eq!(events.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_far_call_tracing_span() {
+        use std::collections::BTreeMap;
+        use tracing::{
+            field::{Field, Visit},
+            span::{Attributes, Id, Record},
+            Event, Metadata, Subscriber,
+        };
+
+        /// Records the fields of all spans, by span name.
+        struct SpanRecorder(Arc<Mutex<Vec<(String, BTreeMap<String, String>)>>>);
+
+        struct FieldVisitor<'a>(&'a mut BTreeMap<String, String>);
+
+        impl Visit for FieldVisitor<'_> {
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                self.0.insert(field.name().to_string(), format!("{value:?}"));
+            }
+        }
+
+        impl Subscriber for SpanRecorder {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut fields = BTreeMap::new();
+                span.record(&mut FieldVisitor(&mut fields));
+                let mut spans = self.0.lock().unwrap();
+                spans.push((span.metadata().name().to_string(), fields));
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, span: &Id, values: &Record<'_>) {
+                let mut spans = self.0.lock().unwrap();
+                let (_, fields) = &mut spans[span.into_u64() as usize - 1];
+                values.record(&mut FieldVisitor(fields));
+            }
+
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+            fn event(&self, _event: &Event<'_>) {}
+
+            fn enter(&self, _span: &Id) {}
+
+            fn exit(&self, _span: &Id) {}
+        }
+
+        let to = H160::repeat_byte(0x1);
+        let call = ParsedFarCall::SimpleCall {
+            to,
+            value: U256::from(7),
+            calldata: hex::decode("deadbeef").unwrap(),
+            context_address: to,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        let spans = Arc::new(Mutex::new(vec![]));
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        tracing::subscriber::with_default(SpanRecorder(spans.clone()), || {
+            handler.on_parsed_far_call(call, CallDepth::new(2), FarCallOpcode::Normal);
+        });
+
+        let spans = spans.lock().unwrap();
+        let (_, fields) = spans
+            .iter()
+            .find(|(name, _)| name == "far_call")
+            .expect("far call span must be emitted");
+        assert_eq!(fields["to"], format!("{to:?}"));
+        assert_eq!(fields["selector"], "deadbeef");
+        assert_eq!(fields["value"], "7");
+        assert_eq!(fields["depth"], "2");
+        assert_eq!(fields["immediate_return"], "false");
+    }
+
+    #[test]
+    fn test_calldata_bytes_read() {
+        let call = |len: usize| ParsedFarCall::SimpleCall {
+            to: H160::repeat_byte(0x1),
+            value: U256::zero(),
+            calldata: vec![0xaa; len],
+            context_address: H160::repeat_byte(0x1),
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        let mut handler = FarCallHandler::default();
+        handler.on_parsed_far_call(call(100), CallDepth::next(), FarCallOpcode::Normal);
+        assert_eq!(handler.calldata_bytes_read(), None);
+
+        handler.set_count_calldata_bytes(true);
+        for len in [4, 36, 0] {
+            handler.on_parsed_far_call(call(len), CallDepth::next(), FarCallOpcode::Normal);
+        }
+        assert_eq!(handler.calldata_bytes_read(), Some(40));
+
+        handler.reset();
+        assert_eq!(handler.calldata_bytes_read(), Some(0));
+        handler.set_count_calldata_bytes(false);
+        assert_eq!(handler.calldata_bytes_read(), None);
+    }
+
+    #[test]
+    fn test_far_call_outcome() {
+        assert_eq!(FarCallOutcome::from(RetOpcode::Ok), FarCallOutcome::Success);
+        assert_eq!(FarCallOutcome::from(RetOpcode::Revert), FarCallOutcome::Revert);
+        assert_eq!(FarCallOutcome::from(RetOpcode::Panic), FarCallOutcome::Panic);
+        assert!(!FarCallOutcome::Success.is_revert());
+        assert!(FarCallOutcome::Panic.is_revert());
+
+        let call = |to: H160| ParsedFarCall::SimpleCall {
+            to,
+            value: U256::zero(),
+            calldata: vec![],
+            context_address: to,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        let mut handler = FarCallHandler::default();
+        handler.set_record_far_calls(true);
+        assert_eq!(handler.last_far_call_outcome(), None);
+
+        // the outer call succeeds, while the nested call reverts
+        handler.on_parsed_far_call(
+            call(H160::repeat_byte(0x1)),
+            CallDepth::new(1),
+            FarCallOpcode::Normal,
+        );
+        handler.on_parsed_far_call(
+            call(H160::repeat_byte(0x2)),
+            CallDepth::new(2),
+            FarCallOpcode::Normal,
+        );
+        handler.on_far_call_completed(CallDepth::new(2), FarCallOutcome::Revert);
+        assert_eq!(
+            handler.last_far_call_outcome(),
+            Some((CallDepth::new(2), FarCallOutcome::Revert))
+        );
+        assert_eq!(handler.recorded_far_calls()[0].outcome, None);
+
+        handler.on_far_call_completed(CallDepth::new(1), FarCallOutcome::Success);
+        let outcomes =
+            handler.recorded_far_calls().iter().map(|recorded| recorded.outcome).collect_vec();
+        assert_eq!(outcomes, vec![Some(FarCallOutcome::Success), Some(FarCallOutcome::Revert)]);
+        assert_eq!(
+            handler.last_far_call_outcome(),
+            Some((CallDepth::new(1), FarCallOutcome::Success))
+        );
+    }
+
+    #[test]
+    fn test_encode_revert_reason() {
+        let revert_data = encode_revert_reason("mocked revert");
+        assert_eq!(revert_data[..4], hex!("08c379a0"));
+        assert_eq!(revert_data.len(), 4 + 32 * 3);
+        let decoded = Revert::abi_decode(&revert_data, true).unwrap();
+        assert_eq!(decoded.reason, "mocked revert");
+
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.set_immediate_revert(encode_revert_reason("")).unwrap();
+        let immediate_return = handler.immediate_return.expect("immediate return must be set");
+        assert!(immediate_return.is_revert);
+        assert_eq!(Revert::abi_decode(&immediate_return.return_data, true).unwrap().reason, "");
+    }
+
+    #[test]
+    fn test_encode_panic() {
+        let revert_data = encode_panic(0x11);
+        assert_eq!(revert_data[..4], hex!("4e487b71"));
+        assert_eq!(revert_data.len(), 4 + 32);
+        let decoded = Panic::abi_decode(&revert_data, true).unwrap();
+        assert_eq!(decoded.code, alloy_primitives::U256::from(0x11));
+    }
+
+    #[test]
+    fn test_capture_return_data() {
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        memory.populate_page(
+            5,
+            vec![(0, U256::from_big_endian(&[0xaa; 32])), (1, U256::from_big_endian(&[0xbb; 32]))],
+            Timestamp(0),
+        );
+        let pointer = FatPointer { memory_page: 5, offset: 4, start: 24, length: 12 };
+        let data = read_fat_pointer_bytes(pointer, &memory);
+        assert_eq!(data, [[0xaa; 4], [0xbb; 4]].concat());
+
+        let mut handler = FarCallHandler::default();
+        handler.on_far_call_return(CallDepth::new(1), false, data.clone());
+        assert!(handler.captured_return_data().is_empty());
+
+        handler.set_capture_return_data(true);
+        handler.on_far_call_return(CallDepth::new(2), false, data.clone());
+        handler.on_far_call_return(CallDepth::new(1), true, vec![]);
+
+        let captured = handler.take_captured_return_data();
+        assert_eq!(
+            captured,
+            vec![
+                CapturedReturnData { depth: CallDepth::new(2), is_revert: false, data },
+                CapturedReturnData { depth: CallDepth::new(1), is_revert: true, data: vec![] },
+            ]
+        );
+        assert!(handler.captured_return_data().is_empty());
+    }
+
+    #[test]
+    fn test_mocked_calls_min_match_len() {
+        let address = H160::repeat_byte(0x1);
+        let mut mocks = MockedCalls::default();
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                value: ValueMatch::Any,
+                calldata: CalldataMatch::Prefix(vec![0xaa, 0xbb, 0xcc, 0xdd]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![1]),
+        );
+        mocks.insert(
+            MockCall {
+                address: Some(address),
+                value: ValueMatch::Any,
+                calldata: CalldataMatch::Prefix(vec![0xaa, 0xbb, 0xcc, 0xee, 0x01]),
+                ..Default::default()
+            },
+            MockCallReturn::Return(vec![2]),
+        );
+
+        let calldata = [0xaa, 0xbb, 0xcc, 0xdd, 0x01];
+        assert_eq!(
+            mocks.get_matching_return_data(address, &calldata, U256::zero()),
+            Some(MockCallReturn::Return(vec![1]))
+        );
+
+        mocks.set_min_match_len(5);
+        // below threshold partial match is skipped
+        assert_eq!(mocks.get_matching_return_data(address, &calldata, U256::zero()), None);
+        // exact matches are unaffected
+        assert_eq!(
+            mocks.get_matching_return_data(address, &calldata[..4], U256::zero()),
+            Some(MockCallReturn::Return(vec![1]))
+        );
+        assert_eq!(
+            mocks.get_matching_return_data(address, &[0xaa, 0xbb, 0xcc, 0xee, 0x01], U256::zero()),
+            Some(MockCallReturn::Return(vec![2]))
+        );
+    }
+
+    #[test]
+    fn test_parsed_far_call_as_eth_balance_query() {
+        let account = H160::repeat_byte(0x1);
+        // balanceOf(uint256(uint160(account)))
+        let calldata =
+            hex::decode("9cc7f7080000000000000000000000000101010101010101010101010101010101010101")
+                .unwrap();
+        let call = |to: H160, calldata: Vec<u8>| ParsedFarCall::SimpleCall {
+            to,
+            value: U256::zero(),
+            calldata,
+            context_address: to,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+
+        assert_eq!(
+            call(L2_ETH_TOKEN_ADDRESS, calldata.clone()).as_eth_balance_query(),
+            Some(account)
+        );
+        assert_eq!(call(H160::repeat_byte(0x2), calldata.clone()).as_eth_balance_query(), None);
+        assert_eq!(
+            call(L2_ETH_TOKEN_ADDRESS, calldata[..35].to_vec()).as_eth_balance_query(),
+            None
+        );
+        let mut transfer = calldata;
+        transfer[..4].copy_from_slice(&SELECTOR_L2_ETH_TRANSFER_FROM_TO_BYTES);
+        assert_eq!(call(L2_ETH_TOKEN_ADDRESS, transfer).as_eth_balance_query(), None);
+    }
+
+    #[test]
+    fn test_selectors() {
+        for (selector, signature) in [
+            (SELECTOR_L2_ETH_BALANCE_OF_BYTES, "balanceOf(uint256)"),
+            (SELECTOR_L2_ETH_TRANSFER_FROM_TO_BYTES, "transferFromTo(address,address,uint256)"),
+            (SELECTOR_SYSTEM_CONTEXT_BLOCK_NUMBER_BYTES, "getBlockNumber()"),
+            (SELECTOR_SYSTEM_CONTEXT_BLOCK_TIMESTAMP_BYTES, "getBlockTimestamp()"),
+            (SELECTOR_SYSTEM_CONTEXT_BLOCK_HASH_EVM_BYTES, "getBlockHashEVM(uint256)"),
+            (SELECTOR_SYSTEM_CONTEXT_CHAIN_ID_BYTES, "chainId()"),
+            (SELECTOR_SYSTEM_CONTEXT_ORIGIN_BYTES, "origin()"),
+            (SELECTOR_SYSTEM_CONTEXT_BASE_FEE_BYTES, "baseFee()"),
+            (
+                SELECTOR_CONTRACT_DEPLOYER_EXTENDED_ACCOUNT_VERSION_BYTES,
+                "extendedAccountVersion(address)",
+            ),
+            (
+                SELECTOR_BOOTLOADER_EXECUTE_TRANSACTION_BYTES,
+                "executeTransaction(bytes32,bytes32,(uint256,uint256,uint256,uint256,uint256,uint256,uint256,uint256,uint256,uint256,uint256[4],bytes,bytes,bytes32[],bytes,bytes))",
+            ),
+            (SELECTOR_NONCE_HOLDER_MIN_NONCE_BYTES, "getMinNonce(address)"),
+            (SELECTOR_CONTRACT_DEPLOYER_CREATE_BYTES, "create(bytes32,bytes32,bytes)"),
+            (SELECTOR_CONTRACT_DEPLOYER_CREATE2_BYTES, "create2(bytes32,bytes32,bytes)"),
+            (
+                SELECTOR_CONTRACT_DEPLOYER_CREATE_ACCOUNT_BYTES,
+                "createAccount(bytes32,bytes32,bytes,uint8)",
+            ),
+            (
+                SELECTOR_CONTRACT_DEPLOYER_CREATE2_ACCOUNT_BYTES,
+                "create2Account(bytes32,bytes32,bytes,uint8)",
+            ),
+        ] {
+            assert_eq!(selector, alloy_primitives::keccak256(signature)[..4], "{signature}");
+        }
+
+        for (selector, selector_hex) in [
+            (SELECTOR_L2_ETH_BALANCE_OF_BYTES, SELECTOR_L2_ETH_BALANCE_OF),
+            (SELECTOR_SYSTEM_CONTEXT_BLOCK_NUMBER_BYTES, SELECTOR_SYSTEM_CONTEXT_BLOCK_NUMBER),
+            (
+                SELECTOR_SYSTEM_CONTEXT_BLOCK_TIMESTAMP_BYTES,
+                SELECTOR_SYSTEM_CONTEXT_BLOCK_TIMESTAMP,
+            ),
+            (SELECTOR_CONTRACT_DEPLOYER_CREATE_BYTES, SELECTOR_CONTRACT_DEPLOYER_CREATE),
+            (SELECTOR_CONTRACT_DEPLOYER_CREATE2_BYTES, SELECTOR_CONTRACT_DEPLOYER_CREATE2),
+        ] {
+            assert_eq!(hex::encode(selector), selector_hex);
+        }
+    }
+
+    #[test]
+    fn test_call_counter_count_matching() {
+        let address = H160::repeat_byte(0x1);
+        let mut counter = CallCounter::default();
+        counter.increment(address, vec![0xaa, 0xbb, 0x01], U256::zero());
+        counter.increment(address, vec![0xaa, 0xbb, 0x02], U256::one());
+
+        assert_eq!(counter.count_matching(address, &[0xaa, 0xbb], None), 2);
+        assert_eq!(counter.count_matching(address, &[0xaa, 0xbb, 0x02], None), 1);
+        assert_eq!(counter.count_matching(address, &[0xaa, 0xbb], Some(U256::one())), 1);
+        assert_eq!(counter.count_matching(address, &[0xcc], None), 0);
+        assert_eq!(counter.count_matching(H160::repeat_byte(0x2), &[0xaa], None), 0);
+
+        counter.clear();
+        assert_eq!(counter.count_matching(address, &[0xaa, 0xbb], None), 0);
+    }
+
+    #[test]
+    fn test_count_calls_to() {
+        let target = H160::repeat_byte(0x1);
+        let call = ParsedFarCall::SimpleCall {
+            to: target,
+            value: U256::zero(),
+            calldata: vec![0xaa, 0xbb],
+            context_address: target,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        let mut handler = FarCallHandler::default();
+        // calls are only counted once their address is registered
+        handler.on_parsed_far_call(call.clone(), CallDepth::new(1), FarCallOpcode::Normal);
+        assert_eq!(handler.count_matching_calls(target, &[], None), 0);
+
+        handler.count_calls_to(target);
+        handler.on_parsed_far_call(call.clone(), CallDepth::new(1), FarCallOpcode::Normal);
+        handler.on_parsed_far_call(call.clone(), CallDepth::new(1), FarCallOpcode::Normal);
+        assert_eq!(handler.count_matching_calls(target, &[0xaa], None), 2);
+        assert_eq!(handler.count_matching_calls(target, &[0xaa, 0xbb, 0xcc], None), 0);
+        assert_eq!(handler.count_matching_calls(target, &[], Some(U256::one())), 0);
+
+        // calls to other addresses are not counted
+        let other = H160::repeat_byte(0x2);
+        let other_call = match call {
+            ParsedFarCall::SimpleCall { calldata, .. } => ParsedFarCall::SimpleCall {
+                to: other,
+                value: U256::zero(),
+                calldata,
+                context_address: other,
+                is_constructor: false,
+                forwarding_mode: FarCallForwardingMode::UseHeap,
+                is_truncated: false,
+                packed_abi: U256::zero(),
+                ergs_passed: 0,
+                is_system_call: false,
+                kind: FarCallKind::Normal,
+                is_static: false,
+            },
+            _ => unreachable!(),
+        };
+        handler.on_parsed_far_call(other_call, CallDepth::new(1), FarCallOpcode::Normal);
+        assert_eq!(handler.count_matching_calls(other, &[], None), 0);
+    }
+
+    #[test]
+    fn test_has_parsed_far_call_consumers() {
+        let mut handler = FarCallHandler::default();
+        assert!(!handler.has_parsed_far_call_consumers());
+
+        handler.count_calls_to(H160::repeat_byte(0x1));
+        assert!(handler.has_parsed_far_call_consumers());
+        handler.reset();
+        assert!(!handler.has_parsed_far_call_consumers());
+
+        handler.override_deployment(H256::repeat_byte(0x1), H160::repeat_byte(0x2));
+        assert!(handler.has_parsed_far_call_consumers());
+        handler.clear_deployment_overrides();
+
+        handler.set_record_far_calls(true);
+        assert!(handler.has_parsed_far_call_consumers());
+        handler.set_record_far_calls(false);
+
+        handler.set_on_far_call(|_, _| {});
+        assert!(handler.has_parsed_far_call_consumers());
+        handler.take_on_far_call();
+        assert!(!handler.has_parsed_far_call_consumers());
+    }
+
+    #[test]
+    fn test_record_far_calls() {
+        let recipient = H160::repeat_byte(0x1);
+        let target = H160::repeat_byte(0x2);
+        let value_call = ParsedFarCall::ValueCall {
+            to: MSG_VALUE_SIMULATOR_ADDRESS,
+            value: U256::from(100),
+            calldata: vec![],
+            recipient,
+            is_system_call: false,
+            context_address: MSG_VALUE_SIMULATOR_ADDRESS,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        let simple_call = ParsedFarCall::SimpleCall {
+            to: target,
+            value: U256::zero(),
+            calldata: vec![0xaa],
+            context_address: target,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+
+        let mut handler = FarCallHandler::default();
+        handler.count_calls_to(target);
+        handler.on_parsed_far_call(value_call.clone(), CallDepth::new(1), FarCallOpcode::Normal);
+        assert!(handler.recorded_far_calls().is_empty());
+
+        handler.set_record_far_calls(true);
+        handler.on_parsed_far_call(value_call, CallDepth::new(1), FarCallOpcode::Normal);
+        handler.on_parsed_far_call(simple_call, CallDepth::new(2), FarCallOpcode::Mimic);
+
+        let recorded = handler.take_recorded_far_calls();
+        assert_eq!(recorded.len(), 2);
+        assert!(matches!(recorded[0].call, ParsedFarCall::ValueCall { .. }));
+        assert_eq!(recorded[0].call.to(), &MSG_VALUE_SIMULATOR_ADDRESS);
+        assert_eq!(recorded[0].call.value(), &U256::from(100));
+        assert_eq!(recorded[0].depth, CallDepth::new(1));
+        assert_eq!(recorded[0].opcode, FarCallOpcode::Normal);
+        assert!(matches!(recorded[1].call, ParsedFarCall::SimpleCall { .. }));
+        assert_eq!(recorded[1].call.to(), &target);
+        assert_eq!(recorded[1].call.value(), &U256::zero());
+        assert_eq!(recorded[1].depth, CallDepth::new(2));
+        assert_eq!(recorded[1].opcode, FarCallOpcode::Mimic);
+        assert!(handler.recorded_far_calls().is_empty());
+
+        // calls are counted regardless of recording
+        assert_eq!(handler.count_matching_calls(target, &[0xaa], None), 1);
+    }
+
+    #[test]
+    fn test_parsed_far_call_calldata_len() {
+        let value_call = |calldata: Vec<u8>| ParsedFarCall::ValueCall {
+            to: MSG_VALUE_SIMULATOR_ADDRESS,
+            value: U256::from(100),
+            calldata,
+            recipient: H160::repeat_byte(0x1),
+            is_system_call: false,
+            context_address: MSG_VALUE_SIMULATOR_ADDRESS,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+
+        let transfer = value_call(vec![]);
+        assert_eq!(transfer.calldata_len(), 0);
+        assert!(transfer.is_bare_transfer());
+
+        let selector_only = value_call(vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(selector_only.calldata_len(), 4);
+        assert!(!selector_only.is_bare_transfer());
+
+        let simple_call = |calldata: Vec<u8>| ParsedFarCall::SimpleCall {
+            to: H160::repeat_byte(0x1),
+            value: U256::zero(),
+            calldata,
+            context_address: H160::repeat_byte(0x1),
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        let full_call = simple_call([[0xde, 0xad, 0xbe, 0xef].as_slice(), &[0x1; 32]].concat());
+        assert_eq!(full_call.calldata_len(), 36);
+        assert!(!full_call.is_bare_transfer());
+
+        // calls without calldata are only transfers via the MsgValueSimulator
+        let empty_call = simple_call(vec![]);
+        assert_eq!(empty_call.calldata_len(), 0);
+        assert!(!empty_call.is_bare_transfer());
+    }
+
+    #[test]
+    fn test_parsed_far_call_recipient_and_is_system_call() {
+        let recipient = H160::repeat_byte(0x1);
+        let value_call = ParsedFarCall::ValueCall {
+            to: MSG_VALUE_SIMULATOR_ADDRESS,
+            value: U256::from(100),
+            calldata: vec![],
+            recipient,
+            is_system_call: true,
+            context_address: MSG_VALUE_SIMULATOR_ADDRESS,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        assert_eq!(value_call.recipient(), Some(recipient));
+        assert!(value_call.is_system_call());
+
+        let simple_call = ParsedFarCall::SimpleCall {
+            to: recipient,
+            value: U256::zero(),
+            calldata: vec![],
+            context_address: recipient,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        assert_eq!(simple_call.recipient(), None);
+        assert!(!simple_call.is_system_call());
+    }
+
+    #[test]
+    fn test_parsed_far_call_delegatecall_context_address() {
+        let code_address = H160::repeat_byte(0x1);
+        let caller = H160::repeat_byte(0x2);
+        let delegatecall = ParsedFarCall::SimpleCall {
+            to: code_address,
+            value: U256::zero(),
+            calldata: vec![],
+            context_address: caller,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        assert_eq!(delegatecall.to(), &code_address);
+        assert_eq!(delegatecall.context_address(), &caller);
+        assert!(format!("{delegatecall:?}").contains("context_address"));
+    }
+
+    #[test]
+    fn test_parsed_far_call_abi_flags() {
+        let constructor_call = ParsedFarCall::SimpleCall {
+            to: H160::repeat_byte(0x1),
+            value: U256::zero(),
+            calldata: vec![],
+            context_address: H160::repeat_byte(0x1),
+            is_constructor: true,
+            forwarding_mode: FarCallForwardingMode::UseAuxHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        assert!(constructor_call.is_constructor());
+        assert!(!constructor_call.is_system_call());
+        assert_eq!(constructor_call.forwarding_mode(), FarCallForwardingMode::UseAuxHeap);
+
+        let system_call = ParsedFarCall::ValueCall {
+            to: MSG_VALUE_SIMULATOR_ADDRESS,
+            value: U256::from(100),
+            calldata: vec![],
+            recipient: H160::repeat_byte(0x1),
+            is_system_call: true,
+            context_address: MSG_VALUE_SIMULATOR_ADDRESS,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::ForwardFatPointer,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        assert!(!system_call.is_constructor());
+        assert!(system_call.is_system_call());
+        assert_eq!(system_call.forwarding_mode(), FarCallForwardingMode::ForwardFatPointer);
+    }
+
+    #[test]
+    fn test_read_calldata_bounded() {
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        memory.populate_page(5, vec![(0, U256::from_big_endian(&[0xaa; 32]))], Timestamp(0));
+        let pointer = FatPointer { memory_page: 5, offset: 0, start: 0, length: u32::MAX };
+
+        let (calldata, is_truncated) = read_calldata_bounded(pointer, &memory, 36);
+        assert!(is_truncated);
+        assert_eq!(calldata, [[0xaa; 32].as_slice(), &[0; 4]].concat());
+
+        let pointer = FatPointer { length: 32, ..pointer };
+        let (calldata, is_truncated) = read_calldata_bounded(pointer, &memory, 36);
+        assert!(!is_truncated);
+        assert_eq!(calldata, [0xaa; 32]);
+    }
+
+    #[test]
+    fn test_read_calldata_bounded_into() {
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        let words = (0..4u8).map(|word| {
+            let bytes = (0..32).map(|byte| word * 32 + byte).collect::<Vec<_>>();
+            (word as usize, U256::from_big_endian(&bytes))
+        });
+        memory.populate_page(5, words.collect(), Timestamp(0));
+
+        let mut buffer = Vec::new();
+        for (start, length) in [(0, 0), (0, 128), (3, 5), (31, 2), (7, 100), (64, 96), (120, 4)] {
+            let pointer = FatPointer { memory_page: 5, offset: 0, start, length };
+            let is_truncated = read_calldata_bounded_into(pointer, &memory, 64, &mut buffer);
+            let expected = memory.read_unaligned_bytes(5, start as usize, length.min(64) as usize);
+            assert_eq!(buffer, expected, "{start} {length}");
+            assert_eq!(is_truncated, length > 64);
+        }
+
+        // the parsed calldata is unchanged when parsing into a reused buffer
+        let mut reg = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+        reg[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize] = PrimitiveValue {
+            value: FatPointer { memory_page: 5, offset: 0, start: 5, length: 70 }.to_u256(),
+            is_pointer: true,
+        };
+        let current = CallStackEntry::empty_context();
+        let config = FarCallParseConfig::default();
+        let call = parse_frame(&current, &reg, &memory, &config, FarCallKind::Normal);
+        let buffered = parse_frame_with_buffer(
+            &current,
+            &reg,
+            &memory,
+            &config,
+            FarCallKind::Normal,
+            Some(&mut buffer),
+        );
+        assert_eq!(buffered.calldata(), call.calldata());
+        assert_eq!(buffered.calldata(), (5..75).collect::<Vec<u8>>());
+        // the buffer is moved into the parsed calldata rather than cloned
+        assert!(buffer.is_empty());
+        let ptr = buffered.calldata().as_ptr();
+        buffer = buffered.into_calldata();
+        assert_eq!(buffer.as_ptr(), ptr);
+
+        // and reused once handed back by the handler, unless the call is recorded
+        let mut handler = FarCallHandler::default();
+        handler.on_parsed_far_call(call.clone(), CallDepth::new(1), FarCallOpcode::Normal);
+        assert_eq!(handler.calldata_buffer, call.calldata());
+        handler.set_record_far_calls(true);
+        handler.calldata_buffer.clear();
+        handler.on_parsed_far_call(call, CallDepth::new(1), FarCallOpcode::Normal);
+        assert!(handler.calldata_buffer.is_empty());
+        assert_eq!(handler.recorded_far_calls().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_frame_with_custom_msg_value_simulator() {
+        let simulator = H160::repeat_byte(0x51);
+        let recipient = H160::repeat_byte(0x22);
+        let config = FarCallParseConfig {
+            msg_value_simulator_address: simulator,
+            system_abi_registers_start: 4,
+            ..Default::default()
+        };
+
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        memory.populate_page(5, vec![(0, U256::from_big_endian(&[0xaa; 32]))], Timestamp(0));
+        let pointer = FatPointer { memory_page: 5, offset: 0, start: 0, length: 4 };
+
+        let mut reg = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+        reg[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize] =
+            PrimitiveValue { value: pointer.to_u256(), is_pointer: true };
+        reg[4].value = U256::from(100);
+        reg[5].value = U256::from_big_endian(recipient.as_bytes());
+        reg[6].value = U256::from(1 << MSG_VALUE_SIMULATOR_IS_SYSTEM_BIT);
+
+        let mut current = CallStackEntry::empty_context();
+        current.code_address = simulator;
+        current.this_address = simulator;
+
+        let call = parse_frame(&current, &reg, &memory, &config, FarCallKind::Normal);
+        assert_eq!(call.to(), &simulator);
+        assert_eq!(call.value(), &U256::from(100));
+        assert_eq!(call.recipient(), Some(recipient));
+        assert!(call.is_system_call());
+        assert_eq!(call.calldata(), [0xaa; 4]);
+
+        let call = parse_frame(
+            &current,
+            &reg,
+            &memory,
+            &FarCallParseConfig::default(),
+            FarCallKind::Normal,
+        );
+        assert!(matches!(call, ParsedFarCall::SimpleCall { .. }));
+    }
+
+    #[test]
+    fn test_parse_frame_zero_value_call() {
+        let recipient = H160::repeat_byte(0x22);
+        let config = FarCallParseConfig::default();
+        let memory = SimpleMemory::<HistoryDisabled>::default();
+        let pointer = FatPointer { memory_page: 5, offset: 0, start: 0, length: 0 };
+
+        let mut reg = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+        reg[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize] =
+            PrimitiveValue { value: pointer.to_u256(), is_pointer: true };
+        reg[config.address_reg()].value = U256::from_big_endian(recipient.as_bytes());
+        reg[config.is_system_reg()].value = U256::from(1 << MSG_VALUE_SIMULATOR_IS_SYSTEM_BIT);
+
+        let mut current = CallStackEntry::empty_context();
+        current.code_address = MSG_VALUE_SIMULATOR_ADDRESS;
+        current.this_address = MSG_VALUE_SIMULATOR_ADDRESS;
+
+        let call = parse_frame(&current, &reg, &memory, &config, FarCallKind::Normal);
+        assert!(matches!(call, ParsedFarCall::ValueCall { .. }));
+        assert_eq!(call.value(), &U256::zero());
+        assert!(!call.has_value());
+        assert_eq!(call.recipient(), Some(recipient));
+        assert!(call.is_system_call());
+
+        reg[config.value_reg()].value = U256::from(100);
+        let call = parse_frame(&current, &reg, &memory, &config, FarCallKind::Normal);
+        assert!(call.has_value());
+
+        // a simple call never transfers value via the MsgValueSimulator
+        current.code_address = recipient;
+        current.this_address = recipient;
+        current.context_u128_value = 100;
+        let call = parse_frame(&current, &reg, &memory, &config, FarCallKind::Mimic);
+        assert!(matches!(call, ParsedFarCall::SimpleCall { .. }));
+        assert_eq!(call.value(), &U256::from(100));
+        assert!(!call.has_value());
+        assert_eq!(call.recipient(), None);
+    }
+
+    #[test]
+    fn test_parsed_far_call_far_call_abi() {
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        memory.populate_page(5, vec![(0, U256::from_big_endian(&[0xaa; 32]))], Timestamp(0));
+        let pointer = FatPointer { memory_page: 5, offset: 0, start: 2, length: 6 };
+
+        let mut reg = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+        reg[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize] =
+            PrimitiveValue { value: pointer.to_u256(), is_pointer: true };
+
+        let current = CallStackEntry::empty_context();
+        let call = parse_frame(
+            &current,
+            &reg,
+            &memory,
+            &FarCallParseConfig::default(),
+            FarCallKind::Normal,
+        );
+        assert_eq!(call.packed_abi(), &pointer.to_u256());
+
+        let far_call_abi = call.far_call_abi();
+        let decoded = far_call_abi.memory_quasi_fat_pointer;
+        assert_eq!(
+            (decoded.memory_page, decoded.offset, decoded.start, decoded.length),
+            (5, 0, 2, 6)
+        );
+        assert_eq!(
+            FarCallForwardingMode::from(far_call_abi.forwarding_mode),
+            call.forwarding_mode()
+        );
+        assert_eq!(far_call_abi.constructor_call, call.is_constructor());
+        assert_eq!(call.calldata(), [0xaa; 6]);
+    }
+
+    #[test]
+    fn test_parse_frame_system_flagged_simple_call() {
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        memory.populate_page(5, vec![(0, U256::from_big_endian(&[0xaa; 32]))], Timestamp(0));
+        let pointer = FatPointer { memory_page: 5, offset: 0, start: 0, length: 4 };
+
+        let mut reg = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+        reg[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize] =
+            PrimitiveValue { value: pointer.to_u256(), is_pointer: true };
+
+        let mut current = CallStackEntry::empty_context();
+        current.code_address = H160::repeat_byte(0x1);
+        let call = parse_frame(
+            &current,
+            &reg,
+            &memory,
+            &FarCallParseConfig::default(),
+            FarCallKind::Normal,
+        );
+        assert!(!call.is_system_call());
+
+        // the system call flag is the most significant byte of the packed ABI
+        reg[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize].value =
+            pointer.to_u256() | (U256::one() << 248);
+        let call = parse_frame(
+            &current,
+            &reg,
+            &memory,
+            &FarCallParseConfig::default(),
+            FarCallKind::Normal,
+        );
+        assert!(matches!(call, ParsedFarCall::SimpleCall { is_system_call: true, .. }));
+        assert!(call.is_system_call());
+        assert!(call.far_call_abi().to_system);
+        assert_eq!(call.value(), &U256::zero());
+        assert_eq!(call.calldata(), [0xaa; 4]);
+        assert!(format!("{call:?}").contains("is_system_call: true"));
+    }
+
+    #[test]
+    fn test_parse_frame_static_call() {
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        memory.populate_page(5, vec![(0, U256::from_big_endian(&[0xaa; 32]))], Timestamp(0));
+        let pointer = FatPointer { memory_page: 5, offset: 0, start: 0, length: 4 };
+
+        let mut reg = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+        reg[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize] =
+            PrimitiveValue { value: pointer.to_u256(), is_pointer: true };
+
+        let mut current = CallStackEntry::empty_context();
+        current.code_address = H160::repeat_byte(0x1);
+        let call = parse_frame(
+            &current,
+            &reg,
+            &memory,
+            &FarCallParseConfig::default(),
+            FarCallKind::Normal,
+        );
+        assert!(!call.is_static());
+
+        // a staticcall enters a static frame
+        current.is_static = true;
+        let call = parse_frame(
+            &current,
+            &reg,
+            &memory,
+            &FarCallParseConfig::default(),
+            FarCallKind::Normal,
+        );
+        assert!(matches!(call, ParsedFarCall::SimpleCall { is_static: true, .. }));
+        assert!(call.is_static());
+        assert!(format!("{call:?}").contains("is_static: true"));
+
+        // the flag is also surfaced for calls via the MsgValueSimulator
+        current.code_address = MSG_VALUE_SIMULATOR_ADDRESS;
+        let call = parse_frame(
+            &current,
+            &reg,
+            &memory,
+            &FarCallParseConfig::default(),
+            FarCallKind::Normal,
+        );
+        assert!(matches!(call, ParsedFarCall::ValueCall { is_static: true, .. }));
+    }
+
+    #[test]
+    fn test_far_call_kind() {
+        assert_eq!(FarCallKind::from(FarCallOpcode::Normal), FarCallKind::Normal);
+        assert_eq!(FarCallKind::from(FarCallOpcode::Delegate), FarCallKind::Delegate);
+        assert_eq!(FarCallKind::from(FarCallOpcode::Mimic), FarCallKind::Mimic);
+
+        let mut caller = CallStackEntry::empty_context();
+        caller.this_address = H160::repeat_byte(0x1);
+        let mut callee = CallStackEntry::empty_context();
+        callee.code_address = H160::repeat_byte(0x2);
+        callee.this_address = H160::repeat_byte(0x2);
+        callee.msg_sender = caller.this_address;
+        assert_eq!(FarCallKind::infer(Some(&caller), &callee), FarCallKind::Normal);
+        assert_eq!(FarCallKind::infer(None, &callee), FarCallKind::Normal);
+
+        callee.msg_sender = H160::repeat_byte(0x3);
+        assert_eq!(FarCallKind::infer(Some(&caller), &callee), FarCallKind::Mimic);
+
+        callee.this_address = caller.this_address;
+        assert_eq!(FarCallKind::infer(Some(&caller), &callee), FarCallKind::Delegate);
+
+        let memory = SimpleMemory::<HistoryDisabled>::default();
+        let reg = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+        let call = parse_frame(
+            &callee,
+            &reg,
+            &memory,
+            &FarCallParseConfig::default(),
+            FarCallOpcode::Delegate.into(),
+        );
+        assert_eq!(call.kind(), FarCallKind::Delegate);
+        assert!(format!("{call:?}").contains("kind: Delegate"));
+    }
+
+    #[test]
+    fn test_parse_frame_without_calldata_pointer() {
+        let memory = SimpleMemory::<HistoryDisabled>::default();
+        let mut reg = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+        reg[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize].value =
+            FatPointer { memory_page: 5, offset: 0, start: 0, length: 32 }.to_u256();
+
+        let mut current = CallStackEntry::empty_context();
+        current.code_address = H160::repeat_byte(0x1);
+        let call = parse_frame(
+            &current,
+            &reg,
+            &memory,
+            &FarCallParseConfig::default(),
+            FarCallKind::Normal,
+        );
+        assert!(call.calldata().is_empty());
+        assert!(!call.is_truncated());
+        assert_eq!(call.to(), &H160::repeat_byte(0x1));
+
+        assert!(matches!(
+            try_parse_frame(
+                &current,
+                &reg,
+                &memory,
+                &FarCallParseConfig::default(),
+                FarCallKind::Normal
+            ),
+            Err(FarCallParseError::NoCalldataPointer)
+        ));
+    }
+
+    #[test]
+    fn test_parsed_far_call_ergs_passed() {
+        let memory = SimpleMemory::<HistoryDisabled>::default();
+        let mut reg = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+        reg[CALL_IMPLICIT_CALLDATA_FAT_PTR_REGISTER as usize] =
+            PrimitiveValue { value: U256::from(1_000_000u32) << 192, is_pointer: true };
+
+        let current = CallStackEntry::empty_context();
+        let call = parse_frame(
+            &current,
+            &reg,
+            &memory,
+            &FarCallParseConfig::default(),
+            FarCallKind::Normal,
+        );
+        assert_eq!(call.ergs_passed(), 1_000_000);
+        assert_eq!(call.ergs_passed(), call.far_call_abi().ergs_passed);
+        assert!(call.calldata().is_empty());
+    }
+
+    #[test]
+    fn test_parsed_far_call_params_with_short_calldata() {
+        for calldata in [vec![], vec![0xaa, 0xbb, 0xcc]] {
+            let call = ParsedFarCall::SimpleCall {
+                to: H160::zero(),
+                value: U256::zero(),
+                calldata,
+                context_address: H160::zero(),
+                is_constructor: false,
+                forwarding_mode: FarCallForwardingMode::UseHeap,
+                is_truncated: false,
+                packed_abi: U256::zero(),
+                ergs_passed: 0,
+                is_system_call: false,
+                kind: FarCallKind::Normal,
+                is_static: false,
+            };
+            assert_eq!(call.selector(), "");
+            assert_eq!(call.selector_bytes(), None);
+            assert!(call.params().is_empty());
+            assert!(call.param_bytes_after(0).is_empty());
+            assert!(call.param_bytes_after(1).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_encode_return() {
+        let encoded = encode_return(&[U256::from(0x1234)]);
+        assert_eq!(encoded.len(), 32);
+        assert_eq!(U256::from_big_endian(&encoded), U256::from(0x1234));
+        assert_eq!(&encoded[30..], &[0x12, 0x34]);
+
+        assert!(encode_return(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_encode_return_bytes() {
+        let data = [0xaa; 33];
+        let encoded = encode_return_bytes(&data);
+        assert_eq!(encoded.len(), 128);
+        assert_eq!(U256::from_big_endian(&encoded[0..32]), U256::from(32));
+        assert_eq!(U256::from_big_endian(&encoded[32..64]), U256::from(33));
+        assert_eq!(&encoded[64..97], &data);
+        assert!(encoded[97..].iter().all(|byte| *byte == 0));
+        assert_eq!(decode_abi_bytes(&encoded, 0), Some(data.to_vec()));
+
+        assert_eq!(encode_return_bytes(&[]), encode_return(&[U256::from(32), U256::zero()]));
+    }
+
+    #[test]
+    fn test_parsed_far_call_selector_bytes() {
+        let call = ParsedFarCall::SimpleCall {
+            to: H160::zero(),
+            value: U256::zero(),
+            calldata: vec![0x9c, 0xc7, 0xf7, 0x08, 0x01],
+            context_address: H160::zero(),
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        assert_eq!(call.selector_bytes(), Some(SELECTOR_L2_ETH_BALANCE_OF_BYTES));
+        assert_eq!(hex::encode(call.selector_bytes().unwrap()), call.selector());
+    }
+
+    #[test]
+    fn test_parsed_far_call_try_params() {
+        let mut calldata = vec![0xaa; 4];
+        calldata.extend_from_slice(&[0x1; 32]);
+        let call = ParsedFarCall::SimpleCall {
+            to: H160::zero(),
+            value: U256::zero(),
+            calldata: calldata.clone(),
+            context_address: H160::zero(),
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        assert_eq!(call.try_params(), Ok(vec![[0x1; 32]]));
+        assert_eq!(call.params(), vec![[0x1; 32]]);
+
+        calldata.extend_from_slice(&[0x2; 4]);
+        let call = ParsedFarCall::SimpleCall {
+            to: H160::zero(),
+            value: U256::zero(),
+            calldata,
+            context_address: H160::zero(),
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        assert_eq!(call.try_params(), Err(FarCallParseError::UnalignedParams(36)));
+
+        let mut padded = [0u8; 32];
+        padded[..4].copy_from_slice(&[0x2; 4]);
+        assert_eq!(call.params(), vec![[0x1; 32], padded]);
+    }
+
+    #[test]
+    fn test_short_circuit_rule() {
+        let target = H160::repeat_byte(0x1);
+        let call = |to: H160, selector: [u8; 4]| ParsedFarCall::SimpleCall {
+            to,
+            value: U256::zero(),
+            calldata: selector.to_vec(),
+            context_address: to,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.add_short_circuit_rule(ShortCircuitRule::new(
+            move |call| call.to() == &target && call.selector_bytes() == Some([0xaa; 4]),
+            MockCallReturn::Return(vec![1]),
+        ));
+
+        // non-matching calls execute as usual
+        handler.on_parsed_far_call(
+            call(target, [0xbb; 4]),
+            CallDepth::next(),
+            FarCallOpcode::Normal,
+        );
+        handler.on_parsed_far_call(
+            call(H160::repeat_byte(0x2), [0xaa; 4]),
+            CallDepth::next(),
+            FarCallOpcode::Normal,
+        );
+        assert!(handler.immediate_return.is_none());
+
+        for _ in 0..2 {
+            handler.on_parsed_far_call(
+                call(target, [0xaa; 4]),
+                CallDepth::next(),
+                FarCallOpcode::Normal,
+            );
+            let immediate_return =
+                handler.immediate_return.take().expect("immediate return must be set");
+            assert!(!immediate_return.is_revert);
+            assert_eq!(immediate_return.return_data, vec![1]);
+        }
+
+        // the last registered matching rule applies
+        handler.add_short_circuit_rule(ShortCircuitRule::new(
+            move |call| call.to() == &target,
+            MockCallReturn::Revert(vec![2]),
+        ));
+        handler.on_parsed_far_call(
+            call(target, [0xaa; 4]),
+            CallDepth::next(),
+            FarCallOpcode::Normal,
+        );
+        let immediate_return =
+            handler.immediate_return.take().expect("immediate return must be set");
+        assert!(immediate_return.is_revert);
+        assert_eq!(immediate_return.return_data, vec![2]);
+
+        handler.clear_short_circuit_rules();
+        handler.on_parsed_far_call(
+            call(target, [0xaa; 4]),
+            CallDepth::next(),
+            FarCallOpcode::Normal,
+        );
+        assert!(handler.immediate_return.is_none());
+    }
+
+    #[test]
+    fn test_mock_call() {
+        let target = H160::repeat_byte(0x1);
+        let call = |to: H160, selector: [u8; 4]| ParsedFarCall::SimpleCall {
+            to,
+            value: U256::zero(),
+            calldata: selector.to_vec(),
+            context_address: to,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        assert!(!handler.has_parsed_far_call_consumers());
+        handler.mock_call(
+            MockCall { calldata: CalldataMatch::Prefix(vec![0xaa; 4]), ..MockCall::new(target) },
+            MockCallReturn::Revert(vec![1]),
+            Default::default(),
+        );
+        assert!(handler.has_parsed_far_call_consumers());
+
+        handler.on_parsed_far_call(
+            call(target, [0xbb; 4]),
+            CallDepth::next(),
+            FarCallOpcode::Normal,
+        );
+        assert!(handler.immediate_return.is_none());
+
+        handler.on_parsed_far_call(
+            call(target, [0xaa; 4]),
+            CallDepth::next(),
+            FarCallOpcode::Normal,
+        );
+        let immediate_return =
+            handler.immediate_return.take().expect("immediate return must be set");
+        assert!(immediate_return.is_revert);
+        assert_eq!(immediate_return.return_data, vec![1]);
+    }
+
+    #[test]
+    fn test_mock_call_return_data_start() {
+        let target = H160::repeat_byte(0x1);
+        let calldata_region = FatPointer { memory_page: 0, offset: 4, start: 96, length: 4 };
+        let call = ParsedFarCall::SimpleCall {
+            to: target,
+            value: U256::zero(),
+            calldata: vec![0xaa; 4],
+            context_address: target,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: calldata_region.to_u256(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        let return_data = [[0x1; WORD_SIZE], [0x2; WORD_SIZE]].concat();
+
+        // the return data is written past the word the caller's calldata region starts in
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.mock_call(
+            MockCall::new(target),
+            MockCallReturn::Return(return_data.clone()),
+            Default::default(),
+        );
+        handler.on_parsed_far_call(call.clone(), CallDepth::next(), FarCallOpcode::Normal);
+        let immediate_return =
+            handler.immediate_return.take().expect("immediate return must be set");
+        let return_fat_ptr = immediate_return.return_fat_pointer();
+        assert_eq!((return_fat_ptr.start, return_fat_ptr.length), (128, 64));
+        // only the words of the return data are written, leaving the words before it intact
+        assert_eq!(
+            immediate_return.return_data_words().into_iter().map(|(word, _)| word).collect_vec(),
+            vec![4, 5]
+        );
+        let mut memory = SimpleMemory::<HistoryDisabled>::default();
+        memory.populate_page(
+            return_fat_ptr.memory_page as usize,
+            immediate_return.return_data_words(),
+            Timestamp(0),
+        );
+        assert_eq!(read_fat_pointer_bytes(return_fat_ptr, &memory), return_data);
+
+        // an explicit start takes precedence
+        handler.mock_call(
+            MockCall::new(target),
+            MockCallReturn::Return(return_data),
+            ImmediateReturnOptions { return_data_start: Some(32), ..Default::default() },
+        );
+        handler.on_parsed_far_call(call, CallDepth::next(), FarCallOpcode::Normal);
+        let immediate_return =
+            handler.immediate_return.take().expect("immediate return must be set");
+        assert_eq!(immediate_return.return_fat_pointer().start, 32);
+    }
+
+    #[test]
+    fn test_deployment_override() {
+        let bytecode_hash = H256::repeat_byte(0x1);
+        let deployed = H160::repeat_byte(0x2);
+        let deployment = |selector: [u8; 4], bytecode_hash: H256| ParsedFarCall::SimpleCall {
+            to: CONTRACT_DEPLOYER_ADDRESS,
+            value: U256::zero(),
+            calldata: [
+                selector.as_slice(),
+                H256::zero().as_bytes(),
+                bytecode_hash.as_bytes(),
+                U256::from(0x60).to_h256().as_bytes(),
+                H256::zero().as_bytes(),
+            ]
+            .concat(),
+            context_address: CONTRACT_DEPLOYER_ADDRESS,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: true,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+
+        for selector in
+            [SELECTOR_CONTRACT_DEPLOYER_CREATE_BYTES, SELECTOR_CONTRACT_DEPLOYER_CREATE2_BYTES]
+        {
+            let mut handler = far_call_handler(FarCallOpcode::Normal);
+            handler.override_deployment(bytecode_hash, deployed);
+
+            // other bytecode hashes are deployed as usual
+            handler.on_parsed_far_call(
+                deployment(selector, H256::repeat_byte(0x3)),
+                CallDepth::next(),
+                FarCallOpcode::Normal,
+            );
+            assert!(handler.immediate_return.is_none());
+
+            handler.on_parsed_far_call(
+                deployment(selector, bytecode_hash),
+                CallDepth::next(),
+                FarCallOpcode::Normal,
+            );
+            let immediate_return = handler.immediate_return.expect("immediate return must be set");
+            assert!(!immediate_return.is_revert);
+
+            let mut memory = SimpleMemory::<HistoryDisabled>::default();
+            let mut registers = [PrimitiveValue { value: U256::zero(), is_pointer: false }; 16];
+            immediate_return.write_return_data(&mut registers, &mut memory, Timestamp(0));
+            let return_fat_ptr = FatPointer::from_u256(
+                registers[RET_IMPLICIT_RETURNDATA_PARAMS_REGISTER as usize].value,
+            );
+            let return_data = read_fat_pointer_bytes(return_fat_ptr, &memory);
+            assert_eq!(H256::from_slice(&return_data).to_h160(), deployed);
+        }
+
+        let mut handler = far_call_handler(FarCallOpcode::Normal);
+        handler.override_deployment(bytecode_hash, deployed);
+        handler.clear_deployment_overrides();
+        handler.on_parsed_far_call(
+            deployment(SELECTOR_CONTRACT_DEPLOYER_CREATE_BYTES, bytecode_hash),
+            CallDepth::next(),
+            FarCallOpcode::Normal,
+        );
+        assert!(handler.immediate_return.is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_parsed_far_call_serde_round_trip() {
+        let calls = [
+            ParsedFarCall::ValueCall {
+                to: MSG_VALUE_SIMULATOR_ADDRESS,
+                value: U256::from(100),
+                calldata: vec![0xde, 0xad, 0xbe, 0xef],
+                recipient: H160::repeat_byte(0x1),
+                is_system_call: true,
+                context_address: MSG_VALUE_SIMULATOR_ADDRESS,
+                is_constructor: false,
+                forwarding_mode: FarCallForwardingMode::ForwardFatPointer,
+                is_truncated: false,
+                packed_abi: U256::from(42),
+                ergs_passed: 1000,
+                kind: FarCallKind::Normal,
+                is_static: false,
+            },
+            ParsedFarCall::SimpleCall {
+                to: H160::repeat_byte(0x2),
+                value: U256::zero(),
+                calldata: vec![],
+                context_address: H160::repeat_byte(0x3),
+                is_constructor: true,
+                forwarding_mode: FarCallForwardingMode::UseAuxHeap,
+                is_truncated: true,
+                packed_abi: U256::zero(),
+                ergs_passed: 0,
+                is_system_call: false,
+                kind: FarCallKind::Delegate,
+                is_static: false,
+            },
+        ];
+
+        for call in calls {
+            let json = serde_json::to_value(&call).unwrap();
+            let decoded: ParsedFarCall = serde_json::from_value(json.clone()).unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{call:?}"));
+            assert_eq!(serde_json::to_value(&decoded).unwrap(), json);
+        }
+
+        let json = serde_json::to_value(&ParsedFarCall::SimpleCall {
+            to: H160::zero(),
+            value: U256::zero(),
+            calldata: vec![0xde, 0xad],
+            context_address: H160::zero(),
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Mimic,
+            is_static: false,
+        })
+        .unwrap();
+        assert_eq!(json["type"], "SimpleCall");
+        assert_eq!(json["calldata"], "0xdead");
+        assert_eq!(json["forwarding_mode"], "UseHeap");
+        assert_eq!(json["kind"], "Mimic");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_mock_call_serde_round_trip() {
+        let calls = [
+            MockCall::default(),
+            MockCall {
+                address: Some(H160::repeat_byte(0x1)),
+                value: ValueMatch::Exact(U256::from(5)),
+                calldata: CalldataMatch::Prefix(vec![0xaa, 0xbb]),
+                caller: Some(H160::repeat_byte(0x2)),
+                max_depth: Some(CallDepth::next()),
+            },
+            MockCall { calldata: CalldataMatch::Any, ..Default::default() },
+        ];
+
+        for call in calls {
+            let json = serde_json::to_string(&call).unwrap();
+            let decoded: MockCall = serde_json::from_str(&json).unwrap();
+            assert_eq!(decoded, call);
+        }
+    }
+
+    #[test]
+    fn test_parsed_far_call_as_account_creation() {
+        let create_account = ParsedFarCall::SimpleCall {
+            to: CONTRACT_DEPLOYER_ADDRESS,
+            value: U256::zero(),
+            calldata: hex::decode(concat!(
+                "ecf95b8a",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "010000795b8b1dd4d6bc1d5a4b3b2e1df29ae4b9a7e1e8c7d3b5fa5de0f9a8c1",
+                "0000000000000000000000000000000000000000000000000000000000000080",
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "0000000000000000000000000000000000000000000000000000000000000020",
+                "000000000000000000000000bc989fde9e54cad2ab4392af6df60f04873a033a",
+            ))
+            .unwrap(),
+            context_address: CONTRACT_DEPLOYER_ADDRESS,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: true,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        let creation = create_account.as_account_creation().expect("must decode createAccount");
+        assert_eq!(creation.kind, DeploymentKind::Create);
+        assert_eq!(creation.salt, H256::zero());
+        assert_eq!(
+            creation.bytecode_hash,
+            H256::from_slice(
+                &hex::decode("010000795b8b1dd4d6bc1d5a4b3b2e1df29ae4b9a7e1e8c7d3b5fa5de0f9a8c1")
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            creation.constructor_input,
+            hex::decode("000000000000000000000000bc989fde9e54cad2ab4392af6df60f04873a033a")
+                .unwrap()
+        );
+        assert_eq!(creation.aa_version, AccountAbstractionVersion::Version1);
+        assert!(create_account.as_deployment().is_none());
+
+        let mut calldata = create_account.calldata().to_vec();
+        calldata[..4].copy_from_slice(&SELECTOR_CONTRACT_DEPLOYER_CREATE2_ACCOUNT_BYTES);
+        calldata[4 + 3 * 32 + 31] = 0;
+        let create2_account = ParsedFarCall::ValueCall {
+            to: MSG_VALUE_SIMULATOR_ADDRESS,
+            value: U256::from(1),
+            calldata: calldata.clone(),
+            recipient: CONTRACT_DEPLOYER_ADDRESS,
+            is_system_call: true,
+            context_address: MSG_VALUE_SIMULATOR_ADDRESS,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        let creation = create2_account.as_account_creation().expect("must decode create2Account");
+        assert_eq!(creation.kind, DeploymentKind::Create2);
+        assert_eq!(creation.aa_version, AccountAbstractionVersion::None);
+
+        // unknown account abstraction versions are rejected
+        calldata[4 + 3 * 32 + 31] = 2;
+        let unknown_version = ParsedFarCall::SimpleCall {
+            to: CONTRACT_DEPLOYER_ADDRESS,
+            value: U256::zero(),
+            calldata,
+            context_address: CONTRACT_DEPLOYER_ADDRESS,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: true,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        assert!(unknown_version.as_account_creation().is_none());
+    }
+
+    #[test]
+    fn test_parsed_far_call_as_deployment() {
+        let create = ParsedFarCall::SimpleCall {
+            to: CONTRACT_DEPLOYER_ADDRESS,
+            value: U256::zero(),
+            calldata: hex::decode(concat!(
+                "9c4d535b",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "0100000f6d092b2cd44547a312320ad99c9587b40e0d03b0c17f09afd286d660",
+                "0000000000000000000000000000000000000000000000000000000000000060",
+                "0000000000000000000000000000000000000000000000000000000000000020",
+                "000000000000000000000000000000000000000000000000000000000000000a",
+            ))
+            .unwrap(),
+            context_address: CONTRACT_DEPLOYER_ADDRESS,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        let deployment = create.as_deployment().expect("must decode create");
+        assert_eq!(deployment.kind, DeploymentKind::Create);
+        assert_eq!(deployment.salt, H256::zero());
+        assert_eq!(
+            deployment.bytecode_hash,
+            H256::from_slice(
+                &hex::decode("0100000f6d092b2cd44547a312320ad99c9587b40e0d03b0c17f09afd286d660")
+                    .unwrap()
+            )
+        );
+        assert_eq!(deployment.constructor_input, U256::from(10).to_h256().as_bytes().to_vec());
+
+        let create2 = ParsedFarCall::ValueCall {
+            to: MSG_VALUE_SIMULATOR_ADDRESS,
+            value: U256::from(1),
+            calldata: hex::decode(concat!(
+                "3cda3351",
+                "00000000000000000000000000000000000000000000000000000000000000ff",
+                "0100000f6d092b2cd44547a312320ad99c9587b40e0d03b0c17f09afd286d660",
+                "0000000000000000000000000000000000000000000000000000000000000060",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+            ))
+            .unwrap(),
+            recipient: CONTRACT_DEPLOYER_ADDRESS,
+            is_system_call: true,
+            context_address: MSG_VALUE_SIMULATOR_ADDRESS,
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        let deployment = create2.as_deployment().expect("must decode create2");
+        assert_eq!(deployment.kind, DeploymentKind::Create2);
+        assert_eq!(deployment.salt, H256::from_low_u64_be(0xff));
+        assert!(deployment.constructor_input.is_empty());
+
+        let not_deployer = ParsedFarCall::SimpleCall {
+            to: H160::repeat_byte(0x1),
+            value: U256::zero(),
+            calldata: create.calldata().to_vec(),
+            context_address: H160::repeat_byte(0x1),
+            is_constructor: false,
+            forwarding_mode: FarCallForwardingMode::UseHeap,
+            is_truncated: false,
+            packed_abi: U256::zero(),
+            ergs_passed: 0,
+            is_system_call: false,
+            kind: FarCallKind::Normal,
+            is_static: false,
+        };
+        assert_eq!(not_deployer.as_deployment(), None);
+
+        let truncated = ParsedFarCall::SimpleCall {
+            to: CONTRACT_DEPLOYER_ADDRESS,
+            value: U256::zero(),
+            calldata: create.calldata()[..4 + 32 * 4].to_vec(),
+        };
+        assert_eq!(truncated.as_deployment(), None);
     }
 }
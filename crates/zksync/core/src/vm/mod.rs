@@ -5,5 +5,22 @@ mod runner;
 mod storage_view;
 mod tracer;
 
+pub use farcall::{
+    encode_panic, encode_return, encode_return_bytes, encode_revert_reason, parse,
+    parse_with_config, parse_with_max_calldata_len, return_heap_page, try_parse,
+    AccountAbstractionVersion, AccountCreationCall, DeploymentCall, DeploymentKind,
+    FarCallForwardingMode, FarCallKind, FarCallParseConfig, FarCallParseError, ParsedFarCall,
+    DEFAULT_MAX_CALLDATA_LEN, SELECTOR_BOOTLOADER_EXECUTE_TRANSACTION_BYTES,
+    SELECTOR_CONTRACT_DEPLOYER_CREATE, SELECTOR_CONTRACT_DEPLOYER_CREATE2,
+    SELECTOR_CONTRACT_DEPLOYER_CREATE2_ACCOUNT_BYTES, SELECTOR_CONTRACT_DEPLOYER_CREATE2_BYTES,
+    SELECTOR_CONTRACT_DEPLOYER_CREATE_ACCOUNT_BYTES, SELECTOR_CONTRACT_DEPLOYER_CREATE_BYTES,
+    SELECTOR_CONTRACT_DEPLOYER_EXTENDED_ACCOUNT_VERSION_BYTES, SELECTOR_L2_ETH_BALANCE_OF,
+    SELECTOR_L2_ETH_BALANCE_OF_BYTES, SELECTOR_L2_ETH_TRANSFER_FROM_TO_BYTES,
+    SELECTOR_NONCE_HOLDER_MIN_NONCE_BYTES, SELECTOR_SYSTEM_CONTEXT_BASE_FEE_BYTES,
+    SELECTOR_SYSTEM_CONTEXT_BLOCK_HASH_EVM_BYTES, SELECTOR_SYSTEM_CONTEXT_BLOCK_NUMBER,
+    SELECTOR_SYSTEM_CONTEXT_BLOCK_NUMBER_BYTES, SELECTOR_SYSTEM_CONTEXT_BLOCK_TIMESTAMP,
+    SELECTOR_SYSTEM_CONTEXT_BLOCK_TIMESTAMP_BYTES, SELECTOR_SYSTEM_CONTEXT_CHAIN_ID_BYTES,
+    SELECTOR_SYSTEM_CONTEXT_ORIGIN_BYTES,
+};
 pub use runner::{balance, call, code_hash, create, encode_create_params, nonce, transact};
 pub use tracer::CheatcodeTracerContext;
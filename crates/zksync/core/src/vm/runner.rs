@@ -106,6 +106,8 @@ where
         msg_sender: env.tx.caller,
         contract: transact_to.to_address(),
         delegate_as: None,
+        msg_value: None,
+        tx_origin: None,
         block_number: env.block.number,
         block_timestamp: env.block.timestamp,
         block_basefee: min(max_fee_per_gas.to_ru256(), env.block.basefee),
@@ -200,6 +202,8 @@ where
         msg_sender: call.caller,
         contract: CONTRACT_DEPLOYER_ADDRESS.to_address(),
         delegate_as: None,
+        msg_value: None,
+        tx_origin: None,
         block_number: env.block.number,
         block_timestamp: env.block.timestamp,
         block_basefee: min(max_fee_per_gas.to_ru256(), env.block.basefee),
@@ -253,6 +257,8 @@ where
             CallScheme::DelegateCall => Some(call.context.address),
             _ => None,
         },
+        msg_value: ccx.call_value,
+        tx_origin: ccx.tx_origin,
         block_number: env.block.number,
         block_timestamp: env.block.timestamp,
         block_basefee: min(max_fee_per_gas.to_ru256(), env.block.basefee),
@@ -517,6 +523,11 @@ fn inspect_inner<S: ReadStorage + Send>(
     if let Some(expected_calls) = ccx.expected_calls.as_mut() {
         expected_calls.extend(cheatcode_result.expected_calls);
     }
+    // mocked calls return without executing, so the VM does not record their events
+    for (index, (position, event)) in cheatcode_result.mocked_events.into_iter().enumerate() {
+        let position = (position + index).min(tx_result.logs.events.len());
+        tx_result.logs.events.insert(position, event);
+    }
 
     formatter::print_vm_details(&tx_result);
 
@@ -3,7 +3,7 @@ use std::{
     sync::Arc,
 };
 
-use alloy_primitives::{hex, Address, Bytes, U256 as rU256};
+use alloy_primitives::{Address, U256 as rU256};
 use foundry_cheatcodes_common::{
     expect::ExpectedCallTracker,
     mock::{MockCallDataContext, MockCallReturnData},
@@ -18,49 +18,26 @@ use multivm::{
     },
 };
 use once_cell::sync::OnceCell;
+use revm::interpreter::InstructionResult;
 use zksync_state::WriteStorage;
 use zksync_types::{
-    BOOTLOADER_ADDRESS, CONTRACT_DEPLOYER_ADDRESS, H256, SYSTEM_CONTEXT_ADDRESS, U256,
+    VmEvent, BOOTLOADER_ADDRESS, CONTRACT_DEPLOYER_ADDRESS, H256, SYSTEM_CONTEXT_ADDRESS,
 };
 
 use crate::{
-    convert::{ConvertAddress, ConvertH160, ConvertH256, ConvertU256},
-    vm::farcall::{CallAction, CallDepth},
+    convert::{ConvertAddress, ConvertH160, ConvertH256, ConvertRU256},
+    vm::farcall::{
+        CallAction, CallDepth, CalldataMatch, MockCall, MockCallReturn, MockedLog, ValueMatch,
+    },
 };
 
-use super::farcall::FarCallHandler;
-
-/// Selector for retrieving account version.
-/// This is used to override the caller's account version when deploying a contract
-/// So non-EOA addresses can also deploy within the VM.
-///
-/// extendedAccountVersion(address)
-const SELECTOR_ACCOUNT_VERSION: [u8; 4] = hex!("bb0fd610");
-
-/// Selector for  executing a VM transaction.
-/// This is used to override the `msg.sender` for the call context
-/// to account for transitive calls.
-///
-/// executeTransaction(bytes32, bytes32, tuple)
-const SELECTOR_EXECUTE_TRANSACTION: [u8; 4] = hex!("df9c1589");
-
-/// Selector for retrieving the current block number.
-/// This is used to override the current `block.number` to foundry test's context.
-///
-/// Selector for `getBlockNumber()`
-const SELECTOR_SYSTEM_CONTEXT_BLOCK_NUMBER: [u8; 4] = hex!("42cbb15c");
-
-/// Selector for retrieving the current block timestamp.
-/// This is used to override the current `block.timestamp` to foundry test's context.
-///
-/// Selector for `getBlockTimestamp()`
-const SELECTOR_SYSTEM_CONTEXT_BLOCK_TIMESTAMP: [u8; 4] = hex!("796b89b9");
-
-/// Selector for retrieving the current base fee.
-/// This is used to override the current `block.basefee` to foundry test's context.
-///
-/// Selector for `baseFee()`
-const SELECTOR_BASE_FEE: [u8; 4] = hex!("6ef25c3a");
+use super::farcall::{
+    FarCallHandler, ImmediateReturnMode, ImmediateReturnOptions,
+    SELECTOR_BOOTLOADER_EXECUTE_TRANSACTION_BYTES,
+    SELECTOR_CONTRACT_DEPLOYER_EXTENDED_ACCOUNT_VERSION_BYTES,
+    SELECTOR_SYSTEM_CONTEXT_BASE_FEE_BYTES, SELECTOR_SYSTEM_CONTEXT_BLOCK_NUMBER_BYTES,
+    SELECTOR_SYSTEM_CONTEXT_BLOCK_TIMESTAMP_BYTES, SELECTOR_SYSTEM_CONTEXT_ORIGIN_BYTES,
+};
 
 /// Represents the context for [CheatcodeContext]
 #[derive(Debug, Default)]
@@ -73,12 +50,19 @@ pub struct CheatcodeTracerContext<'a> {
     pub accesses: Option<&'a mut RecordAccess>,
     /// Factory deps that were persisted across calls
     pub persisted_factory_deps: HashMap<H256, Vec<u8>>,
+    /// Overridden `msg.value` for the call, without transferring it
+    pub call_value: Option<rU256>,
+    /// Overridden `tx.origin` for the called frame, without affecting its nested calls
+    pub tx_origin: Option<Address>,
 }
 
 /// Tracer result to return back to foundry.
 #[derive(Debug, Default)]
 pub struct CheatcodeTracerResult {
     pub expected_calls: ExpectedCallTracker,
+    /// Events emitted by mocked calls, which the VM does not record, alongside the number of
+    /// the VM's events preceding them.
+    pub mocked_events: Vec<(usize, VmEvent)>,
 }
 
 /// Defines the context for a Vm call.
@@ -93,6 +77,10 @@ pub struct CallContext {
     /// Delegated contract's address. This is used
     /// to override `address(this)` for delegate calls.
     pub delegate_as: Option<Address>,
+    /// Overridden value for `msg.value`.
+    pub msg_value: Option<rU256>,
+    /// Overridden value for `tx.origin`, as seen by the called frame only.
+    pub tx_origin: Option<Address>,
 
     /// The current block number
     pub block_number: rU256,
@@ -105,8 +93,6 @@ pub struct CallContext {
 /// A tracer to allow for foundry-specific functionality.
 #[derive(Debug, Default)]
 pub struct CheatcodeTracer {
-    /// List of mocked calls.
-    pub mocked_calls: HashMap<Address, BTreeMap<MockCallDataContext, MockCallReturnData>>,
     /// Tracked for foundry's expected calls.
     pub expected_calls: ExpectedCallTracker,
     /// Defines the current call context.
@@ -125,7 +111,58 @@ impl CheatcodeTracer {
         result: Arc<OnceCell<CheatcodeTracerResult>>,
         call_context: CallContext,
     ) -> Self {
-        CheatcodeTracer { mocked_calls, expected_calls, call_context, result, ..Default::default() }
+        let mut farcall_handler = FarCallHandler::default();
+        for (address, mocks) in mocked_calls {
+            for (ctx, return_data) in mocks {
+                let call = MockCall {
+                    value: ctx
+                        .value
+                        .map_or(ValueMatch::Any, |value| ValueMatch::Exact(value.to_u256())),
+                    // foundry matches all calls to the address for empty calldata
+                    calldata: if ctx.calldata.is_empty() {
+                        CalldataMatch::Any
+                    } else {
+                        CalldataMatch::Prefix(ctx.calldata.to_vec())
+                    },
+                    ..MockCall::new(address)
+                };
+                let mock_return = if return_data.ret_type == InstructionResult::Revert {
+                    MockCallReturn::Revert(return_data.data.to_vec())
+                } else {
+                    MockCallReturn::Return(return_data.data.to_vec())
+                };
+                let logs = return_data
+                    .logs
+                    .iter()
+                    .map(|log| MockedLog {
+                        topics: log.topics().iter().map(|topic| H256::from(topic.0)).collect(),
+                        data: log.data.to_vec(),
+                    })
+                    .collect();
+                let mode = if return_data.execute {
+                    ImmediateReturnMode::OverrideOnReturn
+                } else {
+                    ImmediateReturnMode::SkipExecution
+                };
+                farcall_handler.mock_call(
+                    call,
+                    mock_return,
+                    ImmediateReturnOptions { logs, mode, ..Default::default() },
+                );
+            }
+        }
+        // the counts are verified against the expectations once the test ends
+        for address in expected_calls.keys() {
+            farcall_handler.count_calls_to(address.to_h160());
+        }
+
+        CheatcodeTracer {
+            expected_calls,
+            call_context,
+            result,
+            farcall_handler,
+            ..Default::default()
+        }
     }
 }
 
@@ -157,62 +194,13 @@ impl<S: Send, H: HistoryMode> DynTracer<S, SimpleMemory<H>> for CheatcodeTracer
         memory: &SimpleMemory<H>,
         _storage: zksync_state::StoragePtr<S>,
     ) {
-        self.farcall_handler.track_after_far_calls(&state, &data);
+        self.farcall_handler.track_after_far_calls(&state, &data, memory);
         self.farcall_handler.track_call_actions(&state, &data);
+        self.farcall_handler.track_parsed_far_calls(&state, &data, memory);
 
-        // Checks contract calls for expectCall cheatcode
-        if let Opcode::FarCall(_call) = data.opcode.variant.opcode {
-            let current = state.vm_local_state.callstack.current;
-            if let Some(expected_calls_for_target) =
-                self.expected_calls.get_mut(&current.code_address.to_address())
-            {
-                let calldata = get_calldata(&state, memory);
-                // Match every partial/full calldata
-                for (expected_calldata, (expected, actual_count)) in expected_calls_for_target {
-                    // Increment actual times seen if...
-                    // The calldata is at most, as big as this call's input, and
-                    if expected_calldata.len() <= calldata.len() &&
-                    // Both calldata match, taking the length of the assumed smaller one (which will have at least the selector), and
-                    *expected_calldata == calldata[..expected_calldata.len()] &&
-                    // The value matches, if provided
-                    expected
-                        .value
-                        .map_or(true, |value|{
-                             value == rU256::from(current.context_u128_value)})
-                    {
-                        *actual_count += 1;
-                    }
-                }
-            }
-        }
-
-        // Handle mocked calls
-        if let Opcode::FarCall(_call) = data.opcode.variant.opcode {
-            let current = state.vm_local_state.callstack.current;
-            let call_input = get_calldata(&state, memory);
-            let call_contract = current.code_address.to_address();
-            let call_value = U256::from(current.context_u128_value).to_ru256();
-
-            if let Some(mocks) = self.mocked_calls.get(&call_contract) {
-                let ctx = MockCallDataContext {
-                    calldata: Bytes::from(call_input.clone()),
-                    value: Some(call_value),
-                };
-                if let Some(return_data) = mocks.get(&ctx).or_else(|| {
-                    mocks
-                        .iter()
-                        .find(|(mock, _)| {
-                            call_input.get(..mock.calldata.len()) == Some(&mock.calldata[..]) &&
-                                mock.value.map_or(true, |value| value == call_value)
-                        })
-                        .map(|(_, v)| v)
-                }) {
-                    let return_data = return_data.data.clone().to_vec();
-                    tracing::info!("returning mocked value {:?}", hex::encode(&return_data));
-                    self.farcall_handler.set_immediate_return(return_data);
-                    return;
-                }
-            }
+        // Mocked calls return early, see [FarCallHandler::mock_call]
+        if self.farcall_handler.peek_immediate_return().is_some() {
+            return
         }
 
         // Mark the caller as EOA to avoid panic. This is probably not needed anymore
@@ -223,12 +211,14 @@ impl<S: Send, H: HistoryMode> DynTracer<S, SimpleMemory<H>> for CheatcodeTracer
             let calldata = get_calldata(&state, memory);
 
             if current.code_address == CONTRACT_DEPLOYER_ADDRESS &&
-                calldata.starts_with(&SELECTOR_ACCOUNT_VERSION)
+                calldata.starts_with(&SELECTOR_CONTRACT_DEPLOYER_EXTENDED_ACCOUNT_VERSION_BYTES)
             {
                 let address = H256::from_slice(&calldata[4..36]).to_h160().to_address();
                 if self.call_context.tx_caller == address {
                     tracing::debug!("overriding account version for caller {address:?}");
-                    self.farcall_handler.set_immediate_return(rU256::from(1u32).to_be_bytes_vec());
+                    let _ = self
+                        .farcall_handler
+                        .set_immediate_return(rU256::from(1u32).to_be_bytes_vec());
                     return
                 }
             }
@@ -240,12 +230,20 @@ impl<S: Send, H: HistoryMode> DynTracer<S, SimpleMemory<H>> for CheatcodeTracer
             let current = state.vm_local_state.callstack.current;
 
             if current.msg_sender == BOOTLOADER_ADDRESS &&
-                calldata.starts_with(&SELECTOR_EXECUTE_TRANSACTION)
+                calldata.starts_with(&SELECTOR_BOOTLOADER_EXECUTE_TRANSACTION_BYTES)
             {
                 self.farcall_handler.set_action(
                     CallDepth::next(),
                     CallAction::SetMessageSender(self.call_context.msg_sender),
                 );
+                if let Some(value) = self.call_context.msg_value {
+                    self.farcall_handler
+                        .set_action(CallDepth::next(), CallAction::SetCallValue(value.to_u256()));
+                }
+                if let Some(origin) = self.call_context.tx_origin {
+                    self.farcall_handler
+                        .set_action(CallDepth::next(), CallAction::SetTxOrigin(origin));
+                }
             }
         }
 
@@ -255,14 +253,27 @@ impl<S: Send, H: HistoryMode> DynTracer<S, SimpleMemory<H>> for CheatcodeTracer
             let current = state.vm_local_state.callstack.current;
 
             if current.code_address == SYSTEM_CONTEXT_ADDRESS {
-                if calldata.starts_with(&SELECTOR_SYSTEM_CONTEXT_BLOCK_NUMBER) {
-                    self.farcall_handler
+                if calldata.starts_with(&SELECTOR_SYSTEM_CONTEXT_BLOCK_NUMBER_BYTES) {
+                    let _ = self
+                        .farcall_handler
                         .set_immediate_return(self.call_context.block_number.to_be_bytes_vec());
                     return
-                } else if calldata.starts_with(&SELECTOR_SYSTEM_CONTEXT_BLOCK_TIMESTAMP) {
-                    self.farcall_handler
+                } else if calldata.starts_with(&SELECTOR_SYSTEM_CONTEXT_BLOCK_TIMESTAMP_BYTES) {
+                    let _ = self
+                        .farcall_handler
                         .set_immediate_return(self.call_context.block_timestamp.to_be_bytes_vec());
                     return
+                } else if calldata.starts_with(&SELECTOR_SYSTEM_CONTEXT_ORIGIN_BYTES) {
+                    // the caller's frame is the one below SystemContext
+                    let caller_depth =
+                        CallDepth::new(state.vm_local_state.callstack.depth() as u16).decrement();
+                    if let Some(origin) =
+                        self.farcall_handler.scoped_tx_origin(&caller_depth, current.msg_sender)
+                    {
+                        let _ =
+                            self.farcall_handler.set_immediate_return(origin.into_word().to_vec());
+                        return
+                    }
                 }
             }
         }
@@ -273,9 +284,10 @@ impl<S: Send, H: HistoryMode> DynTracer<S, SimpleMemory<H>> for CheatcodeTracer
             let current = state.vm_local_state.callstack.current;
 
             if current.code_address == SYSTEM_CONTEXT_ADDRESS &&
-                calldata.starts_with(&SELECTOR_BASE_FEE)
+                calldata.starts_with(&SELECTOR_SYSTEM_CONTEXT_BASE_FEE_BYTES)
             {
-                self.farcall_handler
+                let _ = self
+                    .farcall_handler
                     .set_immediate_return(self.call_context.block_basefee.to_be_bytes_vec());
                 return
             }
@@ -311,8 +323,26 @@ impl<S: WriteStorage + Send, H: HistoryMode> VmTracer<S, H> for CheatcodeTracer
                     tracing::info!(old=?state.local_state.callstack.current.this_address, new=?addr, "set address(this)");
                     state.local_state.callstack.current.this_address = addr.to_h160();
                 }
+                CallAction::SetTxOrigin(origin) => {
+                    tracing::info!(new=?origin, "set tx.origin");
+                    self.farcall_handler.set_scoped_tx_origin(
+                        CallDepth::new(state.local_state.callstack.depth() as u16),
+                        state.local_state.callstack.current.this_address,
+                        origin,
+                    );
+                }
+                CallAction::SetCallValue(value) => {
+                    tracing::info!(old=?state.local_state.callstack.current.context_u128_value, new=?value, "set msg.value");
+                    if let Err(err) = CallAction::apply_call_value(
+                        &mut state.local_state.callstack.current,
+                        value,
+                    ) {
+                        tracing::warn!(%err, "ignoring msg.value override");
+                    }
+                }
             }
         }
+        self.farcall_handler.maybe_override_return_data(state, bootloader_state);
         self.farcall_handler.maybe_return_early(state, bootloader_state);
 
         TracerExecutionStatus::Continue
@@ -324,8 +354,23 @@ impl<S: WriteStorage + Send, H: HistoryMode> VmTracer<S, H> for CheatcodeTracer
         _bootloader_state: &BootloaderState,
         _stop_reason: multivm::interface::tracer::VmExecutionStopReason,
     ) {
+        // Update the expectCall counts with the matching calls counted by the handler
+        for (address, expected_calls_for_target) in self.expected_calls.iter_mut() {
+            for (calldata, (expected, actual_count)) in expected_calls_for_target {
+                *actual_count += self.farcall_handler.count_matching_calls(
+                    address.to_h160(),
+                    calldata,
+                    expected.value.map(|value| value.to_u256()),
+                ) as u64;
+            }
+        }
+
         let cell = self.result.as_ref();
-        cell.set(CheatcodeTracerResult { expected_calls: self.expected_calls.clone() }).unwrap();
+        cell.set(CheatcodeTracerResult {
+            expected_calls: self.expected_calls.clone(),
+            mocked_events: self.farcall_handler.take_mocked_events(),
+        })
+        .unwrap();
     }
 }
 
@@ -577,6 +577,15 @@ interface Vm {
     function writeJson(string calldata json, string calldata path) external;
     function writeJson(string calldata json, string calldata path, string calldata valueKey) external;
     function writeLine(string calldata path, string calldata data) external;
+    function zkMockCallEmit(
+        address callee,
+        bytes calldata data,
+        bytes calldata returnData,
+        bytes32[] calldata topics,
+        bytes calldata logData
+    ) external;
+    function zkMockCallExecute(address callee, bytes calldata data, bytes calldata returnData) external;
+    function zkPrankMsgValue(uint256 msgValue) external;
     function zkRegisterContract(
         string calldata name,
         bytes32 evmBytecodeHash,
@@ -4,9 +4,105 @@ pragma solidity ^0.8.18;
 import "ds-test/test.sol";
 import "../cheats/Vm.sol";
 
+contract MockedTarget {
+    function value() public pure returns (uint256) {
+        return 1;
+    }
+
+    function pay() public payable returns (uint256) {
+        return msg.value;
+    }
+
+    function transfer(address, uint256) public returns (bool) {
+        return false;
+    }
+}
+
+contract MockedCaller {
+    event Marker(uint256 index);
+
+    function tryValue(MockedTarget target) public view returns (bool, bytes memory) {
+        try target.value() returns (uint256) {
+            return (true, "");
+        } catch (bytes memory reason) {
+            return (false, reason);
+        }
+    }
+
+    function tryPay(MockedTarget target, uint256 amount) public returns (bool, bytes memory) {
+        try target.pay{value: amount}() returns (uint256) {
+            return (true, "");
+        } catch (bytes memory reason) {
+            return (false, reason);
+        }
+    }
+
+    function returnDataSizeAfter(MockedTarget target, bytes memory first, bytes memory second)
+        public
+        returns (uint256 size)
+    {
+        (bool success,) = address(target).call(first);
+        require(success, "first call failed");
+        (success,) = address(target).call(second);
+        require(success, "second call failed");
+        assembly {
+            size := returndatasize()
+        }
+    }
+
+    function transfer(MockedTarget target, address to, uint256 amount) public returns (bool) {
+        return target.transfer(to, amount);
+    }
+
+    function transferBetweenMarkers(MockedTarget target, address to, uint256 amount) public {
+        emit Marker(0);
+        target.transfer(to, amount);
+        emit Marker(1);
+    }
+
+    function transferAndRevert(MockedTarget target, address to, uint256 amount) public {
+        target.transfer(to, amount);
+        revert("transfer reverted");
+    }
+
+    function tryTransferAndRevert(MockedTarget target, address to, uint256 amount) public returns (bool) {
+        try this.transferAndRevert(target, to, amount) {
+            return true;
+        } catch {
+            return false;
+        }
+    }
+}
+
+contract Counter {
+    uint256 public count;
+
+    function increment() public returns (uint256) {
+        return ++count;
+    }
+}
+
+contract CounterCaller {
+    function increment(Counter counter) public returns (uint256) {
+        return counter.increment();
+    }
+}
+
+contract OriginReader {
+    function senderAndOrigin() public view returns (address, address) {
+        return (msg.sender, tx.origin);
+    }
+
+    function nestedOrigin() public view returns (address origin) {
+        (, origin) = this.senderAndOrigin();
+    }
+}
+
 contract ZkCheatcodesTest is DSTest {
     Vm constant vm = Vm(HEVM_ADDRESS);
 
+    event Transfer(address indexed from, address indexed to, uint256 amount);
+
     uint256 constant ERA_FORK_BLOCK = 19579636;
     uint256 constant ERA_FORK_BLOCK_TS = 1700601590;
 
@@ -76,4 +172,169 @@ contract ZkCheatcodesTest is DSTest {
         uint8 number = abi.decode(output, (uint8));
         require(number == 10, "era etched code incorrect");
     }
+
+    function testZkCheatcodesMockCallRevert() public {
+        vm.zkVm(true);
+
+        MockedTarget target = new MockedTarget();
+        MockedCaller caller = new MockedCaller();
+        bytes memory revertData = abi.encodeWithSignature("CustomError(uint256)", 42);
+        vm.mockCallRevert(address(target), abi.encodeWithSelector(MockedTarget.value.selector), revertData);
+
+        (bool success, bytes memory reason) = caller.tryValue(target);
+        require(!success, "mocked call did not revert");
+        require(keccak256(reason) == keccak256(revertData), "mocked revert data mismatch");
+    }
+
+    function testZkCheatcodesMockCallRevertWithValue() public {
+        vm.zkVm(true);
+
+        MockedTarget target = new MockedTarget();
+        MockedCaller caller = new MockedCaller();
+        vm.deal(address(caller), 100);
+        bytes memory revertData = abi.encodeWithSignature("CustomError(uint256)", 42);
+        vm.mockCallRevert(address(target), 10, abi.encodeWithSelector(MockedTarget.pay.selector), revertData);
+
+        (bool success, bytes memory reason) = caller.tryPay(target, 10);
+        require(!success, "mocked value call did not revert");
+        require(keccak256(reason) == keccak256(revertData), "mocked revert data mismatch");
+        require(address(caller).balance == 100, "reverted value transfer persisted");
+    }
+
+    function testZkCheatcodesMockCallEmptyReturn() public {
+        vm.zkVm(true);
+
+        MockedTarget target = new MockedTarget();
+        MockedCaller caller = new MockedCaller();
+        vm.mockCall(address(target), abi.encodeWithSelector(MockedTarget.pay.selector), "");
+
+        // the mocked empty returndata replaces the returndata of the previous call
+        uint256 size = caller.returnDataSizeAfter(
+            target,
+            abi.encodeWithSelector(MockedTarget.value.selector),
+            abi.encodeWithSelector(MockedTarget.pay.selector)
+        );
+        require(size == 0, "stale returndata after mocked empty return");
+    }
+
+    function testZkCheatcodesPrankOrigin() public {
+        vm.zkVm(true);
+
+        OriginReader reader = new OriginReader();
+        address sender = address(0x1234);
+        address origin = address(0x5678);
+        vm.deal(origin, 1 ether);
+
+        vm.prank(sender, origin);
+        (address seenSender, address seenOrigin) = reader.senderAndOrigin();
+        require(seenSender == sender, "pranked msg.sender mismatch");
+        require(seenOrigin == origin, "pranked tx.origin mismatch");
+
+        // the pranked frame's nested calls see the restored tx.origin
+        address restoredOrigin = tx.origin;
+        vm.prank(sender, origin);
+        require(reader.nestedOrigin() == restoredOrigin, "pranked tx.origin leaked into nested call");
+
+        // the prank ends with the call, and does not leak into later calls
+        (, seenOrigin) = reader.senderAndOrigin();
+        require(seenOrigin != origin, "pranked tx.origin leaked");
+    }
+
+    function testZkCheatcodesPrankMsgValue() public {
+        vm.zkVm(true);
+
+        MockedTarget target = new MockedTarget();
+
+        vm.zkPrankMsgValue(1 ether);
+        require(target.pay() == 1 ether, "pranked msg.value mismatch");
+        require(address(target).balance == 0, "pranked msg.value was transferred");
+
+        // the prank ends with the call, and does not leak into later calls
+        require(target.pay() == 0, "pranked msg.value leaked");
+
+        // a deployment consumes the prank as well
+        vm.zkPrankMsgValue(1 ether);
+        target = new MockedTarget();
+        require(target.pay() == 0, "pranked msg.value leaked past a deployment");
+    }
+
+    function testZkCheatcodesMockCallEmit() public {
+        vm.zkVm(true);
+
+        MockedTarget target = new MockedTarget();
+        MockedCaller caller = new MockedCaller();
+        address to = address(0x1234);
+        bytes32[] memory topics = new bytes32[](3);
+        topics[0] = Transfer.selector;
+        topics[1] = bytes32(uint256(uint160(address(caller))));
+        topics[2] = bytes32(uint256(uint160(to)));
+        bytes memory logData = abi.encode(uint256(10));
+        vm.zkMockCallEmit(
+            address(target), abi.encodeWithSelector(MockedTarget.transfer.selector), abi.encode(true), topics, logData
+        );
+
+        vm.recordLogs();
+        require(caller.transfer(target, to, 10), "mocked transfer failed");
+        require(countLogs(vm.getRecordedLogs(), address(target), topics, logData) == 1, "mocked log not recorded");
+
+        // logs of mocked calls within reverted frames are discarded
+        vm.recordLogs();
+        require(!caller.tryTransferAndRevert(target, to, 10), "transfer did not revert");
+        require(countLogs(vm.getRecordedLogs(), address(target), topics, logData) == 0, "reverted mocked log recorded");
+
+        // logs of mocked calls are ordered among the logs of the calling frame
+        vm.recordLogs();
+        caller.transferBetweenMarkers(target, to, 10);
+        Vm.Log[] memory logs = vm.getRecordedLogs();
+        uint256 found;
+        for (uint256 i = 0; i < logs.length; i++) {
+            if (logs[i].emitter == address(caller)) {
+                require(logs[i].topics[0] == MockedCaller.Marker.selector, "unexpected caller log");
+                require(abi.decode(logs[i].data, (uint256)) * 2 == found, "caller log out of order");
+                found++;
+            } else if (logs[i].emitter == address(target)) {
+                require(found == 1, "mocked log out of order");
+                found++;
+            }
+        }
+        require(found == 3, "logs missing");
+    }
+
+    function testZkCheatcodesMockCallExecute() public {
+        vm.zkVm(true);
+
+        Counter counter = new Counter();
+        CounterCaller caller = new CounterCaller();
+        vm.zkMockCallExecute(address(counter), abi.encodeWithSelector(Counter.increment.selector), abi.encode(42));
+
+        // the caller reads the mocked returndata, while the counter's state change persists
+        require(caller.increment(counter) == 42, "mocked returndata mismatch");
+        require(counter.count() == 1, "mocked call did not execute");
+    }
+
+    function testFailZkCheatcodesMockCallExecuteOutsideZkVm() public {
+        vm.zkVm(true);
+
+        Counter counter = new Counter();
+        vm.zkMockCallExecute(address(counter), abi.encodeWithSelector(Counter.increment.selector), abi.encode(42));
+
+        // the EVM cannot execute the callee and override its returndata, so the call reverts
+        vm.zkVm(false);
+        counter.increment();
+    }
+
+    function countLogs(Vm.Log[] memory logs, address emitter, bytes32[] memory topics, bytes memory data)
+        internal
+        pure
+        returns (uint256 count)
+    {
+        for (uint256 i = 0; i < logs.length; i++) {
+            if (
+                logs[i].emitter == emitter && keccak256(abi.encode(logs[i].topics)) == keccak256(abi.encode(topics))
+                    && keccak256(logs[i].data) == keccak256(data)
+            ) {
+                count++;
+            }
+        }
+    }
 }